@@ -0,0 +1,476 @@
+// JSON API for other clinic tools, served by `medfiles serve` under /api/v1.
+// The data comes from the same model as the terminal and the dashboard: the
+// .med files grouped by patient, the prescriptions replayed through
+// prescription_finalizer, the problem list and the rendered pages. Every
+// request except the OpenAPI description needs `Authorization: Bearer
+// <token>`; tokens are created with `medfiles api token` and only a salted
+// SHA-256 hash of each one is kept in api_tokens.json.
+
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use medfiles::site::{self, Encounter};
+use medfiles::problems;
+
+use crate::server::Response;
+use crate::{audit, crypto, storage};
+
+pub const TOKENS_PATH: &str = "api_tokens.json";
+pub const PREFIX: &str = "/api/v1";
+
+#[derive(Serialize, Deserialize)]
+struct ApiToken {
+    name: String,
+    salt: String,
+    token_hash: String,
+    created_at: String,
+}
+
+type Files = Vec<(PathBuf, SystemTime)>;
+
+fn hash_token(salt: &str, token: &str) -> String {
+    crypto::salted_sha256(salt, token.trim())
+}
+
+fn load_tokens() -> Result<Vec<ApiToken>, String> {
+    if !std::path::Path::new(TOKENS_PATH).exists() {
+        return Ok(vec![]);
+    }
+    let content = storage::read_to_string(TOKENS_PATH).map_err(|e| format!("Falha ao ler {}: {}", TOKENS_PATH, e))?;
+    serde_json::from_str(&content).map_err(|e| format!("Falha ao parsear {}: {}", TOKENS_PATH, e))
+}
+
+fn save_tokens(tokens: &[ApiToken]) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(tokens).map_err(|e| e.to_string())?;
+    storage::write(TOKENS_PATH, json).map_err(|e| format!("Falha ao gravar {}: {}", TOKENS_PATH, e))
+}
+
+pub fn create_token(name: &str) -> Result<String, String> {
+    let mut tokens = load_tokens()?;
+    if tokens.iter().any(|t| t.name == name) {
+        return Err(format!("Já existe um token chamado '{}'; revogue-o antes de criar outro.", name));
+    }
+    let token = crypto::random_hex(32);
+    let salt = crypto::random_hex(16);
+    tokens.push(ApiToken {
+        name: name.to_string(),
+        token_hash: hash_token(&salt, &token),
+        salt,
+        created_at: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+    });
+    save_tokens(&tokens)?;
+    Ok(token)
+}
+
+pub fn revoke_token(name: &str) -> Result<bool, String> {
+    let mut tokens = load_tokens()?;
+    let before = tokens.len();
+    tokens.retain(|t| t.name != name);
+    if tokens.len() == before {
+        return Ok(false);
+    }
+    save_tokens(&tokens)?;
+    Ok(true)
+}
+
+fn error(status: &'static str, message: &str) -> Response {
+    Response::json(status, &json!({ "error": message }))
+}
+
+fn authorize(authorization: Option<&str>) -> Result<String, Response> {
+    // Returns the name of the matching token
+    let tokens = load_tokens().map_err(|e| error("500 Internal Server Error", &e))?;
+    if tokens.is_empty() {
+        return Err(error("401 Unauthorized", "nenhum token configurado; crie um com 'medfiles api token --name <ferramenta>'"));
+    }
+    let Some(token) = authorization.and_then(|h| h.trim().strip_prefix("Bearer ")) else {
+        return Err(error("401 Unauthorized", "cabeçalho Authorization: Bearer <token> ausente"));
+    };
+    tokens
+        .iter()
+        .find(|t| crypto::same_digest(&t.token_hash, &hash_token(&t.salt, token)))
+        .map(|t| t.name.clone())
+        .ok_or_else(|| error("401 Unauthorized", "token inválido"))
+}
+
+fn date(time: SystemTime) -> String {
+    chrono::DateTime::<chrono::Local>::from(time).format("%Y-%m-%d").to_string()
+}
+
+fn patient_json(name: &str, files: &Files) -> Value {
+    json!({
+        "id": site::page_slug(name),
+        "name": name,
+        "encounters": files.len(),
+        "first_encounter": files.first().map(|(_, modified)| date(*modified)),
+        "last_encounter": files.last().map(|(_, modified)| date(*modified)),
+    })
+}
+
+fn encounter_json(encounter: &Encounter) -> Value {
+    let sections: Vec<Value> = encounter
+        .doc
+        .sections
+        .iter()
+        .map(|section| {
+            let directives: Vec<Value> = section
+                .directives()
+                .iter()
+                .map(|d| json!({ "keyword": d.keyword, "content": d.content }))
+                .collect();
+            json!({ "name": section.name, "lines": section.lines, "directives": directives })
+        })
+        .collect();
+    json!({
+        "file": encounter.name,
+        "date": date(encounter.modified),
+        "document": site::attendance_page_name(encounter),
+        "sections": sections,
+    })
+}
+
+fn problem_json(problem: &problems::Problem) -> Value {
+    json!({
+        "name": problem.name,
+        "code": problem.code,
+        "status": problem.status.label(),
+        "active": problem.status.is_active(),
+        "first_seen": problem.first_seen,
+        "last_seen": problem.last_seen,
+        "confirmed_on": problem.confirmed_on,
+        "closed_on": problem.closed_on,
+        "medications": problem.medications,
+    })
+}
+
+fn document_names(encounters: &[Encounter]) -> Vec<String> {
+    let mut names: Vec<String> = site::PAGES.iter().map(|name| name.to_string()).collect();
+    for encounter in encounters {
+        names.push(site::attendance_page_name(encounter));
+        names.push(site::prescription_page_name(encounter));
+    }
+    names
+}
+
+fn render_document(name: &str, encounters: &[Encounter], medications: &medfiles::Medications) -> Option<String> {
+    let (doctor, signature) = crate::load_doctor();
+    site::render_page(name, encounters, medications, doctor.as_ref(), signature.as_deref())
+}
+
+fn patient_route(name: &str, files: &Files, rest: &[&str]) -> Response {
+    let encounters = crate::load_encounters(files);
+    let (medications, changes) = crate::medication_state_replay(files);
+    let body = match rest {
+        [] => patient_json(name, files),
+        ["encounters"] => Value::Array(encounters.iter().map(encounter_json).collect()),
+        ["medications"] => {
+            let mut active: Vec<Value> = medications
+                .iter()
+                .map(|(medication, state)| {
+                    let field = |key: &str| state.get(key).cloned().unwrap_or_default();
+                    json!({
+                        "medication": medication,
+                        "dosage": field("dosage"),
+                        "dosage_observations": field("dosage_observations"),
+                        "posology": field("posologia"),
+                        "posology_observations": field("posology_observations"),
+                    })
+                })
+                .collect();
+            active.sort_by(|a, b| a["medication"].as_str().cmp(&b["medication"].as_str()));
+            Value::Array(active)
+        }
+        ["history"] => Value::Array(
+            changes
+                .iter()
+                .map(|(medication, timestamp, field, old, new, command)| {
+                    json!({
+                        "medication": medication,
+                        "timestamp": timestamp,
+                        "field": field,
+                        "old_value": old,
+                        "new_value": new,
+                        "command": command,
+                    })
+                })
+                .collect(),
+        ),
+        ["problems"] => Value::Array(problems::build_problem_list(&encounters).iter().map(problem_json).collect()),
+        ["documents"] => Value::Array(
+            document_names(&encounters)
+                .into_iter()
+                .map(|document| json!({ "name": document, "url": format!("{}/patients/{}/documents/{}", PREFIX, site::page_slug(name), document) }))
+                .collect(),
+        ),
+        ["documents", document] => match render_document(document, &encounters, &medications) {
+            Some(html) => json!({ "name": document, "content_type": "text/html", "content": html }),
+            None => return error("404 Not Found", &format!("documento '{}' não encontrado", document)),
+        },
+        _ => return error("404 Not Found", "rota não encontrada"),
+    };
+    Response::json("200 OK", &body)
+}
+
+pub fn route(path: &str, authorization: Option<&str>) -> Response {
+    let path = path.split('?').next().unwrap_or(path);
+    let Some(rest) = path.strip_prefix(PREFIX) else {
+        return error("404 Not Found", "rota não encontrada");
+    };
+    if rest == "/openapi.json" {
+        return Response::json("200 OK", &openapi());
+    }
+    let client = match authorize(authorization) {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
+    let segments: Vec<&str> = rest.split('/').filter(|s| !s.is_empty()).collect();
+    let patients = site::group_by_patient(crate::medfile_finder());
+    match segments.as_slice() {
+        ["patients"] => {
+            audit::record("visualizar", &crate::workspace_name(), &format!("API {} ({})", path, client));
+            Response::json("200 OK", &Value::Array(patients.iter().map(|(name, files)| patient_json(name, files)).collect()))
+        }
+        ["patients", id, rest @ ..] => match patients.iter().find(|(name, _)| site::page_slug(name) == *id) {
+            Some((name, files)) => {
+                audit::record("visualizar", name, &format!("API {} ({})", path, client));
+                patient_route(name, files, rest)
+            }
+            None => error("404 Not Found", &format!("paciente '{}' não encontrado", id)),
+        },
+        _ => error("404 Not Found", "rota não encontrada"),
+    }
+}
+
+fn get(summary: &str, schema: Value) -> Value {
+    json!({
+        "get": {
+            "summary": summary,
+            "parameters": [],
+            "responses": {
+                "200": { "description": "OK", "content": { "application/json": { "schema": schema } } },
+                "401": { "$ref": "#/components/responses/Unauthorized" },
+                "404": { "$ref": "#/components/responses/NotFound" },
+            },
+        }
+    })
+}
+
+fn with_patient_id(mut operation: Value) -> Value {
+    operation["get"]["parameters"] = json!([{ "$ref": "#/components/parameters/PatientId" }]);
+    operation
+}
+
+fn array_of(schema: &str) -> Value {
+    json!({ "type": "array", "items": { "$ref": format!("#/components/schemas/{}", schema) } })
+}
+
+fn object(properties: &[(&str, &str)]) -> Value {
+    let properties: serde_json::Map<String, Value> = properties
+        .iter()
+        .map(|(name, kind)| {
+            let schema = match *kind {
+                "string?" => json!({ "type": "string", "nullable": true }),
+                "string[]" => json!({ "type": "array", "items": { "type": "string" } }),
+                kind => json!({ "type": kind }),
+            };
+            (name.to_string(), schema)
+        })
+        .collect();
+    json!({ "type": "object", "properties": properties })
+}
+
+pub fn openapi() -> Value {
+    let mut document_operation = with_patient_id(get("Página do painel renderizada em HTML", json!({ "$ref": "#/components/schemas/Document" })));
+    if let Some(parameters) = document_operation["get"]["parameters"].as_array_mut() {
+        parameters.push(json!({ "name": "name", "in": "path", "required": true, "schema": { "type": "string" } }));
+    }
+    let mut encounter = object(&[("file", "string"), ("date", "string"), ("document", "string")]);
+    encounter["properties"]["sections"] = json!({
+        "type": "array",
+        "items": {
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "lines": { "type": "array", "items": { "type": "string" } },
+                "directives": { "type": "array", "items": object(&[("keyword", "string"), ("content", "string")]) },
+            },
+        },
+    });
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "medfiles API",
+            "version": "1",
+            "description": "Dados dos arquivos .med do workspace: pacientes, atendimentos, medicações em uso, histórico de prescrições, lista de problemas e documentos renderizados.",
+        },
+        "servers": [{ "url": PREFIX }],
+        "security": [{ "bearer": [] }],
+        "paths": {
+            "/patients": get("Pacientes do workspace", array_of("Patient")),
+            "/patients/{id}": with_patient_id(get("Um paciente", json!({ "$ref": "#/components/schemas/Patient" }))),
+            "/patients/{id}/encounters": with_patient_id(get("Atendimentos em ordem cronológica", array_of("Encounter"))),
+            "/patients/{id}/medications": with_patient_id(get("Medicações em uso", array_of("Medication"))),
+            "/patients/{id}/history": with_patient_id(get("Mudanças de prescrição", array_of("HistoryEvent"))),
+            "/patients/{id}/problems": with_patient_id(get("Lista de problemas", array_of("Problem"))),
+            "/patients/{id}/documents": with_patient_id(get("Documentos disponíveis", array_of("DocumentLink"))),
+            "/patients/{id}/documents/{name}": document_operation,
+        },
+        "components": {
+            "securitySchemes": { "bearer": { "type": "http", "scheme": "bearer" } },
+            "parameters": {
+                "PatientId": { "name": "id", "in": "path", "required": true, "schema": { "type": "string" } },
+            },
+            "responses": {
+                "Unauthorized": { "description": "Token ausente ou inválido", "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Error" } } } },
+                "NotFound": { "description": "Paciente, documento ou rota inexistente", "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Error" } } } },
+            },
+            "schemas": {
+                "Error": object(&[("error", "string")]),
+                "Patient": object(&[("id", "string"), ("name", "string"), ("encounters", "integer"), ("first_encounter", "string?"), ("last_encounter", "string?")]),
+                "Encounter": encounter,
+                "Medication": object(&[("medication", "string"), ("dosage", "string"), ("dosage_observations", "string"), ("posology", "string"), ("posology_observations", "string")]),
+                "HistoryEvent": object(&[("medication", "string"), ("timestamp", "string"), ("field", "string"), ("old_value", "string"), ("new_value", "string"), ("command", "string")]),
+                "Problem": object(&[("name", "string"), ("code", "string?"), ("status", "string"), ("active", "boolean"), ("first_seen", "string"), ("last_seen", "string"), ("confirmed_on", "string?"), ("closed_on", "string?"), ("medications", "string[]")]),
+                "DocumentLink": object(&[("name", "string"), ("url", "string")]),
+                "Document": object(&[("name", "string"), ("content_type", "string"), ("content", "string")]),
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, Workspace};
+
+    fn workspace() -> (Workspace, String) {
+        let workspace = Workspace::new();
+        workspace.file(
+            "MARIA SOUZA 2025-01-10.med",
+            "[ANAMNESE]\nCefaleia.\n[HIPOTESE DIAGNOSTICA]\nENXAQUECA {G43};\n[CONDUTA]\n!PRESCREVO TOPIRAMATO 25MG [1 COMPRIMIDO] NOITE;\n",
+        );
+        workspace.file("MARIA SOUZA 2025-02-20.med", "[ANAMNESE]\nMelhora.\n[CONDUTA]\n!AUMENTO TOPIRAMATO 50MG [1 COMPRIMIDO] NOITE;\n");
+        let token = create_token("testes").unwrap();
+        (workspace, token)
+    }
+
+    fn keys(value: &Value) -> Vec<&str> {
+        let mut keys: Vec<&str> = value.as_object().expect("objeto JSON").keys().map(String::as_str).collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn requests_without_a_valid_token_are_refused() {
+        let (_workspace, token) = workspace();
+        let server = testing::start_server();
+
+        let missing = testing::get(server, "/api/v1/patients", None);
+        assert_eq!(missing.status, 401);
+        assert!(missing.json()["error"].as_str().unwrap().contains("ausente"));
+
+        let wrong = testing::get(server, "/api/v1/patients", Some("0000"));
+        assert_eq!(wrong.status, 401);
+        assert_eq!(wrong.json()["error"], "token inválido");
+
+        let revoked = token.clone();
+        assert!(revoke_token("testes").unwrap());
+        assert_eq!(testing::get(server, "/api/v1/patients", Some(&revoked)).status, 401);
+    }
+
+    #[test]
+    fn tokens_are_stored_only_as_salted_hashes() {
+        let (_workspace, token) = workspace();
+        let stored = std::fs::read_to_string(TOKENS_PATH).unwrap();
+        assert!(!stored.contains(&token));
+        assert!(create_token("testes").is_err());
+    }
+
+    #[test]
+    fn patient_routes_return_the_documented_shapes() {
+        let (_workspace, token) = workspace();
+        let server = testing::start_server();
+        let get = |path: &str| {
+            let response = testing::get(server, path, Some(&token));
+            assert_eq!(response.status, 200, "{}: {}", path, response.body);
+            assert!(response.headers.contains("application/json"));
+            response.json()
+        };
+
+        let patients = get("/api/v1/patients");
+        assert_eq!(patients.as_array().unwrap().len(), 1);
+        assert_eq!(keys(&patients[0]), ["encounters", "first_encounter", "id", "last_encounter", "name"]);
+        assert_eq!(patients[0]["name"], "MARIA SOUZA");
+        assert_eq!(patients[0]["encounters"], 2);
+        let id = patients[0]["id"].as_str().unwrap().to_string();
+        let base = format!("/api/v1/patients/{}", id);
+
+        assert_eq!(get(&base)["id"], id.as_str());
+
+        let encounters = get(&format!("{}/encounters", base));
+        assert_eq!(encounters.as_array().unwrap().len(), 2);
+        assert_eq!(keys(&encounters[0]), ["date", "document", "file", "sections"]);
+        let sections = encounters[0]["sections"].as_array().unwrap();
+        assert_eq!(keys(&sections[0]), ["directives", "lines", "name"]);
+        let conduct = sections.iter().find(|s| s["name"] == "CONDUTA").unwrap();
+        assert_eq!(keys(&conduct["directives"][0]), ["content", "keyword"]);
+
+        let medications = get(&format!("{}/medications", base));
+        assert_eq!(medications.as_array().unwrap().len(), 1);
+        assert_eq!(keys(&medications[0]), ["dosage", "dosage_observations", "medication", "posology", "posology_observations"]);
+        assert_eq!(medications[0]["medication"], "TOPIRAMATO");
+        assert_eq!(medications[0]["dosage"], "50MG");
+
+        let history = get(&format!("{}/history", base));
+        assert!(!history.as_array().unwrap().is_empty());
+        assert_eq!(keys(&history[0]), ["command", "field", "medication", "new_value", "old_value", "timestamp"]);
+
+        let problems = get(&format!("{}/problems", base));
+        assert_eq!(
+            keys(&problems[0]),
+            ["active", "closed_on", "code", "confirmed_on", "first_seen", "last_seen", "medications", "name", "status"]
+        );
+        assert_eq!(problems[0]["code"], "G43");
+
+        let documents = get(&format!("{}/documents", base));
+        let names: Vec<&str> = documents.as_array().unwrap().iter().map(|d| d["name"].as_str().unwrap()).collect();
+        assert!(names.contains(&"index.html"));
+        assert_eq!(keys(&documents[0]), ["name", "url"]);
+        let attendance = encounters[0]["document"].as_str().unwrap();
+        assert!(names.contains(&attendance));
+
+        let document = get(&format!("{}/documents/{}", base, attendance));
+        assert_eq!(keys(&document), ["content", "content_type", "name"]);
+        assert_eq!(document["content_type"], "text/html");
+        assert!(document["content"].as_str().unwrap().contains("<html"));
+    }
+
+    #[test]
+    fn unknown_patients_and_routes_are_not_found() {
+        let (_workspace, token) = workspace();
+        let server = testing::start_server();
+        assert_eq!(testing::get(server, "/api/v1/patients/NINGUEM", Some(&token)).status, 404);
+        assert_eq!(testing::get(server, "/api/v1/patients/MARIA_SOUZA/receitas", Some(&token)).status, 404);
+        assert_eq!(testing::get(server, "/api/v1/patients/MARIA_SOUZA/documents/nada.html", Some(&token)).status, 404);
+    }
+
+    #[test]
+    fn openapi_description_is_public_and_lists_every_route() {
+        let _workspace = Workspace::new();
+        let server = testing::start_server();
+        let response = testing::get(server, "/api/v1/openapi.json", None);
+        assert_eq!(response.status, 200);
+        let description = response.json();
+        assert_eq!(description["openapi"], "3.0.3");
+        let paths = keys(&description["paths"]);
+        for route in ["/patients", "/patients/{id}", "/patients/{id}/encounters", "/patients/{id}/medications", "/patients/{id}/history", "/patients/{id}/problems", "/patients/{id}/documents", "/patients/{id}/documents/{name}"] {
+            assert!(paths.contains(&route), "{} ausente", route);
+        }
+        for schema in ["Patient", "Encounter", "Medication", "HistoryEvent", "Problem", "DocumentLink", "Document", "Error"] {
+            assert!(description["components"]["schemas"][schema].is_object(), "{} ausente", schema);
+        }
+    }
+}
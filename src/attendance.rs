@@ -0,0 +1,375 @@
+// Rendering of a complete encounter (.med file) into the styled HTML used by
+// the prescriptions. Each section gets its own block; known directives are
+// grouped into structured lists and tables instead of being shown raw.
+
+use crate::cid10;
+use crate::html::{generate_html_footer, generate_html_header_with_nav, html_escape};
+use crate::lab::{self, Demographics};
+use crate::medfile::{self, MedDocument, MedSection};
+use crate::pdf::PdfBlock;
+use crate::prescription::{self, is_prescription};
+use crate::profile::UserInfo;
+use crate::site::Encounter;
+use crate::vitals::{self, Vitals};
+
+pub fn generate_attendance_html(
+    title: &str,
+    date_str: &str,
+    doc: &MedDocument,
+    nav_html: &str,
+    history: &[Encounter],
+    doctor: Option<&UserInfo>,
+) -> String {
+    let mut html = generate_html_header_with_nav(&format!("Atendimento: {}", html_escape(title)), nav_html, doctor);
+    html.push_str(&format!(
+        "        <p class=\"attendance-date\"><strong>Data do atendimento:</strong> {}</p>\n",
+        html_escape(date_str)
+    ));
+    let context = RenderContext {
+        codes: medfile::diagnosis_codes(doc),
+        demographics: if history.is_empty() { Demographics::from_document(doc) } else { Demographics::from_encounters(history) },
+        date_str,
+    };
+    for section in &doc.sections {
+        html.push_str(&generate_attendance_section(section, &context));
+    }
+    html.push_str(&generate_trends_section(history, &context.demographics));
+    html.push_str(&generate_html_footer());
+    html
+}
+
+// Encounter-wide data needed while rendering individual sections
+struct RenderContext<'a> {
+    codes: Vec<String>,
+    demographics: Demographics,
+    date_str: &'a str,
+}
+
+fn generate_trends_section(history: &[Encounter], demographics: &Demographics) -> String {
+    // History holds the encounters up to and including this one, oldest first
+    let blocks = [
+        ("Evolução dos Sinais Vitais", vitals::generate_vitals_charts(&vitals::vitals_history(history))),
+        ("Evolução Laboratorial", lab::generate_lab_trends(&lab::lab_history(history), demographics)),
+    ];
+    let mut html = String::new();
+    for (title, body) in blocks.iter().filter(|(_, body)| !body.is_empty()) {
+        html.push_str(&format!(
+            r#"
+    <div class="prescription attendance-section">
+        <h2>{}</h2>
+        {}
+    </div>
+"#,
+            title, body
+        ));
+    }
+    html
+}
+
+fn section_title(name: &str) -> String {
+    // Plain text: the section name comes from the .med file and is escaped by the HTML callers, not by the PDF
+    match name {
+        "" => "Notas".to_string(),
+        "ANAMNESE" => "Anamnese".to_string(),
+        "EXAME FISICO" => "Exame Físico".to_string(),
+        "HIPOTESE DIAGNOSTICA" => "Hipótese Diagnóstica".to_string(),
+        "CONDUTA" => "Conduta".to_string(),
+        "LABORATORIO" => "Laboratório".to_string(),
+        "NOTAS DE IMPORTACAO" => "Notas de Importação".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn generate_attendance_section(section: &MedSection, context: &RenderContext) -> String {
+    let body = match section.name.as_str() {
+        "HIPOTESE DIAGNOSTICA" => generate_diagnosis_block(&section.lines),
+        "CONDUTA" => generate_conduct_block(&section.lines, &context.codes),
+        "LABORATORIO" => generate_lab_block(section, context),
+        _ => generate_generic_block(section, context),
+    };
+    format!(
+        r#"
+    <div class="prescription attendance-section">
+        <h2>{}</h2>
+        {}
+    </div>
+"#,
+        html_escape(&section_title(&section.name)),
+        body
+    )
+}
+
+fn generate_lab_block(section: &MedSection, context: &RenderContext) -> String {
+    let (results, text) = lab::section_results(&section.lines, context.date_str);
+    let mut html = String::new();
+    for line in text {
+        html.push_str(&format!("<p>{}</p>", html_escape(&line)));
+    }
+    html.push_str(&lab::generate_lab_table(&results, &context.demographics));
+    html
+}
+
+fn generate_generic_block(section: &MedSection, context: &RenderContext) -> String {
+    let mut html = String::new();
+    for line in section.free_text() {
+        html.push_str(&format!("<p>{}</p>", html_escape(&line)));
+    }
+    if section.name == "EXAME FISICO" {
+        html.push_str(&vitals::generate_vitals_table(&Vitals::from_section(section)));
+    }
+
+    let directives = section.directives();
+    let mut history = vec![];
+    let mut medications = vec![];
+    let mut family_history = vec![];
+    let mut allergies = vec![];
+    let mut exams = vec![];
+    let mut labs = vec![];
+    let mut others = vec![];
+    for directive in directives {
+        match directive.keyword.as_str() {
+            "HPP" => history.push(directive.content),
+            "MED" => medications.push(directive.content),
+            "HF" => family_history.push(directive.content),
+            "ALERGIA" => allergies.push(directive.content),
+            "EX" => exams.push(medfile::parse_exam(&directive.content)),
+            "SV" if section.name == "EXAME FISICO" => {}
+            "LAB" => match lab::parse_result(&directive.content, context.date_str) {
+                Some(result) => labs.push(result),
+                None => others.push(format!("LAB: {}", directive.content)),
+            },
+            _ => others.push(format!("{}: {}", directive.keyword, directive.content)),
+        }
+    }
+
+    html.push_str(&generate_directive_list("História Patológica Pregressa", &history));
+    html.push_str(&generate_directive_list("Medicações em Uso", &medications));
+    html.push_str(&generate_directive_list("História Familiar", &family_history));
+    html.push_str(&generate_directive_list("Alergias", &allergies));
+    if !exams.is_empty() {
+        html.push_str("<h3>Exames</h3><table class=\"exams\"><thead><tr><th>Exame</th><th>Data</th><th>Resultado</th></tr></thead><tbody>");
+        for exam in exams {
+            html.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                html_escape(&exam.name),
+                html_escape(&exam.date),
+                html_escape(&exam.result)
+            ));
+        }
+        html.push_str("</tbody></table>");
+    }
+    if !labs.is_empty() {
+        html.push_str("<h3>Laboratório</h3>");
+        html.push_str(&lab::generate_lab_table(&labs, &context.demographics));
+    }
+    html.push_str(&generate_directive_list("Outros Registros", &others));
+    html
+}
+
+fn generate_directive_list(title: &str, items: &[String]) -> String {
+    if items.is_empty() {
+        return String::new();
+    }
+    let mut html = format!("<h3>{}</h3><ul class=\"directive-list\">", title);
+    for item in items {
+        html.push_str(&format!("<li>{}</li>", html_escape(item)));
+    }
+    html.push_str("</ul>");
+    html
+}
+
+fn generate_diagnosis_block(lines: &[String]) -> String {
+    let mut html = String::from("<ol class=\"diagnosis-list\">");
+    for diagnosis in medfile::diagnosis_items(lines) {
+        let code = match diagnosis.cid_code() {
+            Some(code) => format!(
+                " <span class=\"cid\" title=\"{}\">CID-10 {}</span>",
+                html_escape(&cid10::description(&code).unwrap_or_default()),
+                html_escape(&code)
+            ),
+            None => String::new(),
+        };
+        if diagnosis.suspected {
+            html.push_str(&format!(
+                "<li class=\"suspected\">{}{} <span class=\"badge\">suspeita</span></li>",
+                html_escape(&diagnosis.name),
+                code
+            ));
+        } else {
+            html.push_str(&format!("<li>{}{}</li>", html_escape(&diagnosis.name), code));
+        }
+    }
+    html.push_str("</ol>");
+    let closures: Vec<String> = lines
+        .iter()
+        .filter_map(|line| medfile::parse_directive(line))
+        .map(|directive| format!("<li><span class=\"badge\">{}</span>{}</li>", html_escape(&conduct_label(&directive.keyword)), html_escape(&directive.content)))
+        .collect();
+    if !closures.is_empty() {
+        html.push_str(&format!("<ul class=\"directive-list\">{}</ul>", closures.concat()));
+    }
+    html
+}
+
+fn conduct_label(keyword: &str) -> String {
+    // Plain text, like section_title: unknown keywords come straight from the file
+    match keyword {
+        "PRESCREVO" => "Prescrição".to_string(),
+        "AUMENTO" | "INCREMENTO" => "Aumento".to_string(),
+        "REDUZO" | "DECREMENTO" => "Redução".to_string(),
+        "SUSPENDO" => "Suspensão".to_string(),
+        "DESMAME" => "Desmame".to_string(),
+        "ENCAMINHO" => "Encaminhamento".to_string(),
+        "SOLICITO" => "Solicitação".to_string(),
+        "ORIENTO" => "Orientação".to_string(),
+        "RETORNO" => "Retorno".to_string(),
+        "ATESTADO" => "Atestado".to_string(),
+        "DESCARTO" => "Descartado".to_string(),
+        "RESOLVIDO" => "Resolvido".to_string(),
+        "INDICACAO" => "Indicação".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn cid_note(keyword: &str, codes: &[String]) -> Option<String> {
+    // Exam requests and certificates carry the coded hypotheses of the encounter
+    if codes.is_empty() || !matches!(keyword, "SOLICITO" | "ATESTADO") {
+        return None;
+    }
+    Some(format!("CID-10: {}", codes.join(", ")))
+}
+
+fn generate_conduct_block(lines: &[String], codes: &[String]) -> String {
+    let mut html = String::from("<ul class=\"conduct-list\">");
+    for line in lines {
+        if is_prescription(line) {
+            let item = prescription::medication_json_populator(line);
+            let (line1, line2) = prescription::recipe_lines(&item);
+            let keyword = medfile::parse_directive(line).map(|d| d.keyword).unwrap_or_default();
+            let objective = if item.objective.is_empty() {
+                String::new()
+            } else {
+                format!("<br><em>Objetivo: {}</em>", html_escape(&item.objective))
+            };
+            html.push_str(&format!(
+                "<li class=\"conduct-prescription\"><span class=\"badge\">{}</span><strong>{}</strong>{}{}</li>",
+                html_escape(&conduct_label(&keyword)),
+                html_escape(&line1),
+                html_escape(&line2),
+                objective
+            ));
+        } else if let Some(directive) = medfile::parse_directive(line) {
+            let note = cid_note(&directive.keyword, codes)
+                .map(|note| format!("<br><em>{}</em>", html_escape(&note)))
+                .unwrap_or_default();
+            html.push_str(&format!(
+                "<li><span class=\"badge\">{}</span>{}{}</li>",
+                html_escape(&conduct_label(&directive.keyword)),
+                html_escape(&directive.content),
+                note
+            ));
+        } else {
+            html.push_str(&format!("<li>{}</li>", html_escape(line)));
+        }
+    }
+    html.push_str("</ul>");
+    html
+}
+
+pub fn attendance_pdf_blocks(doc: &MedDocument) -> Vec<PdfBlock> {
+    let mut blocks = vec![];
+    let codes = medfile::diagnosis_codes(doc);
+    let demographics = Demographics::from_document(doc);
+    for section in &doc.sections {
+        blocks.push(PdfBlock::Heading(section_title(&section.name)));
+        match section.name.as_str() {
+            "HIPOTESE DIAGNOSTICA" => {
+                for (i, diagnosis) in medfile::diagnosis_items(&section.lines).into_iter().enumerate() {
+                    let code = diagnosis.cid_code().map(|code| format!(" · CID-10 {}", code)).unwrap_or_default();
+                    let suffix = if diagnosis.suspected { " (suspeita)" } else { "" };
+                    blocks.push(PdfBlock::Paragraph(format!("{}. {}{}{}", i + 1, diagnosis.name, code, suffix)));
+                }
+                for directive in section.directives() {
+                    blocks.push(PdfBlock::Paragraph(format!("{}: {}", conduct_label(&directive.keyword), directive.content)));
+                }
+            }
+            "CONDUTA" => {
+                for line in &section.lines {
+                    if is_prescription(line) {
+                        let item = prescription::medication_json_populator(line);
+                        let (line1, line2) = prescription::recipe_lines(&item);
+                        blocks.push(PdfBlock::Item(line1, line2));
+                    } else if let Some(directive) = medfile::parse_directive(line) {
+                        let note = cid_note(&directive.keyword, &codes).map(|note| format!(" ({})", note)).unwrap_or_default();
+                        blocks.push(PdfBlock::Paragraph(format!("{}: {}{}", conduct_label(&directive.keyword), directive.content, note)));
+                    } else {
+                        blocks.push(PdfBlock::Paragraph(line.clone()));
+                    }
+                }
+            }
+            "LABORATORIO" => {
+                let (results, text) = lab::section_results(&section.lines, "");
+                for line in text {
+                    blocks.push(PdfBlock::Paragraph(line));
+                }
+                for result in &results {
+                    blocks.push(PdfBlock::Paragraph(lab::result_text(result, &demographics)));
+                }
+            }
+            _ => {
+                for line in section.free_text() {
+                    blocks.push(PdfBlock::Paragraph(line));
+                }
+                if section.name == "EXAME FISICO" {
+                    let readings: Vec<String> = Vitals::from_section(section).readings().iter().map(vitals::reading_text).collect();
+                    if !readings.is_empty() {
+                        blocks.push(PdfBlock::Paragraph(format!("Sinais vitais: {}", readings.join(" · "))));
+                    }
+                }
+                for directive in section.directives().into_iter().filter(|d| !(d.keyword == "SV" && section.name == "EXAME FISICO")) {
+                    let text = match directive.keyword.as_str() {
+                        "HPP" => format!("HPP: {}", directive.content),
+                        "MED" => format!("Medicação em uso: {}", directive.content),
+                        "HF" => format!("História familiar: {}", directive.content),
+                        "ALERGIA" => format!("Alergia: {}", directive.content),
+                        "EX" => {
+                            let exam = medfile::parse_exam(&directive.content);
+                            format!("Exame {} [{}]: {}", exam.name, exam.date, exam.result)
+                        }
+                        "LAB" => match lab::parse_result(&directive.content, "") {
+                            Some(result) => lab::result_text(&result, &demographics),
+                            None => format!("LAB: {}", directive.content),
+                        },
+                        _ => format!("{}: {}", directive.keyword, directive.content),
+                    };
+                    blocks.push(PdfBlock::Paragraph(text));
+                }
+            }
+        }
+    }
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn section_names_and_unknown_keywords_are_escaped() {
+        let doc = MedDocument::parse("[<SCRIPT>ALERT(1)</SCRIPT>]\nTEXTO\n\n[CONDUTA]\n!<IMG>X SRC=Y;\n!PRESCREVO TOPIRAMATO 25MG [1 COMPRIMIDO] NOITE;\n");
+        let html = generate_attendance_html("ANA LIMA", "10/01/2025", &doc, "", &[], None);
+        assert!(!html.contains("<SCRIPT>") && !html.contains("<IMG>"));
+        assert!(html.contains("&lt;SCRIPT&gt;ALERT(1)&lt;/SCRIPT&gt;"));
+        assert!(html.contains("&lt;IMG&gt;X"));
+        assert!(html.contains("Prescrição"));
+    }
+
+    #[test]
+    fn encounter_date_is_escaped() {
+        let doc = MedDocument::parse("[HIPOTESE DIAGNOSTICA]\nENXAQUECA {G43.9};\n");
+        let html = generate_attendance_html("ANA LIMA", "<B>2025-01-10</B>", &doc, "", &[], None);
+        assert!(!html.contains("<B>"));
+        assert!(html.contains("&lt;B&gt;2025-01-10&lt;/B&gt;"));
+        assert!(html.contains("CID-10 G43.9"));
+    }
+}
@@ -0,0 +1,351 @@
+// Append-only audit log of record access and changes. Each line of
+// audit_log.jsonl is one entry whose hash covers its own fields and the hash of
+// the previous entry, so editing, removing or reordering any entry breaks the
+// chain from that point on and is reported by `medfiles audit verify`. The
+// chain alone cannot show entries cut from the end or a log rebuilt with fresh
+// hashes, so the count and the last hash are also kept in audit_head.json.
+// The head is authenticated with an HMAC keyed from the workspace key when the
+// workspace is encrypted, or from MEDFILES_AUDIT_KEY, a secret kept outside
+// the workspace. Without either, anyone who can write both files can forge a
+// coherent log, and `medfiles audit verify` says so instead of calling the log
+// intact.
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::crypto;
+
+pub const AUDIT_PATH: &str = "audit_log.jsonl";
+pub const HEAD_PATH: &str = "audit_head.json";
+const HEAD_KEY_PURPOSE: &str = "medfiles-audit-head";
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub seq: u64,
+    pub timestamp: String,
+    pub user: String,
+    pub action: String,
+    // File stem of the .med record; empty for workspace-level actions
+    pub patient: String,
+    pub detail: String,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AuditHead {
+    count: u64,
+    hash: String,
+    // HMAC-SHA256 of count and hash; empty when no head key was available
+    #[serde(default)]
+    mac: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Verified {
+    pub entries: usize,
+    // Whether the head was checked against its HMAC, the only thing that makes the log tamper-evident
+    pub keyed: bool,
+}
+
+impl AuditHead {
+    fn new(count: u64, hash: String, key: Option<&[u8]>) -> AuditHead {
+        let mut head = AuditHead { count, hash, mac: String::new() };
+        if let Some(key) = key {
+            head.mac = head.compute_mac(key);
+        }
+        head
+    }
+
+    fn compute_mac(&self, key: &[u8]) -> String {
+        crypto::hmac_sha256(key, format!("{}\n{}", self.count, self.hash).as_bytes())
+    }
+}
+
+impl AuditEntry {
+    fn compute_hash(&self) -> String {
+        let fields = (&self.seq, &self.timestamp, &self.user, &self.action, &self.patient, &self.detail, &self.prev_hash);
+        let canonical = serde_json::to_string(&fields).unwrap_or_default();
+        crypto::sha256_hex(canonical.as_bytes())
+    }
+}
+
+pub fn patient_name(path: &Path) -> String {
+    path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+fn current_user() -> String {
+    if let Some(user) = crate::load_user_info()
+        && !user.email.is_empty()
+    {
+        return user.email;
+    }
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "desconhecido".to_string())
+}
+
+fn load_entries() -> Result<Vec<AuditEntry>, String> {
+    let content = match crate::storage::read_to_string(AUDIT_PATH) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(format!("Falha ao ler {}: {}", AUDIT_PATH, e)),
+    };
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| serde_json::from_str(line).map_err(|e| format!("Linha {} ilegível: {}", i + 1, e)))
+        .collect()
+}
+
+fn append(entry: &AuditEntry) -> Result<(), String> {
+    let line = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+    if crate::storage::is_encrypted_workspace() {
+        // Sealed files cannot be appended to; the whole log is rewritten under the workspace key
+        let mut content = crate::storage::read_to_string(AUDIT_PATH).unwrap_or_default();
+        content.push_str(&line);
+        content.push('\n');
+        return crate::storage::write(AUDIT_PATH, content).map_err(|e| e.to_string());
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(AUDIT_PATH)
+        .map_err(|e| format!("Falha ao abrir {}: {}", AUDIT_PATH, e))?;
+    writeln!(file, "{}", line).map_err(|e| e.to_string())
+}
+
+fn head_key() -> Result<Option<Vec<u8>>, String> {
+    if let Some(key) = crate::storage::derived_key(HEAD_KEY_PURPOSE)? {
+        return Ok(Some(key.to_vec()));
+    }
+    Ok(std::env::var("MEDFILES_AUDIT_KEY").ok().filter(|key| !key.is_empty()).map(String::into_bytes))
+}
+
+fn try_record(action: &str, patient: &str, detail: &str) -> Result<(), String> {
+    record_with(head_key()?.as_deref(), action, patient, detail)
+}
+
+fn record_with(key: Option<&[u8]>, action: &str, patient: &str, detail: &str) -> Result<(), String> {
+    // The head already holds the last hash; the log is only read for workspaces written before it existed
+    let (seq, prev_hash) = match load_head()? {
+        Some(head) => (head.count + 1, head.hash),
+        None => match load_entries()?.last() {
+            Some(last) => (last.seq + 1, last.hash.clone()),
+            None => (1, GENESIS_HASH.to_string()),
+        },
+    };
+    let mut entry = AuditEntry {
+        seq,
+        timestamp: chrono::Local::now().to_rfc3339(),
+        user: current_user(),
+        action: action.to_string(),
+        patient: patient.to_string(),
+        detail: detail.to_string(),
+        prev_hash,
+        hash: String::new(),
+    };
+    entry.hash = entry.compute_hash();
+    append(&entry)?;
+    write_head(&AuditHead::new(seq, entry.hash, key))
+}
+
+fn write_head(head: &AuditHead) -> Result<(), String> {
+    let json = serde_json::to_string(head).map_err(|e| e.to_string())?;
+    crate::storage::write(HEAD_PATH, json).map_err(|e| format!("Falha ao gravar {}: {}", HEAD_PATH, e))
+}
+
+fn load_head() -> Result<Option<AuditHead>, String> {
+    let content = match crate::storage::read_to_string(HEAD_PATH) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Falha ao ler {}: {}", HEAD_PATH, e)),
+    };
+    serde_json::from_str(&content).map(Some).map_err(|e| format!("{} ilegível: {}", HEAD_PATH, e))
+}
+
+pub fn record(action: &str, patient: &str, detail: &str) {
+    // A failed audit write is reported but never blocks clinical work
+    if let Err(e) = try_record(action, patient, detail) {
+        eprintln!("Falha ao registrar auditoria: {}", e);
+    }
+}
+
+pub fn record_file(action: &str, path: &Path, detail: &str) {
+    record(action, &patient_name(path), detail);
+}
+
+pub fn verify() -> Result<Verified, String> {
+    verify_with(head_key()?.as_deref())
+}
+
+fn verify_with(key: Option<&[u8]>) -> Result<Verified, String> {
+    let head = load_head()?;
+    if let (Some(head), Some(key)) = (&head, key)
+        && !crypto::same_digest(&head.mac, &head.compute_mac(key))
+    {
+        return Err(format!("{} não confere com a chave de auditoria (registro de topo forjado?).", HEAD_PATH));
+    }
+    let keyed = key.is_some();
+    if !Path::new(AUDIT_PATH).exists() {
+        return match head {
+            Some(head) if head.count > 0 => Err(format!("{} removido; o registro de topo indica {} entrada(s).", AUDIT_PATH, head.count)),
+            _ => Ok(Verified { entries: 0, keyed }),
+        };
+    }
+    let entries = load_entries()?;
+    let mut prev_hash = GENESIS_HASH.to_string();
+    for (i, entry) in entries.iter().enumerate() {
+        let expected_seq = i as u64 + 1;
+        if entry.seq != expected_seq {
+            return Err(format!("Entrada {}: sequência {} fora de ordem (esperado {}).", i + 1, entry.seq, expected_seq));
+        }
+        if entry.prev_hash != prev_hash {
+            return Err(format!("Entrada {}: encadeamento quebrado (hash anterior não confere).", entry.seq));
+        }
+        if entry.compute_hash() != entry.hash {
+            return Err(format!("Entrada {}: conteúdo alterado (hash não confere).", entry.seq));
+        }
+        prev_hash = entry.hash.clone();
+    }
+    let Some(head) = head else {
+        if entries.is_empty() {
+            return Ok(Verified { entries: 0, keyed });
+        }
+        return Err(format!("{} ausente: não é possível conferir o fim do log.", HEAD_PATH));
+    };
+    if head.count != entries.len() as u64 {
+        return Err(format!("o log tem {} entrada(s), mas o registro de topo indica {} (entradas removidas do final?).", entries.len(), head.count));
+    }
+    if head.hash != prev_hash {
+        return Err("a última entrada não confere com o registro de topo (log regravado?).".to_string());
+    }
+    Ok(Verified { entries: entries.len(), keyed })
+}
+
+pub fn show(patient: Option<&str>) -> Result<(), String> {
+    let entries = load_entries()?;
+    let filter = patient.map(|p| p.to_lowercase());
+    let mut shown = 0;
+    for entry in &entries {
+        if let Some(filter) = &filter
+            && !entry.patient.to_lowercase().contains(filter.as_str())
+        {
+            continue;
+        }
+        let timestamp = chrono::DateTime::parse_from_rfc3339(&entry.timestamp)
+            .map(|t| t.format("%d/%m/%Y %H:%M:%S").to_string())
+            .unwrap_or_else(|_| entry.timestamp.clone());
+        let patient = if entry.patient.is_empty() { "-" } else { &entry.patient };
+        println!("#{} {} {} {} [{}] {}", entry.seq, timestamp, entry.user, entry.action, patient, entry.detail);
+        shown += 1;
+    }
+    if shown == 0 {
+        println!("Nenhuma entrada de auditoria encontrada.");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testing::Workspace;
+
+    fn log_with_three_entries() -> Workspace {
+        let workspace = Workspace::new();
+        for detail in ["primeira", "segunda", "terceira"] {
+            try_record("visualizar", "ANA LIMA 2025-01-10", detail).unwrap();
+        }
+        assert_eq!(verify(), Ok(Verified { entries: 3, keyed: false }));
+        workspace
+    }
+
+    fn rewrite(entries: &[AuditEntry]) {
+        let lines: Vec<String> = entries.iter().map(|e| serde_json::to_string(e).unwrap()).collect();
+        fs::write(AUDIT_PATH, lines.join("\n") + "\n").unwrap();
+    }
+
+    #[test]
+    fn edited_entry_breaks_the_chain() {
+        let _workspace = log_with_three_entries();
+        let mut entries = load_entries().unwrap();
+        entries[1].detail = "outra".to_string();
+        rewrite(&entries);
+        assert!(verify().unwrap_err().contains("Entrada 2"));
+    }
+
+    #[test]
+    fn entries_removed_from_the_end_are_detected() {
+        let _workspace = log_with_three_entries();
+        let entries = load_entries().unwrap();
+        rewrite(&entries[..2]);
+        assert!(verify().unwrap_err().contains("removidas do final"));
+        fs::remove_file(AUDIT_PATH).unwrap();
+        assert!(verify().unwrap_err().contains("removido"));
+    }
+
+    #[test]
+    fn rehashed_log_is_detected() {
+        let _workspace = log_with_three_entries();
+        let mut entries = load_entries().unwrap();
+        let mut prev_hash = GENESIS_HASH.to_string();
+        for entry in &mut entries {
+            entry.detail = "adulterada".to_string();
+            entry.prev_hash = prev_hash;
+            entry.hash = entry.compute_hash();
+            prev_hash = entry.hash.clone();
+        }
+        rewrite(&entries);
+        assert!(verify().unwrap_err().contains("regravado"));
+    }
+
+    #[test]
+    fn missing_head_is_reported() {
+        let _workspace = log_with_three_entries();
+        fs::remove_file(HEAD_PATH).unwrap();
+        assert!(verify().unwrap_err().contains(HEAD_PATH));
+    }
+
+    const KEY: &[u8] = b"segredo fora do workspace";
+
+    #[test]
+    fn keyed_head_detects_a_rebuilt_log() {
+        let _workspace = Workspace::new();
+        for detail in ["primeira", "segunda"] {
+            record_with(Some(KEY), "visualizar", "ANA LIMA 2025-01-10", detail).unwrap();
+        }
+        assert_eq!(verify_with(Some(KEY)), Ok(Verified { entries: 2, keyed: true }));
+
+        // A forger without the key rebuilds the log and a matching head
+        let mut entries = load_entries().unwrap();
+        entries.truncate(1);
+        entries[0].detail = "adulterada".to_string();
+        entries[0].hash = entries[0].compute_hash();
+        rewrite(&entries);
+        write_head(&AuditHead::new(1, entries[0].hash.clone(), Some(b"outra chave"))).unwrap();
+        assert!(verify_with(Some(KEY)).unwrap_err().contains("forjado"));
+        write_head(&AuditHead::new(1, entries[0].hash.clone(), None)).unwrap();
+        assert!(verify_with(Some(KEY)).unwrap_err().contains("forjado"));
+
+        // Without the key the forgery goes through, which is why it is not reported as intact
+        assert_eq!(verify_with(None), Ok(Verified { entries: 1, keyed: false }));
+    }
+
+    #[test]
+    fn new_entries_chain_from_the_head() {
+        let _workspace = log_with_three_entries();
+        let head = load_head().unwrap().unwrap();
+        try_record("visualizar", "ANA LIMA 2025-01-10", "quarta").unwrap();
+        let entries = load_entries().unwrap();
+        assert_eq!(entries[3].seq, 4);
+        assert_eq!(entries[3].prev_hash, head.hash);
+        assert_eq!(verify().unwrap().entries, 4);
+    }
+}
@@ -0,0 +1,252 @@
+// Small inline SVG line charts for values tracked across encounters (vital
+// signs, laboratory results). Visits are spaced evenly on the x axis and the
+// normal range, when known, is drawn as a shaded band behind the lines. Doses
+// are drawn as step lines over a true date axis instead, since the time spent
+// on each dose matters as much as the dose itself.
+
+use chrono::NaiveDate;
+
+use crate::html::html_escape;
+
+const WIDTH: f64 = 600.0;
+const HEIGHT: f64 = 200.0;
+const LEFT: f64 = 45.0;
+const RIGHT: f64 = 15.0;
+const TOP: f64 = 15.0;
+const BOTTOM: f64 = 30.0;
+const COLORS: [&str; 4] = ["#2980b9", "#8e44ad", "#16a085", "#d35400"];
+
+pub struct Point {
+    pub label: String,
+    pub value: f64,
+    // Out-of-range points are drawn in red
+    pub flagged: bool,
+}
+
+pub struct Series {
+    pub name: String,
+    pub points: Vec<Option<Point>>,
+}
+
+pub struct Step {
+    pub date: NaiveDate,
+    pub value: f64,
+    // A suspension ends the line and is marked with a cross on the axis
+    pub suspended: bool,
+    pub tooltip: String,
+}
+
+pub fn format_value(value: f64) -> String {
+    if value.fract() == 0.0 { format!("{:.0}", value) } else { format!("{:.1}", value).replace('.', ",") }
+}
+
+pub fn line_chart_svg(labels: &[String], series: &[Series], band: Option<(f64, f64)>) -> String {
+    let values: Vec<f64> = series.iter().flat_map(|s| s.points.iter().flatten().map(|p| p.value)).collect();
+    if values.is_empty() || labels.is_empty() {
+        return String::new();
+    }
+    let mut min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+    let mut max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    if let Some((low, high)) = band {
+        min = min.min(low);
+        max = max.max(high);
+    }
+    let margin = ((max - min) * 0.1).max(1.0);
+    let (min, max) = (min - margin, max + margin);
+
+    let plot_width = WIDTH - LEFT - RIGHT;
+    let plot_height = HEIGHT - TOP - BOTTOM;
+    let x = |i: usize| {
+        if labels.len() == 1 { LEFT + plot_width / 2.0 } else { LEFT + plot_width * i as f64 / (labels.len() - 1) as f64 }
+    };
+    let y = |value: f64| TOP + plot_height * (max - value) / (max - min);
+
+    let mut svg = format!(
+        "<svg class=\"chart\" xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {} {}\" role=\"img\">",
+        WIDTH, HEIGHT
+    );
+    if let Some((low, high)) = band {
+        svg.push_str(&format!(
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"#eafaf1\"/>",
+            LEFT,
+            y(high),
+            plot_width,
+            (y(low) - y(high)).max(0.0)
+        ));
+    }
+    svg.push_str(&format!(
+        "<line x1=\"{0}\" y1=\"{1}\" x2=\"{0}\" y2=\"{2}\" stroke=\"#bdc3c7\"/><line x1=\"{0}\" y1=\"{2}\" x2=\"{3}\" y2=\"{2}\" stroke=\"#bdc3c7\"/>",
+        LEFT,
+        TOP,
+        TOP + plot_height,
+        WIDTH - RIGHT
+    ));
+    for value in [min + margin, max - margin] {
+        svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" font-size=\"11\" text-anchor=\"end\" fill=\"#7f8c8d\">{}</text>",
+            LEFT - 5.0,
+            y(value) + 4.0,
+            format_value(value)
+        ));
+    }
+    for (i, label) in labels.iter().enumerate() {
+        svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" font-size=\"11\" text-anchor=\"middle\" fill=\"#7f8c8d\">{}</text>",
+            x(i),
+            HEIGHT - 10.0,
+            html_escape(label)
+        ));
+    }
+
+    for (index, s) in series.iter().enumerate() {
+        let color = COLORS[index % COLORS.len()];
+        let coordinates: Vec<String> = s
+            .points
+            .iter()
+            .enumerate()
+            .filter_map(|(i, p)| p.as_ref().map(|p| format!("{:.1},{:.1}", x(i), y(p.value))))
+            .collect();
+        if coordinates.len() > 1 {
+            svg.push_str(&format!(
+                "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\"/>",
+                coordinates.join(" "),
+                color
+            ));
+        }
+        for (i, point) in s.points.iter().enumerate() {
+            let Some(point) = point else {
+                continue;
+            };
+            let fill = if point.flagged { "#e74c3c" } else { color };
+            svg.push_str(&format!(
+                "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"4\" fill=\"{}\"><title>{}: {} ({})</title></circle>",
+                x(i),
+                y(point.value),
+                fill,
+                html_escape(&s.name),
+                format_value(point.value),
+                html_escape(&point.label)
+            ));
+        }
+    }
+    if series.len() > 1 {
+        for (index, s) in series.iter().enumerate() {
+            svg.push_str(&format!(
+                "<text x=\"{:.1}\" y=\"{:.1}\" font-size=\"11\" fill=\"{}\">{}</text>",
+                LEFT + 10.0 + index as f64 * 110.0,
+                TOP + 10.0,
+                COLORS[index % COLORS.len()],
+                html_escape(&s.name)
+            ));
+        }
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+pub fn date_labels(dates: &[NaiveDate], x: impl Fn(NaiveDate) -> f64, y: f64) -> String {
+    // Labels closer than 60 units to the previous one are skipped so they never overlap
+    let mut dates = dates.to_vec();
+    dates.sort();
+    dates.dedup();
+    let mut svg = String::new();
+    let mut last_label: Option<f64> = None;
+    for date in dates {
+        let position = x(date);
+        if last_label.is_some_and(|last| position - last < 60.0) {
+            continue;
+        }
+        svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" font-size=\"11\" text-anchor=\"middle\" fill=\"#7f8c8d\">{}</text>",
+            position,
+            y,
+            date.format("%d/%m/%y")
+        ));
+        last_label = Some(position);
+    }
+    svg
+}
+
+pub fn step_chart_svg(start: NaiveDate, end: NaiveDate, steps: &[Step], color_index: usize) -> String {
+    if steps.is_empty() {
+        return String::new();
+    }
+    // A single day has no width: pad the axis so the step is still visible
+    let (start, end) = if end > start { (start, end) } else { (start - chrono::Duration::days(15), start + chrono::Duration::days(15)) };
+    let max = steps.iter().map(|s| s.value).fold(0.0, f64::max);
+    let top = if max > 0.0 { max * 1.15 } else { 1.0 };
+    let plot_width = WIDTH - LEFT - RIGHT;
+    let plot_height = HEIGHT - TOP - BOTTOM;
+    let span = (end - start).num_days() as f64;
+    let x = |date: NaiveDate| LEFT + plot_width * (date - start).num_days() as f64 / span;
+    let y = |value: f64| TOP + plot_height * (top - value) / top;
+    let color = COLORS[color_index % COLORS.len()];
+
+    let mut svg = format!(
+        "<svg class=\"chart\" xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {} {}\" role=\"img\">",
+        WIDTH, HEIGHT
+    );
+    svg.push_str(&format!(
+        "<line x1=\"{0}\" y1=\"{1}\" x2=\"{0}\" y2=\"{2}\" stroke=\"#bdc3c7\"/><line x1=\"{0}\" y1=\"{2}\" x2=\"{3}\" y2=\"{2}\" stroke=\"#bdc3c7\"/>",
+        LEFT,
+        TOP,
+        TOP + plot_height,
+        WIDTH - RIGHT
+    ));
+    for value in [0.0, max] {
+        svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" font-size=\"11\" text-anchor=\"end\" fill=\"#7f8c8d\">{}</text>",
+            LEFT - 5.0,
+            y(value) + 4.0,
+            format_value(value)
+        ));
+    }
+    // Date labels at the ends of the axis and at every change
+    let dates: Vec<NaiveDate> = std::iter::once(start).chain(steps.iter().map(|s| s.date)).chain(std::iter::once(end)).collect();
+    svg.push_str(&date_labels(&dates, x, HEIGHT - 10.0));
+
+    let mut path = String::new();
+    for (index, step) in steps.iter().enumerate() {
+        if step.suspended {
+            continue;
+        }
+        let until = steps.get(index + 1).map(|next| next.date).unwrap_or(end);
+        path.push_str(&format!("M{:.1},{:.1} H{:.1} ", x(step.date), y(step.value), x(until)));
+        if let Some(next) = steps.get(index + 1)
+            && !next.suspended
+        {
+            path.push_str(&format!("V{:.1} ", y(next.value)));
+        }
+    }
+    if !path.is_empty() {
+        svg.push_str(&format!("<path d=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\"/>", path.trim_end(), color));
+    }
+    for step in steps {
+        let tooltip = html_escape(&step.tooltip);
+        if step.suspended {
+            let (cx, cy) = (x(step.date), y(0.0));
+            svg.push_str(&format!(
+                "<g stroke=\"#e74c3c\" stroke-width=\"2\"><title>{}</title><line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\"/><line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\"/></g>",
+                tooltip,
+                cx - 5.0,
+                cy - 5.0,
+                cx + 5.0,
+                cy + 5.0,
+                cx - 5.0,
+                cy + 5.0,
+                cx + 5.0,
+                cy - 5.0
+            ));
+        } else {
+            svg.push_str(&format!(
+                "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"4\" fill=\"{}\"><title>{}</title></circle>",
+                x(step.date),
+                y(step.value),
+                color,
+                tooltip
+            ));
+        }
+    }
+    svg.push_str("</svg>");
+    svg
+}
@@ -0,0 +1,216 @@
+// CID-10 coding of diagnostic hypotheses. The table in assets/cid10/cid10.tsv
+// is a local subset of the DATASUS classification, embedded in the binary so
+// lookups work offline. Free-text hypotheses are matched against descriptions
+// and synonyms ignoring accents, case and small typos; an explicit `{G20}`
+// annotation in the .med file always wins over any suggestion.
+
+use std::sync::OnceLock;
+
+const TABLE: &str = include_str!("../assets/cid10/cid10.tsv");
+const STOPWORDS: [&str; 12] = ["a", "o", "as", "os", "de", "da", "do", "das", "dos", "e", "em", "ou"];
+// Suggestions scoring below this are noise rather than candidates
+const MIN_SCORE: f64 = 0.5;
+
+pub struct CidEntry {
+    pub code: String,
+    pub description: String,
+    pub synonyms: Vec<String>,
+}
+
+pub struct Suggestion {
+    pub entry: &'static CidEntry,
+    pub score: f64,
+}
+
+pub fn entries() -> &'static [CidEntry] {
+    static ENTRIES: OnceLock<Vec<CidEntry>> = OnceLock::new();
+    ENTRIES.get_or_init(|| {
+        TABLE
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let mut columns = line.split('\t');
+                let code = columns.next()?.trim().to_string();
+                let description = columns.next()?.trim().to_string();
+                let synonyms = columns
+                    .next()
+                    .map(|s| s.split('|').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect())
+                    .unwrap_or_default();
+                Some(CidEntry { code, description, synonyms })
+            })
+            .collect()
+    })
+}
+
+fn fold_accent(c: char) -> char {
+    match c {
+        'á' | 'à' | 'â' | 'ã' | 'ä' => 'a',
+        'é' | 'è' | 'ê' | 'ë' => 'e',
+        'í' | 'ì' | 'î' | 'ï' => 'i',
+        'ó' | 'ò' | 'ô' | 'õ' | 'ö' => 'o',
+        'ú' | 'ù' | 'û' | 'ü' => 'u',
+        'ç' => 'c',
+        'ñ' => 'n',
+        other => other,
+    }
+}
+
+pub fn normalize(text: &str) -> String {
+    let folded: String = text
+        .to_lowercase()
+        .chars()
+        .map(fold_accent)
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { ' ' })
+        .collect();
+    folded.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn tokens(text: &str) -> Vec<String> {
+    normalize(text)
+        .split(' ')
+        .filter(|token| !token.is_empty() && !STOPWORDS.contains(token))
+        .map(str::to_string)
+        .collect()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            current.push((previous[j] + cost).min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn token_similarity(query: &str, candidate: &str) -> f64 {
+    if query == candidate {
+        return 1.0;
+    }
+    let shorter = query.len().min(candidate.len());
+    // "parkinson" / "parkinsoniano", "epilep" / "epilepsia"
+    if shorter >= 4 && (candidate.starts_with(query) || query.starts_with(candidate)) {
+        return 0.8;
+    }
+    let allowed = match shorter {
+        0..=4 => 0,
+        5..=7 => 1,
+        _ => 2,
+    };
+    if allowed > 0 && edit_distance(query, candidate) <= allowed {
+        return 0.8;
+    }
+    0.0
+}
+
+fn text_score(query: &[String], candidate: &str) -> f64 {
+    let candidate = tokens(candidate);
+    if query.is_empty() || candidate.is_empty() {
+        return 0.0;
+    }
+    let matched: f64 = query
+        .iter()
+        .map(|q| candidate.iter().map(|c| token_similarity(q, c)).fold(0.0, f64::max))
+        .sum();
+    // Covering the query matters most; extra words in the description cost a little
+    let coverage = matched / query.len() as f64;
+    let precision = (matched / candidate.len() as f64).min(1.0);
+    coverage * (0.6 + 0.4 * precision)
+}
+
+pub fn suggest(text: &str, limit: usize) -> Vec<Suggestion> {
+    let query = tokens(text);
+    let mut suggestions: Vec<Suggestion> = entries()
+        .iter()
+        .filter_map(|entry| {
+            let score = std::iter::once(&entry.description)
+                .chain(entry.synonyms.iter())
+                .map(|candidate| text_score(&query, candidate))
+                .fold(0.0, f64::max);
+            (score >= MIN_SCORE).then_some(Suggestion { entry, score })
+        })
+        .collect();
+    suggestions.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.entry.code.cmp(&b.entry.code)));
+    suggestions.truncate(limit);
+    suggestions
+}
+
+pub fn exact_match(text: &str) -> Option<&'static CidEntry> {
+    let normalized = normalize(text);
+    if normalized.is_empty() {
+        return None;
+    }
+    entries().iter().find(|entry| {
+        normalize(&entry.description) == normalized || entry.synonyms.iter().any(|s| normalize(s) == normalized)
+    })
+}
+
+pub fn normalize_code(code: &str) -> Option<String> {
+    // Accepts "g20", "G25.0", "G250" and "G25 0", returning the dotted form
+    let compact: String = code.chars().filter(|c| !matches!(c, '.' | ' ' | '-')).collect::<String>().to_uppercase();
+    let mut chars = compact.chars();
+    let letter = chars.next().filter(|c| c.is_ascii_uppercase())?;
+    let digits: String = chars.collect();
+    if !(2..=4).contains(&digits.len()) || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let (category, subcategory) = digits.split_at(2);
+    if subcategory.is_empty() {
+        Some(format!("{}{}", letter, category))
+    } else {
+        Some(format!("{}{}.{}", letter, category, subcategory))
+    }
+}
+
+pub fn lookup(code: &str) -> Option<&'static CidEntry> {
+    let code = normalize_code(code)?;
+    entries().iter().find(|entry| entry.code == code)
+}
+
+pub fn description(code: &str) -> Option<String> {
+    lookup(code).map(|entry| entry.description.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(text: &str) -> Vec<String> {
+        suggest(text, 3).iter().map(|s| s.entry.code.clone()).collect()
+    }
+
+    #[test]
+    fn suggestions_ignore_accents_case_and_small_typos() {
+        assert_eq!(normalize("  Doença de PARKINSON! "), "doenca de parkinson");
+        assert_eq!(codes("doenca de parkinson")[0], "G20");
+        assert_eq!(codes("Doença de Parkinsom")[0], "G20");
+        assert_eq!(codes("diabetes tipo 2")[0], "E11.9");
+        assert!(codes("xyzw qwerty").is_empty());
+        assert_eq!(edit_distance("parkinson", "parkinsom"), 1);
+        assert_eq!(edit_distance("", "dm2"), 3);
+    }
+
+    #[test]
+    fn exact_match_uses_descriptions_and_synonyms() {
+        assert_eq!(exact_match("doença de parkinson").unwrap().code, "G20");
+        assert_eq!(exact_match("dm2").unwrap().code, "E11.9");
+        assert!(exact_match("parkinsonismo").is_none());
+        assert!(exact_match(" - ").is_none());
+    }
+
+    #[test]
+    fn codes_are_normalized_to_the_dotted_form() {
+        assert_eq!(normalize_code("g20").as_deref(), Some("G20"));
+        assert_eq!(normalize_code("G21.1").as_deref(), Some("G21.1"));
+        assert_eq!(normalize_code("g211").as_deref(), Some("G21.1"));
+        assert_eq!(normalize_code("G21 1").as_deref(), Some("G21.1"));
+        for invalid in ["", "20", "G2", "G21111", "GXX"] {
+            assert_eq!(normalize_code(invalid), None, "{}", invalid);
+        }
+        assert_eq!(description("g20").as_deref(), Some("Doença de Parkinson"));
+    }
+}
@@ -0,0 +1,57 @@
+// Hashing helpers shared by the modules that keep secrets or digests in the
+// workspace: API tokens, verification codes, document signatures, the audit
+// log and issued prescriptions. Digests are stored as lowercase hex.
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex(&bytes)
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+pub fn salted_sha256(salt: &str, secret: &str) -> String {
+    // Enough for random high-entropy secrets such as API tokens
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(secret.as_bytes());
+    hex(&hasher.finalize())
+}
+
+pub fn argon2id(salt: &str, secret: &str, (m_cost, t_cost): (u32, u32)) -> String {
+    // For low-entropy secrets, where the cost of each guess is the protection
+    let mut hash = [0u8; 32];
+    let hashed = Params::new(m_cost, t_cost, 1, Some(hash.len()))
+        .map(|params| Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+        .and_then(|argon2| argon2.hash_password_into(secret.as_bytes(), salt.as_bytes(), &mut hash));
+    // Fixed parameters and a 16-byte salt never fail; an empty hash would match nothing anyway
+    if hashed.is_err() {
+        return String::new();
+    }
+    hex(&hash)
+}
+
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> String {
+    let Ok(mut mac) = <Hmac<Sha256> as Mac>::new_from_slice(key) else {
+        return String::new();
+    };
+    mac.update(message);
+    hex(&mac.finalize().into_bytes())
+}
+
+pub fn same_digest(a: &str, b: &str) -> bool {
+    // Constant time, so a stored hash cannot be recovered byte by byte from response times
+    !a.is_empty() && bool::from(a.as_bytes().ct_eq(b.as_bytes()))
+}
@@ -0,0 +1,262 @@
+// Daily dose of each medication over time. The encounters are replayed in
+// chronological order through the medication engine, so the doses follow the
+// same rules as medications.json: `!PRESCREVO` starts a medication, `!AUMENTO`
+// changes one in use and `!SUSPENDO` stops it, while commands the engine
+// ignores leave no point on the chart. The daily dose is the number of units
+// per intake (`[1/2 COMPRIMIDO]`) times the intakes per day read from the
+// posology (`12/12 HORAS`, `2X`, `NOITE`), and is normalized to mg/day when
+// the strength (`37,5MG`) is known.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::NaiveDate;
+
+use crate::charts::{self, Step};
+use crate::html::html_escape;
+use crate::prescription::{self, ChangeRecord, Medications, parse_dosage};
+use crate::site::Encounter;
+
+pub struct DoseEvent {
+    pub date: NaiveDate,
+    pub date_str: String,
+    pub encounter: String,
+    pub command: String,
+    pub units_per_day: f64,
+    pub mg_per_day: Option<f64>,
+    pub suspended: bool,
+    // Plain-text diff against the previous prescription of the medication
+    pub diff: String,
+}
+
+pub struct DoseHistory {
+    pub medication: String,
+    pub events: Vec<DoseEvent>,
+}
+
+impl DoseHistory {
+    pub fn in_mg(&self) -> bool {
+        // A single intake with unknown strength makes the mg axis meaningless
+        self.events.iter().filter(|e| !e.suspended).all(|e| e.mg_per_day.is_some())
+    }
+
+    pub fn unit(&self) -> &'static str {
+        if self.in_mg() { "mg/dia" } else { "unid./dia" }
+    }
+
+    pub fn value(&self, event: &DoseEvent) -> f64 {
+        if event.suspended {
+            0.0
+        } else if self.in_mg() {
+            event.mg_per_day.unwrap_or(0.0)
+        } else {
+            event.units_per_day
+        }
+    }
+}
+
+pub fn strength_mg(dosage: &str) -> Option<f64> {
+    // "37,5MG", "1G", "500MCG"; combinations such as "100/25MG" have no single strength
+    let dosage = dosage.trim().to_uppercase();
+    if dosage.contains('/') {
+        return None;
+    }
+    let number: String = dosage.chars().take_while(|c| c.is_ascii_digit() || *c == ',' || *c == '.').collect();
+    let value: f64 = number.replace(',', ".").parse().ok()?;
+    match dosage[number.len()..].trim() {
+        "MG" => Some(value),
+        "G" => Some(value * 1000.0),
+        "MCG" | "UG" => Some(value / 1000.0),
+        _ => None,
+    }
+}
+
+pub fn intakes_per_day(posology: &str) -> f64 {
+    let posology = posology.to_uppercase();
+    let tokens: Vec<&str> = posology.split_whitespace().collect();
+    let weekly = posology.contains("SEMANA");
+    for token in &tokens {
+        // "12/12", "8/8H"
+        if let Some((interval, _)) = token.split_once('/')
+            && let Ok(hours) = interval.parse::<f64>()
+            && hours > 0.0
+        {
+            return 24.0 / hours;
+        }
+        // "2X", "3X/DIA", "1X/SEMANA"
+        if let Some(count) = token.split_once('X').and_then(|(count, _)| count.parse::<f64>().ok()) {
+            return if weekly { count / 7.0 } else { count };
+        }
+    }
+    let periods = ["MANHA", "MANHÃ", "TARDE", "NOITE", "ALMOCO", "ALMOÇO", "JANTAR", "DEITAR"];
+    let count = tokens.iter().filter(|t| periods.contains(t)).count();
+    if weekly { 1.0 / 7.0 } else { count.max(1) as f64 }
+}
+
+fn units_per_intake(dosage_observations: &str) -> f64 {
+    match parse_dosage(dosage_observations) {
+        units if units > 0.0 => units,
+        _ => 1.0,
+    }
+}
+
+pub fn daily_dose(dosage: &str, dosage_observations: &str, posology: &str) -> (f64, Option<f64>) {
+    // (units per day, mg per day when the strength is known)
+    let units_per_day = units_per_intake(dosage_observations) * intakes_per_day(posology);
+    (units_per_day, strength_mg(dosage).map(|mg| mg * units_per_day))
+}
+
+const FIELDS: [&str; 4] = ["dosage", "dosage_observations", "posologia", "posology_observations"];
+
+fn change_diff(medication: &str, command: &str, records: &[&ChangeRecord], state: Option<&HashMap<String, String>>) -> String {
+    if command == "SUSPEND" {
+        return format!("Suspensão de {}", medication);
+    }
+    let mut lines = vec![format!("Mudanças para {}:", medication)];
+    if records.iter().any(|r| r.2 == "initial") {
+        // A new medication has nothing to remove: list the fields it starts with
+        for field in FIELDS {
+            let value = state.and_then(|s| s.get(field)).map(|v| v.trim()).unwrap_or("");
+            if !value.is_empty() {
+                lines.push(format!("+ {}: {}", field, value));
+            }
+        }
+    } else {
+        for (_, _, field, old, new, _) in records {
+            lines.push(format!("- {}: {}", field, old));
+            lines.push(format!("+ {}: {}", field, new));
+        }
+    }
+    lines.join("\n")
+}
+
+pub fn dose_history(encounters: &[Encounter]) -> Vec<DoseHistory> {
+    let mut medications = Medications::new();
+    let mut histories: BTreeMap<String, Vec<DoseEvent>> = BTreeMap::new();
+    for encounter in encounters {
+        let date = chrono::DateTime::<chrono::Local>::from(encounter.modified).date_naive();
+        let changes = prescription::replay_encounter(encounter, &mut medications);
+        // One point per medication changed in the encounter, at the state it was left in
+        let mut changed: Vec<&str> = vec![];
+        for record in &changes {
+            if !changed.contains(&record.0.as_str()) {
+                changed.push(&record.0);
+            }
+        }
+        for medication in changed {
+            let records: Vec<&ChangeRecord> = changes.iter().filter(|r| r.0 == medication).collect();
+            let command = records.last().map(|r| r.5.clone()).unwrap_or_default();
+            let state = medications.get(medication);
+            let field = |name: &str| state.and_then(|s| s.get(name)).map(String::as_str).unwrap_or("");
+            let (units_per_day, mg_per_day) = daily_dose(field("dosage"), field("dosage_observations"), field("posologia"));
+            histories.entry(medication.to_string()).or_default().push(DoseEvent {
+                date,
+                date_str: encounter.date_str.clone(),
+                encounter: encounter.name.clone(),
+                diff: change_diff(medication, &command, &records, state),
+                suspended: state.is_none(),
+                command,
+                units_per_day,
+                mg_per_day,
+            });
+        }
+    }
+    histories
+        .into_iter()
+        .map(|(medication, events)| DoseHistory { medication, events })
+        .collect()
+}
+
+pub fn command_label(command: &str) -> &str {
+    match command {
+        "PRESCRIBE" => "Prescrição",
+        "INCREASE" => "Aumento",
+        "SUSPEND" => "Suspensão",
+        other => other,
+    }
+}
+
+pub fn date_range(encounters: &[Encounter]) -> Option<(NaiveDate, NaiveDate)> {
+    let dates: Vec<NaiveDate> = encounters
+        .iter()
+        .map(|e| chrono::DateTime::<chrono::Local>::from(e.modified).date_naive())
+        .collect();
+    Some((*dates.iter().min()?, *dates.iter().max()?))
+}
+
+pub fn generate_dose_charts(encounters: &[Encounter]) -> String {
+    let histories = dose_history(encounters);
+    let Some((start, end)) = date_range(encounters) else {
+        return String::new();
+    };
+    if histories.is_empty() {
+        return String::new();
+    }
+    let mut html = String::from("<div class=\"prescription-timeline\">");
+    for (index, history) in histories.iter().enumerate() {
+        let unit = history.unit();
+        let steps: Vec<Step> = history
+            .events
+            .iter()
+            .map(|event| Step {
+                date: event.date,
+                value: history.value(event),
+                suspended: event.suspended,
+                tooltip: format!(
+                    "{} - {} ({}): {} {}\n{}",
+                    event.date_str,
+                    command_label(&event.command),
+                    event.encounter,
+                    charts::format_value(history.value(event)),
+                    unit,
+                    event.diff
+                ),
+            })
+            .collect();
+        html.push_str(&format!(
+            "<h3>{} <small>({})</small></h3>{}",
+            html_escape(&history.medication),
+            unit,
+            charts::step_chart_svg(start, end, &steps, index)
+        ));
+    }
+    html.push_str("</div>");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+    use crate::medfile::MedDocument;
+
+    fn encounter(day: u64, conduta: &str) -> Encounter {
+        Encounter {
+            modified: SystemTime::UNIX_EPOCH + Duration::from_secs(day * 86_400),
+            name: format!("ANA LIMA dia {}", day),
+            slug: format!("ana-lima-{}", day),
+            date_str: format!("dia {}", day),
+            doc: MedDocument::parse(&format!("[CONDUTA]\n{}\n", conduta)),
+        }
+    }
+
+    #[test]
+    fn only_changes_applied_by_the_engine_become_points() {
+        let encounters = [
+            encounter(1, "!AUMENTO TOPIRAMATO 50MG [1 COMPRIMIDO] NOITE;"),
+            encounter(2, "!PRESCREVO TOPIRAMATO 25MG [1 COMPRIMIDO] NOITE;"),
+            encounter(3, "!REDUZO TOPIRAMATO 12,5MG [1 COMPRIMIDO] NOITE;\n!DESMAME TOPIRAMATO;"),
+            encounter(4, "!AUMENTO TOPIRAMATO 50MG [1 COMPRIMIDO] 12/12 HORAS;"),
+            encounter(5, "!SUSPENDO TOPIRAMATO;\n!SUSPENDO PROPRANOLOL;"),
+        ];
+        let histories = dose_history(&encounters);
+        assert_eq!(histories.len(), 1);
+        let history = &histories[0];
+        assert_eq!(history.medication, "TOPIRAMATO");
+        let points: Vec<_> = history.events.iter().map(|e| (e.date_str.as_str(), e.command.as_str(), history.value(e))).collect();
+        assert_eq!(points, [("dia 2", "PRESCRIBE", 25.0), ("dia 4", "INCREASE", 100.0), ("dia 5", "SUSPEND", 0.0)]);
+        assert!(history.events[2].suspended);
+        assert!(history.events[1].diff.contains("- posologia: NOITE"));
+        assert!(history.events[1].diff.contains("+ dosage: 50MG"));
+    }
+}
@@ -0,0 +1,109 @@
+// Errors of the library and of the command line. Every error names the file
+// it refers to (empty when there is none, e.g. a port already in use) and its
+// kind decides the exit code of `medfiles`, documented in the README so that
+// scripts can tell an unreadable file from a corrupted state or a bad
+// configuration.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const EXIT_IO: i32 = 1;
+// Invalid arguments, reported by clap
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_PARSE: i32 = 3;
+pub const EXIT_STATE: i32 = 4;
+pub const EXIT_CONFIG: i32 = 5;
+pub const EXIT_INVALID: i32 = 6;
+// The command finished, but one or more files were skipped
+pub const EXIT_PARTIAL: i32 = 7;
+
+#[derive(Debug)]
+pub enum MedError {
+    // A file could not be read or written
+    Io { path: PathBuf, source: io::Error },
+    // A file was read but its content is not what was expected (.med, JSON, FHIR)
+    Parse { path: PathBuf, message: String },
+    // medications.json, history.json or another state file is inconsistent
+    State { path: PathBuf, message: String },
+    // user_info.json, SMTP, keys, tokens, passphrase or command options
+    Config { path: PathBuf, message: String },
+    // A signature, prescription code or audit chain did not verify
+    Invalid { path: PathBuf, message: String },
+}
+
+impl MedError {
+    pub fn io(path: impl AsRef<Path>, source: io::Error) -> MedError {
+        MedError::Io { path: path.as_ref().to_path_buf(), source }
+    }
+
+    pub fn parse(path: impl AsRef<Path>, message: impl fmt::Display) -> MedError {
+        MedError::Parse { path: path.as_ref().to_path_buf(), message: message.to_string() }
+    }
+
+    pub fn state(path: impl AsRef<Path>, message: impl fmt::Display) -> MedError {
+        MedError::State { path: path.as_ref().to_path_buf(), message: message.to_string() }
+    }
+
+    pub fn config(path: impl AsRef<Path>, message: impl fmt::Display) -> MedError {
+        MedError::Config { path: path.as_ref().to_path_buf(), message: message.to_string() }
+    }
+
+    pub fn invalid(path: impl AsRef<Path>, message: impl fmt::Display) -> MedError {
+        MedError::Invalid { path: path.as_ref().to_path_buf(), message: message.to_string() }
+    }
+
+    pub fn read(path: impl AsRef<Path>, source: io::Error) -> MedError {
+        // Text files that are not UTF-8 were read fine but cannot be interpreted
+        if source.kind() == io::ErrorKind::InvalidData {
+            return MedError::parse(path, source);
+        }
+        MedError::io(path, source)
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            MedError::Io { path, .. }
+            | MedError::Parse { path, .. }
+            | MedError::State { path, .. }
+            | MedError::Config { path, .. }
+            | MedError::Invalid { path, .. } => path,
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            MedError::Io { .. } => EXIT_IO,
+            MedError::Parse { .. } => EXIT_PARSE,
+            MedError::State { .. } => EXIT_STATE,
+            MedError::Config { .. } => EXIT_CONFIG,
+            MedError::Invalid { .. } => EXIT_INVALID,
+        }
+    }
+}
+
+impl fmt::Display for MedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (kind, message) = match self {
+            MedError::Io { source, .. } => ("Erro de leitura/gravação", source.to_string()),
+            MedError::Parse { message, .. } => ("Formato inválido", message.clone()),
+            MedError::State { message, .. } => ("Estado inconsistente", message.clone()),
+            MedError::Config { message, .. } => ("Configuração inválida", message.clone()),
+            MedError::Invalid { message, .. } => ("Verificação falhou", message.clone()),
+        };
+        if self.path().as_os_str().is_empty() {
+            write!(f, "{}: {}", kind, message)
+        } else {
+            write!(f, "{} em {}: {}", kind, self.path().display(), message)
+        }
+    }
+}
+
+impl std::error::Error for MedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MedError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
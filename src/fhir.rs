@@ -0,0 +1,682 @@
+// FHIR R4 interoperability. The export builds a `collection` Bundle from the
+// workspace: the patient, the configured practitioner, one Encounter per .med
+// file and the conditions, prescriptions, referrals, exam requests and exam
+// results recorded in it. Before writing, the bundle is checked against the
+// schema subset bundled in assets/fhir/bundle-schema.json.
+
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::site::{self, Encounter};
+use crate::prescription::recipe_lines;
+use crate::problems::{self, Problem, ProblemStatus};
+use crate::profile::UserInfo;
+use crate::{cid10, medfile};
+
+const BUNDLE_SCHEMA: &str = include_str!("../assets/fhir/bundle-schema.json");
+const CPF_SYSTEM: &str = "https://saude.gov.br/sid/cpf";
+const CRM_SYSTEM: &str = "https://saude.gov.br/sid/crm";
+const ACT_CODE_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/v3-ActCode";
+const CONDITION_CLINICAL_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/condition-clinical";
+const CONDITION_VER_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/condition-ver-status";
+const CONDITION_CATEGORY_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/condition-category";
+const OBSERVATION_CATEGORY_SYSTEM: &str = "http://terminology.hl7.org/CodeSystem/observation-category";
+const SNOMED_SYSTEM: &str = "http://snomed.info/sct";
+const CID10_SYSTEM: &str = "http://www.saude.gov.br/fhir/r4/CodeSystem/BRCID10";
+
+fn resource_uuid(kind: &str, key: &str) -> String {
+    // Name-based UUID, so exporting the same workspace twice yields the same ids
+    let digest = Sha256::digest(format!("medfiles/{}/{}", kind, key).as_bytes());
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    bytes[6] = (bytes[6] & 0x0f) | 0x50;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+}
+
+fn reference(id: &str) -> Value {
+    json!({ "reference": format!("urn:uuid:{}", id) })
+}
+
+fn text_concept(text: &str) -> Value {
+    json!({ "text": text })
+}
+
+fn coded_concept(system: &str, code: &str, display: &str) -> Value {
+    json!({ "coding": [{ "system": system, "code": code, "display": display }], "text": display })
+}
+
+fn cid_concept(code: &str, text: &str) -> Value {
+    let display = cid10::description(code).unwrap_or_else(|| text.to_string());
+    json!({ "coding": [{ "system": CID10_SYSTEM, "code": code, "display": display }], "text": text })
+}
+
+fn entry(resource: Value) -> Value {
+    let id = resource["id"].as_str().unwrap_or_default().to_string();
+    json!({ "fullUrl": format!("urn:uuid:{}", id), "resource": resource })
+}
+
+fn encounter_date(encounter: &Encounter) -> String {
+    chrono::DateTime::<chrono::Local>::from(encounter.modified).format("%Y-%m-%d").to_string()
+}
+
+pub fn exam_date(date: &str) -> Option<String> {
+    // .med exam dates are DD/MM/AAAA, MM/AAAA or AAAA; FHIR wants the reverse order
+    let parts: Vec<&str> = date.trim().split('/').collect();
+    if !parts.iter().all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit())) {
+        return None;
+    }
+    match parts.as_slice() {
+        [day, month, year] if year.len() == 4 => Some(format!("{}-{:0>2}-{:0>2}", year, month, day)),
+        [month, year] if year.len() == 4 => Some(format!("{}-{:0>2}", year, month)),
+        [year] if year.len() == 4 => Some(year.to_string()),
+        _ => None,
+    }
+}
+
+fn patient_resource(patient_name: &str) -> Value {
+    json!({
+        "resourceType": "Patient",
+        "id": resource_uuid("Patient", patient_name),
+        "name": [{ "text": patient_name }],
+    })
+}
+
+fn practitioner_resource(user: &UserInfo) -> Value {
+    let mut resource = json!({
+        "resourceType": "Practitioner",
+        "id": resource_uuid("Practitioner", &format!("{}{}", user.crm_uf, user.crm)),
+        "name": [{ "text": user.name }],
+    });
+    if !user.cpf.is_empty() {
+        resource["identifier"] = json!([{ "system": CPF_SYSTEM, "value": user.cpf }]);
+    }
+    let mut telecom = vec![];
+    if !user.phone.is_empty() {
+        telecom.push(json!({ "system": "phone", "value": user.phone }));
+    }
+    if !user.email.is_empty() {
+        telecom.push(json!({ "system": "email", "value": user.email }));
+    }
+    if !telecom.is_empty() {
+        resource["telecom"] = Value::Array(telecom);
+    }
+    if !user.crm.is_empty() {
+        resource["qualification"] = json!([{
+            "identifier": [{ "system": CRM_SYSTEM, "value": format!("{}/{}", user.crm, user.crm_uf) }],
+            "code": text_concept(if user.specialty.is_empty() { "CRM" } else { &user.specialty }),
+        }]);
+    }
+    resource
+}
+
+fn condition_status(problem: Option<&Problem>, suspected: bool) -> (Value, Value) {
+    // The status comes from the problem list, so a diagnosis closed later is exported as such;
+    // a ruled-out diagnosis is a verification status in FHIR, with the condition inactive
+    let (verification, verification_display) = if suspected { ("provisional", "Provisional") } else { ("confirmed", "Confirmed") };
+    let (clinical, clinical_display, verification, verification_display) = match problem.map(|p| p.status) {
+        Some(ProblemStatus::RuledOut) => ("inactive", "Inactive", "refuted", "Refuted"),
+        Some(ProblemStatus::Resolved) => ("resolved", "Resolved", verification, verification_display),
+        _ => ("active", "Active", verification, verification_display),
+    };
+    (
+        coded_concept(CONDITION_CLINICAL_SYSTEM, clinical, clinical_display),
+        coded_concept(CONDITION_VER_SYSTEM, verification, verification_display),
+    )
+}
+
+fn encounter_resources(encounter: &Encounter, problems: &[Problem], patient_id: &str, practitioner_id: &str) -> Vec<Value> {
+    let date = encounter_date(encounter);
+    let encounter_id = resource_uuid("Encounter", &encounter.name);
+    let mut resources = vec![json!({
+        "resourceType": "Encounter",
+        "id": encounter_id,
+        "status": "finished",
+        "class": { "system": ACT_CODE_SYSTEM, "code": "AMB", "display": "ambulatory" },
+        "subject": reference(patient_id),
+        "participant": [{ "individual": reference(practitioner_id) }],
+        "period": { "start": date },
+    })];
+
+    let diagnoses = medfile::diagnosis_items(&encounter.doc.section_lines("HIPOTESE DIAGNOSTICA"));
+    for (index, diagnosis) in diagnoses.iter().enumerate() {
+        let (clinical_status, verification_status) = condition_status(problems::find_problem(problems, diagnosis), diagnosis.suspected);
+        let code = match diagnosis.cid_code() {
+            Some(code) => cid_concept(&code, &diagnosis.name),
+            None => text_concept(&diagnosis.name),
+        };
+        resources.push(json!({
+            "resourceType": "Condition",
+            "id": resource_uuid("Condition", &format!("{}#{}", encounter.name, index)),
+            "clinicalStatus": clinical_status,
+            "verificationStatus": verification_status,
+            "category": [coded_concept(CONDITION_CATEGORY_SYSTEM, "encounter-diagnosis", "Encounter Diagnosis")],
+            "code": code,
+            "subject": reference(patient_id),
+            "encounter": reference(&encounter_id),
+            "recordedDate": date,
+        }));
+    }
+
+    for (index, item) in site::encounter_prescriptions(encounter).iter().enumerate() {
+        let (line1, line2) = recipe_lines(item);
+        let status = if item.command == "SUSPEND" { "stopped" } else { "active" };
+        let mut request = json!({
+            "resourceType": "MedicationRequest",
+            "id": resource_uuid("MedicationRequest", &format!("{}#{}", encounter.name, index)),
+            "status": status,
+            "intent": "order",
+            "medicationCodeableConcept": text_concept(&line1),
+            "subject": reference(patient_id),
+            "encounter": reference(&encounter_id),
+            "authoredOn": date,
+            "requester": reference(practitioner_id),
+            "dosageInstruction": [{ "text": line2 }],
+        });
+        if !item.objective.is_empty() {
+            request["note"] = json!([{ "text": format!("Objetivo: {}", item.objective) }]);
+        }
+        resources.push(request);
+    }
+
+    let reasons: Vec<Value> = diagnoses
+        .iter()
+        .filter_map(|diagnosis| Some(cid_concept(&diagnosis.cid_code()?, &diagnosis.name)))
+        .collect();
+    let conduct = encounter.doc.section("CONDUTA").map(|s| s.directives()).unwrap_or_default();
+    for (index, directive) in conduct.iter().enumerate() {
+        let category = match directive.keyword.as_str() {
+            "ENCAMINHO" => coded_concept(SNOMED_SYSTEM, "3457005", "Patient referral"),
+            "SOLICITO" => coded_concept(SNOMED_SYSTEM, "108252007", "Laboratory procedure"),
+            _ => continue,
+        };
+        let mut request = json!({
+            "resourceType": "ServiceRequest",
+            "id": resource_uuid("ServiceRequest", &format!("{}#{}", encounter.name, index)),
+            "status": "active",
+            "intent": "order",
+            "category": [category],
+            "code": text_concept(&directive.content),
+            "subject": reference(patient_id),
+            "encounter": reference(&encounter_id),
+            "authoredOn": date,
+            "requester": reference(practitioner_id),
+        });
+        if directive.keyword == "SOLICITO" && !reasons.is_empty() {
+            request["reasonCode"] = Value::Array(reasons.clone());
+        }
+        resources.push(request);
+    }
+
+    let exams = encounter
+        .doc
+        .sections
+        .iter()
+        .flat_map(|section| section.directives())
+        .filter(|directive| directive.keyword == "EX");
+    for (index, directive) in exams.enumerate() {
+        let exam = medfile::parse_exam(&directive.content);
+        let mut observation = json!({
+            "resourceType": "Observation",
+            "id": resource_uuid("Observation", &format!("{}#{}", encounter.name, index)),
+            "status": "final",
+            "category": [coded_concept(OBSERVATION_CATEGORY_SYSTEM, "exam", "Exam")],
+            "code": text_concept(&exam.name),
+            "subject": reference(patient_id),
+            "encounter": reference(&encounter_id),
+            "valueString": exam.result,
+        });
+        if let Some(effective) = exam_date(&exam.date) {
+            observation["effectiveDateTime"] = json!(effective);
+        }
+        resources.push(observation);
+    }
+    resources
+}
+
+fn medication_statements(medications: &HashMap<String, HashMap<String, String>>, patient_id: &str, practitioner_id: &str) -> Vec<Value> {
+    let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+    site::state_prescriptions(medications)
+        .iter()
+        .map(|item| {
+            let (line1, line2) = recipe_lines(item);
+            json!({
+                "resourceType": "MedicationStatement",
+                "id": resource_uuid("MedicationStatement", &item.medication),
+                "status": "active",
+                "medicationCodeableConcept": text_concept(&line1),
+                "subject": reference(patient_id),
+                "dateAsserted": today,
+                "informationSource": reference(practitioner_id),
+                "dosage": [{ "text": line2 }],
+            })
+        })
+        .collect()
+}
+
+pub fn build_bundle(
+    patient_name: &str,
+    user: &UserInfo,
+    encounters: &[Encounter],
+    medications: &HashMap<String, HashMap<String, String>>,
+) -> Value {
+    let patient = patient_resource(patient_name);
+    let practitioner = practitioner_resource(user);
+    let patient_id = patient["id"].as_str().unwrap_or_default().to_string();
+    let practitioner_id = practitioner["id"].as_str().unwrap_or_default().to_string();
+
+    let mut entries = vec![entry(patient), entry(practitioner)];
+    let problems = problems::build_problem_list(encounters);
+    for encounter in encounters {
+        entries.extend(encounter_resources(encounter, &problems, &patient_id, &practitioner_id).into_iter().map(entry));
+    }
+    entries.extend(medication_statements(medications, &patient_id, &practitioner_id).into_iter().map(entry));
+
+    json!({
+        "resourceType": "Bundle",
+        "id": resource_uuid("Bundle", patient_name),
+        "type": "collection",
+        "timestamp": chrono::Local::now().to_rfc3339(),
+        "entry": entries,
+    })
+}
+
+pub fn validate_bundle(bundle: &Value) -> Vec<String> {
+    let schema: Value = match serde_json::from_str(BUNDLE_SCHEMA) {
+        Ok(schema) => schema,
+        Err(e) => return vec![format!("Esquema FHIR embutido inválido: {}", e)],
+    };
+    let mut errors = vec![];
+    validate_node(&schema, &schema, bundle, "$", &mut errors);
+    errors
+}
+
+fn json_type_matches(expected: &str, value: &Value) -> bool {
+    match expected {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        _ => true,
+    }
+}
+
+fn validate_node(root: &Value, schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+    // Supports the keywords used by the bundled subset: $ref, type, const, enum,
+    // required, properties, items and oneOf
+    if let Some(target) = schema.get("$ref").and_then(Value::as_str) {
+        let name = target.trim_start_matches("#/definitions/");
+        match root.get("definitions").and_then(|d| d.get(name)) {
+            Some(definition) => validate_node(root, definition, value, path, errors),
+            None => errors.push(format!("{}: definição {} ausente no esquema", path, target)),
+        }
+        return;
+    }
+    if let Some(expected) = schema.get("type").and_then(Value::as_str)
+        && !json_type_matches(expected, value)
+    {
+        errors.push(format!("{}: esperado {}", path, expected));
+        return;
+    }
+    if let Some(constant) = schema.get("const")
+        && constant != value
+    {
+        errors.push(format!("{}: esperado {}", path, constant));
+    }
+    if let Some(options) = schema.get("enum").and_then(Value::as_array)
+        && !options.contains(value)
+    {
+        errors.push(format!("{}: valor {} fora do conjunto permitido", path, value));
+    }
+    if let Some(required) = schema.get("required").and_then(Value::as_array) {
+        for field in required.iter().filter_map(Value::as_str) {
+            if value.get(field).is_none() {
+                errors.push(format!("{}: campo obrigatório {} ausente", path, field));
+            }
+        }
+    }
+    if let (Some(properties), Some(object)) = (schema.get("properties").and_then(Value::as_object), value.as_object()) {
+        for (field, field_schema) in properties {
+            if let Some(field_value) = object.get(field) {
+                validate_node(root, field_schema, field_value, &format!("{}.{}", path, field), errors);
+            }
+        }
+    }
+    if let (Some(items), Some(array)) = (schema.get("items"), value.as_array()) {
+        for (index, item) in array.iter().enumerate() {
+            validate_node(root, items, item, &format!("{}[{}]", path, index), errors);
+        }
+    }
+    if let Some(options) = schema.get("oneOf").and_then(Value::as_array) {
+        let mut attempts: Vec<Vec<String>> = options
+            .iter()
+            .map(|option| {
+                let mut option_errors = vec![];
+                validate_node(root, option, value, path, &mut option_errors);
+                option_errors
+            })
+            .collect();
+        let matches = attempts.iter().filter(|e| e.is_empty()).count();
+        if matches == 0 {
+            // Report the errors of the alternative whose resourceType matched, i.e. the resource the value meant to be
+            let discriminator = format!("{}.resourceType:", path);
+            attempts.sort_by_key(|e| (e.iter().any(|error| error.starts_with(&discriminator)), e.len()));
+            errors.extend(attempts.into_iter().next().unwrap_or_default());
+        } else if matches > 1 {
+            errors.push(format!("{}: corresponde a mais de uma alternativa", path));
+        }
+    }
+}
+
+pub struct ImportedRecord {
+    pub patient_name: Option<String>,
+    pub content: String,
+    pub mapped: usize,
+    pub notes: usize,
+}
+
+fn concept_text(concept: &Value) -> Option<String> {
+    if let Some(text) = concept.get("text").and_then(Value::as_str) {
+        return Some(text.to_string());
+    }
+    let coding = concept.get("coding")?.as_array()?.first()?;
+    coding
+        .get("display")
+        .or_else(|| coding.get("code"))
+        .and_then(Value::as_str)
+        .map(str::to_string)
+}
+
+fn med_text(text: &str) -> String {
+    // `;` ends a directive and brackets delimit exam dates, so neither may leak into imported text
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace(';', ",")
+        .replace('[', "(")
+        .replace(']', ")")
+        .to_uppercase()
+}
+
+fn med_date(date: &str) -> String {
+    // Inverse of exam_date: 2025-07-03 -> 03/07/2025, 2025-07 -> 07/2025
+    let date = date.get(..10).unwrap_or(date);
+    let parts: Vec<&str> = date.split('-').collect();
+    match parts.as_slice() {
+        [year, month, day] => format!("{}/{}/{}", day, month, year),
+        [year, month] => format!("{}/{}", month, year),
+        _ => date.to_string(),
+    }
+}
+
+fn observation_value(resource: &Value) -> Option<String> {
+    if let Some(quantity) = resource.get("valueQuantity") {
+        let value = quantity.get("value").map(|v| v.to_string()).unwrap_or_default();
+        let unit = quantity.get("unit").or_else(|| quantity.get("code")).and_then(Value::as_str).unwrap_or_default();
+        return Some(format!("{} {}", value, unit).trim().to_string());
+    }
+    if let Some(text) = resource.get("valueString").and_then(Value::as_str) {
+        return Some(text.to_string());
+    }
+    if let Some(concept) = resource.get("valueCodeableConcept") {
+        return concept_text(concept);
+    }
+    if let Some(flag) = resource.get("valueBoolean").and_then(Value::as_bool) {
+        return Some(if flag { "SIM" } else { "NÃO" }.to_string());
+    }
+    let components: Vec<String> = resource
+        .get("component")
+        .and_then(Value::as_array)
+        .map(|components| {
+            components
+                .iter()
+                .filter_map(|c| Some(format!("{} {}", concept_text(c.get("code")?)?, observation_value(c)?)))
+                .collect()
+        })
+        .unwrap_or_default();
+    if components.is_empty() { None } else { Some(components.join(", ")) }
+}
+
+fn is_cid10_system(system: &str) -> bool {
+    let system = system.to_lowercase();
+    system.contains("cid10") || system.contains("icd-10") || system.contains("icd10")
+}
+
+fn resource_summary(resource: &Value) -> String {
+    let resource_type = resource.get("resourceType").and_then(Value::as_str).unwrap_or("?");
+    let label = ["code", "medicationCodeableConcept", "vaccineCode", "type"]
+        .iter()
+        .find_map(|field| resource.get(*field).and_then(concept_text))
+        .or_else(|| resource.get("id").and_then(Value::as_str).map(|id| format!("id {}", id)));
+    match label {
+        Some(label) => format!("{}: {}", resource_type, label),
+        None => resource_type.to_string(),
+    }
+}
+
+pub fn bundle_to_med(bundle: &Value, source: &str) -> Result<ImportedRecord, String> {
+    if bundle.get("resourceType").and_then(Value::as_str) != Some("Bundle") {
+        return Err("O arquivo não é um Bundle FHIR (resourceType diferente de Bundle).".to_string());
+    }
+    let mut patient_name = None;
+    let mut anamnesis: Vec<String> = vec![];
+    let mut notes: Vec<String> = vec![];
+
+    let entries = bundle.get("entry").and_then(Value::as_array).cloned().unwrap_or_default();
+    for resource in entries.iter().filter_map(|entry| entry.get("resource")) {
+        let resource_type = resource.get("resourceType").and_then(Value::as_str).unwrap_or_default();
+        match resource_type {
+            "Patient" => {
+                let name = resource
+                    .get("name")
+                    .and_then(Value::as_array)
+                    .and_then(|names| names.first())
+                    .and_then(|name| {
+                        name.get("text").and_then(Value::as_str).map(str::to_string).or_else(|| {
+                            let given: Vec<&str> = name.get("given")?.as_array()?.iter().filter_map(Value::as_str).collect();
+                            let family = name.get("family").and_then(Value::as_str).unwrap_or_default();
+                            Some(format!("{} {}", given.join(" "), family).trim().to_string())
+                        })
+                    });
+                let mut details = vec![];
+                if let Some(birth) = resource.get("birthDate").and_then(Value::as_str) {
+                    details.push(format!("NASCIMENTO {}", med_date(birth)));
+                }
+                if let Some(gender) = resource.get("gender").and_then(Value::as_str) {
+                    details.push(format!("SEXO {}", gender));
+                }
+                for identifier in resource.get("identifier").and_then(Value::as_array).into_iter().flatten() {
+                    let system = identifier.get("system").and_then(Value::as_str).unwrap_or("identificador");
+                    if let Some(value) = identifier.get("value").and_then(Value::as_str) {
+                        details.push(format!("{} {}", system, value));
+                    }
+                }
+                if let Some(name) = &name {
+                    notes.push(med_text(&format!("PACIENTE: {}", name)));
+                }
+                if !details.is_empty() {
+                    notes.push(med_text(&details.join(", ")));
+                }
+                patient_name = patient_name.or(name);
+            }
+            "Condition" => match resource.get("code").and_then(concept_text) {
+                Some(name) => {
+                    let clinical = resource.get("clinicalStatus").and_then(concept_text).unwrap_or_default().to_lowercase();
+                    let verification = resource.get("verificationStatus").and_then(concept_text).unwrap_or_default().to_lowercase();
+                    let suffix = if verification == "refuted" {
+                        " (DESCARTADO)"
+                    } else if clinical == "resolved" || clinical == "inactive" || clinical == "remission" {
+                        " (RESOLVIDO)"
+                    } else {
+                        ""
+                    };
+                    let code = resource
+                        .get("code")
+                        .and_then(|c| c.get("coding"))
+                        .and_then(Value::as_array)
+                        .into_iter()
+                        .flatten()
+                        .filter(|coding| coding.get("system").and_then(Value::as_str).is_some_and(is_cid10_system))
+                        .find_map(|coding| cid10::normalize_code(coding.get("code")?.as_str()?))
+                        .map(|code| format!(" {{{}}}", code))
+                        .unwrap_or_default();
+                    anamnesis.push(format!("!HPP {}{}{};", med_text(&name), code, suffix));
+                }
+                None => notes.push(format!("Sem código: {}", resource_summary(resource))),
+            },
+            "MedicationStatement" => {
+                let medication = resource.get("medicationCodeableConcept").and_then(concept_text);
+                let status = resource.get("status").and_then(Value::as_str).unwrap_or("active");
+                match medication {
+                    Some(name) if status == "active" || status == "intended" => {
+                        let dosage = resource
+                            .get("dosage")
+                            .and_then(Value::as_array)
+                            .and_then(|d| d.first())
+                            .and_then(|d| d.get("text"))
+                            .and_then(Value::as_str)
+                            .map(|text| format!(" {}", med_text(text)))
+                            .unwrap_or_default();
+                        anamnesis.push(format!("!MED {}{};", med_text(&name), dosage));
+                    }
+                    Some(name) => notes.push(med_text(&format!("MEDICAÇÃO {} ({})", name, status))),
+                    None => notes.push(format!("Sem medicação codificada: {}", resource_summary(resource))),
+                }
+            }
+            "AllergyIntolerance" => match resource.get("code").and_then(concept_text) {
+                Some(substance) => {
+                    let reactions: Vec<String> = resource
+                        .get("reaction")
+                        .and_then(Value::as_array)
+                        .into_iter()
+                        .flatten()
+                        .flat_map(|r| r.get("manifestation").and_then(Value::as_array).cloned().unwrap_or_default())
+                        .filter_map(|m| concept_text(&m))
+                        .collect();
+                    let detail = if reactions.is_empty() { String::new() } else { format!(" ({})", med_text(&reactions.join(", "))) };
+                    anamnesis.push(format!("!ALERGIA {}{};", med_text(&substance), detail));
+                }
+                None => notes.push(format!("Sem substância: {}", resource_summary(resource))),
+            },
+            "Observation" => {
+                let name = resource.get("code").and_then(concept_text);
+                match (name, observation_value(resource)) {
+                    (Some(name), Some(value)) => {
+                        let date = resource
+                            .get("effectiveDateTime")
+                            .or_else(|| resource.get("effectivePeriod").and_then(|p| p.get("start")))
+                            .or_else(|| resource.get("issued"))
+                            .and_then(Value::as_str)
+                            .map(|d| format!("[{}]", med_date(d)))
+                            .unwrap_or_default();
+                        anamnesis.push(format!("!EX @{}{}: {};", med_text(&name).replace(' ', "_"), date, med_text(&value)));
+                    }
+                    _ => notes.push(format!("Sem resultado: {}", resource_summary(resource))),
+                }
+            }
+            _ => notes.push(format!("Recurso não importado: {}", resource_summary(resource))),
+        }
+    }
+
+    let mapped = anamnesis.len();
+    let mut content = format!(
+        "[ANAMNESE]\nREGISTRO IMPORTADO DO BUNDLE FHIR {} EM {}.\n",
+        med_text(source),
+        chrono::Local::now().format("%d/%m/%Y")
+    );
+    for line in &anamnesis {
+        content.push_str(line);
+        content.push('\n');
+    }
+    if !notes.is_empty() {
+        content.push_str("\n[NOTAS DE IMPORTACAO]\n");
+        for line in &notes {
+            content.push_str(&line.replace(';', ","));
+            content.push('\n');
+        }
+    }
+    Ok(ImportedRecord { patient_name, content, mapped, notes: notes.len() })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::medfile::MedDocument;
+
+    const CONTENT: &str = "[HIPOTESE DIAGNOSTICA]\nENXAQUECA {G43.9};\n[CONDUTA]\n!PRESCREVO TOPIRAMATO 25MG [1 COMPRIMIDO] NOITE;\n!SOLICITO RESSONANCIA DE CRANIO;\n";
+
+    fn bundle() -> Value {
+        let encounter = Encounter {
+            modified: SystemTime::UNIX_EPOCH,
+            name: "ANA LIMA 2025-01-10".to_string(),
+            slug: site::page_slug("ANA LIMA 2025-01-10"),
+            date_str: "2025-01-10".to_string(),
+            doc: MedDocument::parse(CONTENT),
+        };
+        let user = UserInfo { name: "Dra. Teste".to_string(), crm: "52123456".to_string(), crm_uf: "RJ".to_string(), ..Default::default() };
+        let (medications, _) = crate::prescription::replay(std::slice::from_ref(&encounter));
+        build_bundle("ANA LIMA", &user, &[encounter], &medications)
+    }
+
+    fn resources(bundle: &Value, kind: &str) -> Vec<Value> {
+        bundle["entry"].as_array().unwrap().iter().map(|e| e["resource"].clone()).filter(|r| r["resourceType"] == kind).collect()
+    }
+
+    #[test]
+    fn bundle_is_built_from_the_encounter_and_validates() {
+        let bundle = bundle();
+        assert_eq!(validate_bundle(&bundle), Vec::<String>::new());
+        assert_eq!(resources(&bundle, "Patient")[0]["name"][0]["text"], "ANA LIMA");
+        assert_eq!(resources(&bundle, "Encounter").len(), 1);
+
+        let condition = &resources(&bundle, "Condition")[0];
+        assert_eq!(condition["code"]["coding"][0]["system"], CID10_SYSTEM);
+        assert_eq!(condition["code"]["coding"][0]["code"], "G43.9");
+        assert_eq!(condition["clinicalStatus"]["coding"][0]["code"], "active");
+
+        let request = &resources(&bundle, "MedicationRequest")[0];
+        assert!(request["medicationCodeableConcept"]["text"].as_str().unwrap().contains("TOPIRAMATO"));
+        assert_eq!(resources(&bundle, "MedicationStatement").len(), 1);
+        assert_eq!(resources(&bundle, "ServiceRequest")[0]["reasonCode"][0]["coding"][0]["code"], "G43.9");
+    }
+
+    #[test]
+    fn validation_points_at_the_broken_field() {
+        let mut bundle = bundle();
+        bundle["type"] = json!("document-ish");
+        bundle["entry"][0]["resource"].as_object_mut().unwrap().remove("resourceType");
+        let errors = validate_bundle(&bundle);
+        assert!(errors.iter().any(|e| e.starts_with("$.type:")), "{:?}", errors);
+        assert!(errors.iter().any(|e| e.starts_with("$.entry[0].resource")), "{:?}", errors);
+        assert!(!validate_bundle(&json!({ "resourceType": "Patient" })).is_empty());
+    }
+
+    #[test]
+    fn exam_dates_are_reversed_for_fhir() {
+        assert_eq!(exam_date("05/03/2025").as_deref(), Some("2025-03-05"));
+        assert_eq!(exam_date("3/2025").as_deref(), Some("2025-03"));
+        assert_eq!(exam_date("2025").as_deref(), Some("2025"));
+        assert_eq!(exam_date("março de 2025"), None);
+        assert_eq!(exam_date("05/03/25"), None);
+    }
+
+    #[test]
+    fn exported_bundle_imports_back_into_a_med_file() {
+        let record = bundle_to_med(&bundle(), "ana.json").unwrap();
+        assert_eq!(record.patient_name.as_deref(), Some("ANA LIMA"));
+        assert!(record.content.contains("!HPP ENXAQUECA {G43.9};"), "{}", record.content);
+        assert!(record.content.contains("!MED TOPIRAMATO 25MG"), "{}", record.content);
+        assert!(record.mapped > 0);
+        assert!(MedDocument::parse(&record.content).section("ANAMNESE").is_some());
+
+        let error = bundle_to_med(&json!({ "resourceType": "Patient" }), "x.json").err().unwrap();
+        assert!(error.contains("não é um Bundle"));
+    }
+}
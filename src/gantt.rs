@@ -0,0 +1,344 @@
+// Medication exposure over time (Gantt view). Each medication is a bar from
+// its first prescription to its suspension, or to today while it is still in
+// use, split into segments shaded by the daily dose. The bars are rebuilt from
+// history.json: starting from the current state in medications.json, the
+// recorded field changes are undone from the latest to the oldest, which gives
+// the prescription in force between every pair of changes.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{NaiveDate, NaiveDateTime};
+
+use crate::html::html_escape;
+use crate::prescription::ChangeRecord;
+use crate::{charts, doses};
+
+const WIDTH: f64 = 700.0;
+const LABEL: f64 = 160.0;
+const RIGHT: f64 = 20.0;
+const TOP: f64 = 10.0;
+const ROW: f64 = 26.0;
+const BOTTOM: f64 = 30.0;
+const COLOR: &str = "#2980b9";
+// Terminal columns used by the bars
+const COLUMNS: usize = 60;
+const NAME_WIDTH: usize = 22;
+
+pub struct Segment {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+    pub units_per_day: f64,
+    pub mg_per_day: Option<f64>,
+}
+
+pub struct Bar {
+    pub medication: String,
+    pub segments: Vec<Segment>,
+    pub suspended_on: Option<NaiveDate>,
+}
+
+impl Bar {
+    pub fn in_mg(&self) -> bool {
+        self.segments.iter().all(|s| s.mg_per_day.is_some())
+    }
+
+    pub fn unit(&self) -> &'static str {
+        if self.in_mg() { "mg/dia" } else { "unid./dia" }
+    }
+
+    pub fn value(&self, segment: &Segment) -> f64 {
+        if self.in_mg() { segment.mg_per_day.unwrap_or(0.0) } else { segment.units_per_day }
+    }
+
+    fn level(&self, segment: &Segment) -> f64 {
+        // Shades are relative to the highest dose of the same medication
+        let max = self.segments.iter().map(|s| self.value(s)).fold(0.0, f64::max);
+        if max > 0.0 { self.value(segment) / max } else { 1.0 }
+    }
+
+    fn dose_text(&self, segment: &Segment) -> String {
+        format!("{} {}", charts::format_value(self.value(segment)), self.unit())
+    }
+
+    fn segment_at(&self, date: NaiveDate) -> Option<&Segment> {
+        self.segments
+            .iter()
+            .find(|s| (s.start <= date && date < s.end) || (s.start == s.end && s.start == date))
+    }
+}
+
+fn record_date(time: &str) -> Option<NaiveDate> {
+    NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S").ok().map(|t| t.date())
+}
+
+pub fn build_bars(history: &[ChangeRecord], medications: &HashMap<String, HashMap<String, String>>, today: NaiveDate) -> Vec<Bar> {
+    let mut records: Vec<&ChangeRecord> = history.iter().collect();
+    records.sort();
+    let mut by_medication: BTreeMap<&str, Vec<(&str, Vec<&ChangeRecord>)>> = BTreeMap::new();
+    for record in records {
+        let groups = by_medication.entry(record.0.as_str()).or_default();
+        match groups.last_mut() {
+            Some((time, group)) if *time == record.1 => group.push(record),
+            _ => groups.push((record.1.as_str(), vec![record])),
+        }
+    }
+
+    let mut bars = vec![];
+    for (medication, groups) in by_medication {
+        // State in force after each group of changes, rebuilt from the latest backwards
+        let mut state = medications.get(medication).cloned().unwrap_or_default();
+        let mut states = vec![];
+        for (_, group) in groups.iter().rev() {
+            states.push(state.clone());
+            // Only a first prescription starts from nothing; prescribing again records the fields it replaced
+            if group.iter().any(|r| r.2 == "initial") {
+                state = HashMap::new();
+            } else {
+                for (_, _, field, old, _, _) in group {
+                    state.insert(field.clone(), old.clone());
+                }
+            }
+        }
+        states.reverse();
+
+        let mut segments = vec![];
+        let mut suspended_on = None;
+        for (index, ((time, group), state)) in groups.iter().zip(&states).enumerate() {
+            let Some(start) = record_date(time) else {
+                continue;
+            };
+            if group.iter().any(|r| r.5 == "SUSPEND") {
+                suspended_on = Some(start);
+                continue;
+            }
+            suspended_on = None;
+            let end = groups.get(index + 1).and_then(|(t, _)| record_date(t)).unwrap_or(today).max(start);
+            let field = |name: &str| state.get(name).map(String::as_str).unwrap_or("");
+            let (units_per_day, mg_per_day) = doses::daily_dose(field("dosage"), field("dosage_observations"), field("posologia"));
+            segments.push(Segment { start, end, units_per_day, mg_per_day });
+        }
+        if !segments.is_empty() {
+            bars.push(Bar { medication: medication.to_string(), segments, suspended_on });
+        }
+    }
+    bars
+}
+
+pub fn bars_from_doses(histories: &[doses::DoseHistory], today: NaiveDate) -> Vec<Bar> {
+    // Same bars straight from the encounters, for pages that do not read history.json
+    let mut bars = vec![];
+    for history in histories {
+        let mut segments = vec![];
+        let mut suspended_on = None;
+        for (index, event) in history.events.iter().enumerate() {
+            let next = history.events.get(index + 1).map(|e| e.date);
+            if event.suspended {
+                suspended_on = Some(event.date);
+                continue;
+            }
+            suspended_on = None;
+            // Several changes in the same encounter: the last one is in force
+            if next == Some(event.date) {
+                continue;
+            }
+            let end = next.unwrap_or(today).max(event.date);
+            segments.push(Segment { start: event.date, end, units_per_day: event.units_per_day, mg_per_day: event.mg_per_day });
+        }
+        if !segments.is_empty() {
+            bars.push(Bar { medication: history.medication.clone(), segments, suspended_on });
+        }
+    }
+    bars
+}
+
+fn date_range(bars: &[Bar], encounter_dates: &[NaiveDate], today: NaiveDate) -> (NaiveDate, NaiveDate) {
+    let start = bars
+        .iter()
+        .flat_map(|b| b.segments.iter().map(|s| s.start))
+        .chain(encounter_dates.iter().copied())
+        .min()
+        .unwrap_or(today);
+    // A single day has no width: show at least a month
+    (start.min(today - chrono::Duration::days(30)), today)
+}
+
+fn shade(level: f64) -> char {
+    match level {
+        l if l <= 0.25 => '░',
+        l if l <= 0.5 => '▒',
+        l if l <= 0.75 => '▓',
+        _ => '█',
+    }
+}
+
+pub fn print_gantt(bars: &[Bar], encounter_dates: &[NaiveDate], today: NaiveDate) {
+    if bars.is_empty() {
+        println!("Nenhuma medicação no histórico de prescrições.");
+        return;
+    }
+    let (start, end) = date_range(bars, encounter_dates, today);
+    let span = (end - start).num_days().max(1);
+    let column = |date: NaiveDate| ((date - start).num_days() * (COLUMNS as i64 - 1) / span) as usize;
+    let date_at = |col: usize| start + chrono::Duration::days(col as i64 * span / (COLUMNS as i64 - 1));
+    let gridlines: Vec<usize> = encounter_dates.iter().map(|d| column(*d)).collect();
+
+    println!("Exposição às medicações:");
+    for bar in bars {
+        let mut row: Vec<char> = (0..COLUMNS).map(|col| if gridlines.contains(&col) { '┆' } else { ' ' }).collect();
+        for (col, cell) in row.iter_mut().enumerate() {
+            if let Some(segment) = bar.segment_at(date_at(col)) {
+                *cell = shade(bar.level(segment));
+            }
+        }
+        if let Some(date) = bar.suspended_on {
+            row[column(date)] = '✕';
+        }
+        let status = match (bar.suspended_on, bar.segments.last()) {
+            (Some(date), _) => format!("suspenso em {}", date.format("%d/%m/%Y")),
+            (None, Some(segment)) => bar.dose_text(segment),
+            (None, None) => String::new(),
+        };
+        let name: String = bar.medication.chars().take(NAME_WIDTH - 1).collect();
+        println!("{:<width$}{} {}", name, row.iter().collect::<String>(), status, width = NAME_WIDTH);
+    }
+    let axis: String = (0..COLUMNS).map(|col| if gridlines.contains(&col) { '┬' } else { '─' }).collect();
+    println!("{:<width$}{}", "", axis, width = NAME_WIDTH);
+    let first = start.format("%d/%m/%Y").to_string();
+    let last = format!("hoje {}", end.format("%d/%m/%Y"));
+    println!(
+        "{:<width$}{}{:>fill$}",
+        "",
+        first,
+        last,
+        width = NAME_WIDTH,
+        fill = COLUMNS.saturating_sub(first.chars().count())
+    );
+    println!("Tons: ░ menor dose ... █ maior dose de cada medicação; ┆ atendimentos; ✕ suspensão.");
+}
+
+pub fn generate_gantt_html(bars: &[Bar], encounter_dates: &[NaiveDate], today: NaiveDate) -> String {
+    if bars.is_empty() {
+        return "<p>Nenhuma medicação no histórico de prescrições.</p>".to_string();
+    }
+    let (start, end) = date_range(bars, encounter_dates, today);
+    let span = (end - start).num_days().max(1) as f64;
+    let plot_width = WIDTH - LABEL - RIGHT;
+    let x = |date: NaiveDate| LABEL + plot_width * (date - start).num_days() as f64 / span;
+    let bottom = TOP + ROW * bars.len() as f64;
+    let height = bottom + BOTTOM;
+
+    let mut svg = format!(
+        "<svg class=\"chart gantt\" xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {} {}\" role=\"img\">",
+        WIDTH, height
+    );
+    for date in encounter_dates {
+        svg.push_str(&format!(
+            "<line x1=\"{0:.1}\" y1=\"{1}\" x2=\"{0:.1}\" y2=\"{2}\" stroke=\"#bdc3c7\" stroke-dasharray=\"3,3\"><title>Atendimento em {3}</title></line>",
+            x(*date),
+            TOP,
+            bottom,
+            date.format("%d/%m/%Y")
+        ));
+    }
+    svg.push_str(&format!(
+        "<line x1=\"{0}\" y1=\"{1}\" x2=\"{2}\" y2=\"{1}\" stroke=\"#bdc3c7\"/>",
+        LABEL,
+        bottom,
+        WIDTH - RIGHT
+    ));
+    for (index, bar) in bars.iter().enumerate() {
+        let y = TOP + ROW * index as f64;
+        svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" font-size=\"12\" text-anchor=\"end\" fill=\"#2c3e50\">{}</text>",
+            LABEL - 8.0,
+            y + ROW / 2.0 + 4.0,
+            html_escape(&bar.medication)
+        ));
+        for segment in &bar.segments {
+            svg.push_str(&format!(
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\" fill-opacity=\"{:.2}\"><title>{}: {} de {} a {}</title></rect>",
+                x(segment.start),
+                y + 5.0,
+                (x(segment.end) - x(segment.start)).max(2.0),
+                ROW - 10.0,
+                COLOR,
+                0.25 + 0.75 * bar.level(segment),
+                html_escape(&bar.medication),
+                bar.dose_text(segment),
+                segment.start.format("%d/%m/%Y"),
+                segment.end.format("%d/%m/%Y")
+            ));
+        }
+        if let Some(date) = bar.suspended_on {
+            let (cx, cy) = (x(date), y + ROW / 2.0);
+            svg.push_str(&format!(
+                "<g stroke=\"#e74c3c\" stroke-width=\"2\"><title>Suspenso em {}</title><line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\"/><line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\"/></g>",
+                date.format("%d/%m/%Y"),
+                cx - 5.0,
+                cy - 5.0,
+                cx + 5.0,
+                cy + 5.0,
+                cx - 5.0,
+                cy + 5.0,
+                cx + 5.0,
+                cy - 5.0
+            ));
+        }
+    }
+    let dates: Vec<NaiveDate> = std::iter::once(start).chain(encounter_dates.iter().copied()).chain(std::iter::once(end)).collect();
+    svg.push_str(&charts::date_labels(&dates, x, height - 10.0));
+    svg.push_str("</svg>");
+    format!(
+        "{}<p><small>Tons mais escuros indicam doses maiores de cada medicação; linhas tracejadas marcam os atendimentos e o X vermelho, as suspensões.</small></p>",
+        svg
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Local, TimeZone};
+
+    use super::*;
+    use crate::prescription::{Medications, medication_json_populator, prescription_finalizer};
+
+    #[test]
+    fn bar_ends_at_the_suspension() {
+        let mut medications = Medications::new();
+        let mut history = vec![];
+        for (day, line) in [
+            (10, "!PRESCREVO TOPIRAMATO 25MG [1 COMPRIMIDO] NOITE;"),
+            (20, "!AUMENTO TOPIRAMATO 50MG [1 COMPRIMIDO] NOITE;"),
+            (30, "!SUSPENDO TOPIRAMATO;"),
+        ] {
+            let time = Local.with_ymd_and_hms(2025, 1, day, 9, 0, 0).unwrap();
+            let (_, _, changes) = prescription_finalizer(vec![medication_json_populator(line)], &mut medications, time);
+            history.extend(changes);
+        }
+        let date = |day| NaiveDate::from_ymd_opt(2025, 1, day).unwrap();
+        let bars = build_bars(&history, &medications, date(31));
+        assert_eq!(bars.len(), 1);
+        assert_eq!(bars[0].suspended_on, Some(date(30)));
+        let doses: Vec<_> = bars[0].segments.iter().map(|s| (s.start, s.end, s.mg_per_day)).collect();
+        assert_eq!(doses, [(date(10), date(20), Some(25.0)), (date(20), date(30), Some(50.0))]);
+    }
+
+    #[test]
+    fn prescribing_again_while_active_keeps_the_earlier_dose() {
+        let mut medications = Medications::new();
+        let mut history = vec![];
+        for (day, line) in [
+            (10, "!PRESCREVO TOPIRAMATO 25MG [1 COMPRIMIDO] NOITE;"),
+            (20, "!PRESCREVO TOPIRAMATO 50MG [1 COMPRIMIDO] NOITE;"),
+        ] {
+            let time = Local.with_ymd_and_hms(2025, 1, day, 9, 0, 0).unwrap();
+            let (_, _, changes) = prescription_finalizer(vec![medication_json_populator(line)], &mut medications, time);
+            history.extend(changes);
+        }
+        assert_eq!(history.iter().filter(|r| r.2 == "initial").count(), 1);
+        let date = |day| NaiveDate::from_ymd_opt(2025, 1, day).unwrap();
+        let bars = build_bars(&history, &medications, date(31));
+        let doses: Vec<_> = bars[0].segments.iter().map(|s| (s.start, s.end, s.mg_per_day)).collect();
+        assert_eq!(doses, [(date(10), date(20), Some(25.0)), (date(20), date(31), Some(50.0))]);
+        assert_eq!(bars[0].suspended_on, None);
+    }
+}
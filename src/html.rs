@@ -0,0 +1,338 @@
+// Building blocks of the generated HTML documents: the page header with the
+// doctor identification and styles, the prescription sections and the
+// signature. Every page of the site, the attendance view and the printed
+// prescription are put together from these.
+
+use crate::doses;
+use crate::profile::{self, UserInfo};
+use crate::site::Encounter;
+
+pub fn ansi_to_html(text: &str) -> String {
+    text.replace("\x1b[31m", "<span class=\"removed\">")
+        .replace("\x1b[32m", "<span class=\"added\">")
+        .replace("\x1b[0m", "</span>")
+}
+
+pub fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn parse_prescription_to_list(recipe_content: &str) -> String {
+    let mut html = String::from("<ol class=\"prescription-list\">");
+
+    for medication_block in recipe_content.split("\n\n") {
+        let lines: Vec<&str> = medication_block.lines().collect();
+        if lines.len() >= 2 {
+            let medication_name = lines[0];
+            let dosage_info = lines[1];
+            html.push_str(&format!(
+                "<li><strong>{}</strong><br>{}</li>",
+                medication_name, dosage_info
+            ));
+        }
+    }
+
+    html.push_str("</ol>");
+    html
+}
+
+pub fn prescription_graphs_html(encounters: &[Encounter]) -> String {
+    doses::generate_dose_charts(encounters)
+}
+
+pub fn generate_html_header(title: &str, doctor: Option<&UserInfo>) -> String {
+    generate_html_header_with_nav(title, "", doctor)
+}
+
+pub fn generate_html_header_with_nav(title: &str, nav_html: &str, doctor: Option<&UserInfo>) -> String {
+    // Without a doctor the page is rendered without the identification header
+    format!(r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="UTF-8">
+    <title>{}</title>
+    <meta charset="UTF-8">
+    <link href="https://fonts.googleapis.com/css2?family=Ubuntu:wght@300;400;500;600&display=swap" rel="stylesheet">
+    <style>
+        body {{
+            font-family: 'Ubuntu', sans-serif;
+            margin: 0;
+            padding: 20px;
+            background-color: #f4f7fa;
+            color: #333;
+            line-height: 1.6;
+        }}
+        .container {{
+            max-width: 800px;
+            margin: 0 auto;
+            background: white;
+            padding: 40px;
+            box-shadow: 0 0 20px rgba(0,0,0,0.1);
+            border-radius: 8px;
+        }}
+        h1 {{
+            color: #2c3e50;
+            text-align: center;
+            margin-bottom: 40px;
+            font-size: 2.5em;
+            font-weight: 300;
+        }}
+        h2 {{
+            color: #34495e;
+            border-bottom: 2px solid #3498db;
+            padding-bottom: 10px;
+            margin-top: 40px;
+            margin-bottom: 20px;
+            font-size: 1.8em;
+            font-weight: 400;
+        }}
+        .prescription {{
+            border: 1px solid #e1e8ed;
+            padding: 25px;
+            margin-bottom: 30px;
+            background: #fafbfc;
+            border-radius: 6px;
+        }}
+        .changes {{
+            background-color: #ecf0f1;
+            padding: 20px;
+            border-left: 4px solid #3498db;
+            border-radius: 0 6px 6px 0;
+        }}
+        pre {{
+            white-space: pre-wrap;
+            font-family: 'Ubuntu', sans-serif;
+            background: #f8f9fa;
+            color: #333;
+            padding: 15px;
+            border-radius: 4px;
+            border: 1px solid #e1e8ed;
+            overflow-x: auto;
+        }}
+        .removed {{ color: #e74c3c; font-weight: bold; }}
+        .added {{ color: #27ae60; font-weight: bold; }}
+
+        .prescription-list {{
+            padding-left: 0;
+            list-style: none;
+        }}
+        .prescription-list li {{
+            margin-bottom: 20px;
+            padding: 15px;
+            background: white;
+            border: 1px solid #e1e8ed;
+            border-radius: 4px;
+            box-shadow: 0 1px 3px rgba(0,0,0,0.1);
+        }}
+        .prescription-list strong {{
+            color: #2c3e50;
+            font-size: 1.1em;
+            display: block;
+            margin-bottom: 5px;
+        }}
+        .prescription-timeline {{
+            margin-top: 40px;
+        }}
+        .prescription-timeline h3 {{
+            color: #2c3e50;
+            margin-bottom: 20px;
+            font-size: 1.3em;
+            font-weight: 500;
+        }}
+        .attendance-date {{
+            text-align: center;
+            color: #7f8c8d;
+        }}
+        .attendance-section h3 {{
+            color: #2c3e50;
+            font-size: 1.1em;
+            font-weight: 500;
+            margin-bottom: 10px;
+        }}
+        .directive-list, .conduct-list, .diagnosis-list {{
+            padding-left: 0;
+            list-style: none;
+        }}
+        .directive-list li, .conduct-list li, .diagnosis-list li {{
+            margin-bottom: 8px;
+            padding: 10px 15px;
+            background: white;
+            border: 1px solid #e1e8ed;
+            border-radius: 4px;
+        }}
+        .diagnosis-list li.suspected {{ color: #7f8c8d; }}
+        table.vitals th {{ width: 120px; text-align: left; }}
+        table.vitals tr.flagged td {{ color: #c0392b; font-weight: 500; }}
+        .badge.alert {{ background: #e74c3c; }}
+        svg.chart {{ width: 100%; height: auto; margin-bottom: 15px; }}
+        table.problems tr.closed {{ color: #95a5a6; }}
+        .cid {{
+            margin-left: 6px;
+            font-family: monospace;
+            font-size: 0.85em;
+            color: #2980b9;
+        }}
+        .conduct-list li.conduct-prescription {{
+            border-left: 4px solid #27ae60;
+            background: #eafaf1;
+        }}
+        .badge {{
+            display: inline-block;
+            margin-right: 8px;
+            padding: 2px 8px;
+            border-radius: 10px;
+            background: #3498db;
+            color: white;
+            font-size: 0.8em;
+        }}
+        .conduct-prescription strong {{
+            display: block;
+            color: #2c3e50;
+        }}
+        table.exams {{
+            width: 100%;
+            border-collapse: collapse;
+            margin-bottom: 20px;
+        }}
+        table.exams th, table.exams td {{
+            border: 1px solid #e1e8ed;
+            padding: 8px;
+            text-align: left;
+            vertical-align: top;
+        }}
+        table.exams th {{ background: #ecf0f1; }}
+        .navbar {{
+            max-width: 880px;
+            margin: 0 auto 20px;
+        }}
+        .navbar a {{
+            color: #3498db;
+            text-decoration: none;
+            font-weight: 500;
+            margin-right: 20px;
+        }}
+        .dashboard {{
+            display: flex;
+            flex-wrap: wrap;
+            gap: 20px;
+        }}
+        .card {{
+            flex: 1 1 300px;
+            border: 1px solid #e1e8ed;
+            border-radius: 6px;
+            padding: 20px;
+            background: #fafbfc;
+        }}
+        .card h2 {{ margin-top: 0; }}
+        .card a {{ color: #3498db; }}
+        .filter {{
+            width: 100%;
+            padding: 10px;
+            margin-bottom: 15px;
+            border: 1px solid #e1e8ed;
+            border-radius: 4px;
+            box-sizing: border-box;
+        }}
+        .print-button {{
+            background: #3498db;
+            color: white;
+            border: none;
+            padding: 10px 20px;
+            border-radius: 4px;
+            cursor: pointer;
+        }}
+        .doctor-header {{
+            text-align: center;
+            color: #7f8c8d;
+            font-size: 0.9em;
+            border-bottom: 1px solid #e1e8ed;
+            padding-bottom: 10px;
+        }}
+        .doctor-header strong {{
+            color: #2c3e50;
+            font-size: 1.2em;
+        }}
+        .signature {{
+            margin-top: 60px;
+            text-align: center;
+        }}
+        .signature img {{ max-height: 80px; }}
+        .verification {{
+            display: flex;
+            align-items: center;
+            gap: 15px;
+            margin-top: 30px;
+            font-size: 0.85em;
+            color: #555;
+        }}
+        .verification .qr svg {{ width: 110px; height: 110px; }}
+        .signature-line {{
+            display: inline-block;
+            width: 300px;
+            border-top: 1px solid #333;
+        }}
+        @media print {{
+            body {{ background: white; padding: 0; }}
+            .container {{ box-shadow: none; padding: 0; }}
+            .navbar, .print-button, .filter {{ display: none; }}
+            .page-break {{ page-break-after: always; }}
+        }}
+    </style>
+</head>
+<body>
+{}    <div class="container">
+{}        <h1>{}</h1>
+"#, title, nav_html, doctor.map(profile::generate_doctor_header_html).unwrap_or_default(), title)
+}
+
+pub fn generate_recipe_section(recipe_list_html: &str) -> String {
+    format!(r#"
+    <div class="prescription">
+        <h2>Receita</h2>
+        {}
+    </div>
+"#, recipe_list_html)
+}
+
+pub fn generate_changes_section(returns_content: &str) -> String {
+    format!(r#"
+    <div class="prescription changes">
+        <h2>Alterações</h2>
+        <pre>{}</pre>
+    </div>
+"#, returns_content)
+}
+
+pub fn generate_timeline_section(graph_html: &str) -> String {
+    format!(r#"
+    <div class="prescription">
+        <h2>Evolução das Prescrições</h2>
+        {}
+    </div>
+"#, graph_html)
+}
+
+pub fn generate_signature_section(doctor: Option<&UserInfo>, signature_image: Option<&[u8]>) -> String {
+    doctor.map(|user| profile::generate_signature_html(user, signature_image)).unwrap_or_default()
+}
+
+pub fn generate_html_footer() -> String {
+    "\n    </div>\n</body>\n</html>".to_string()
+}
+
+pub fn generate_complete_html(
+    recipe_list_html: &str,
+    returns_content: &str,
+    graph_html: &str,
+    doctor: Option<&UserInfo>,
+) -> String {
+    let mut html = generate_html_header("Prescrição Médica", doctor);
+    html.push_str(&generate_recipe_section(recipe_list_html));
+    html.push_str(&generate_changes_section(returns_content));
+    html.push_str(&generate_timeline_section(graph_html));
+    html.push_str(&generate_html_footer());
+    html
+}
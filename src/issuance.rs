@@ -0,0 +1,238 @@
+// Prescription numbering and verification codes. Every prescription issued by
+// `prescription_printer` or `print --pdf` gets the next sequential number and is
+// archived in issued_prescriptions.json with the SHA-256 of its content. The QR
+// code printed on the document carries the number, issue date, prescriber CRM
+// and that hash, so `medfiles verify-code` can check a scanned payload against
+// the archive.
+
+use qrcodegen::{QrCode, QrCodeEcc};
+use serde::{Deserialize, Serialize};
+
+use medfiles::html::html_escape;
+
+use crate::crypto;
+
+pub const ARCHIVE_PATH: &str = "issued_prescriptions.json";
+const PAYLOAD_PREFIX: &str = "MEDFILES-RX";
+const PAYLOAD_VERSION: &str = "v1";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct IssuedPrescription {
+    pub seq: u64,
+    pub number: String,
+    pub issued_at: String,
+    // YYYY-MM-DD, as encoded in the QR payload
+    pub issue_date: String,
+    pub crm: String,
+    pub sha256: String,
+    pub content: String,
+}
+
+pub struct Payload {
+    pub number: String,
+    pub issue_date: String,
+    pub crm: String,
+    pub sha256: String,
+}
+
+impl IssuedPrescription {
+    pub fn payload(&self) -> String {
+        [PAYLOAD_PREFIX, PAYLOAD_VERSION, &self.number, &self.issue_date, &self.crm, &self.sha256].join("|")
+    }
+
+    pub fn display_date(&self) -> String {
+        chrono::NaiveDate::parse_from_str(&self.issue_date, "%Y-%m-%d")
+            .map(|date| date.format("%d/%m/%Y").to_string())
+            .unwrap_or_else(|_| self.issue_date.clone())
+    }
+}
+
+pub fn content_hash(content: &str) -> String {
+    crypto::sha256_hex(content.trim().as_bytes())
+}
+
+fn prescriber_crm() -> String {
+    match crate::load_user_info() {
+        Some(user) if !user.crm.is_empty() => format!("CRM-{}-{}", user.crm_uf, user.crm),
+        _ => String::new(),
+    }
+}
+
+fn load_archive() -> Result<Vec<IssuedPrescription>, String> {
+    match crate::storage::read_to_string(ARCHIVE_PATH) {
+        Ok(content) => serde_json::from_str(&content).map_err(|e| format!("Falha ao parsear {}: {}", ARCHIVE_PATH, e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(format!("Falha ao ler {}: {}", ARCHIVE_PATH, e)),
+    }
+}
+
+pub fn issue(content: &str) -> Result<IssuedPrescription, String> {
+    issue_at(content, chrono::Local::now())
+}
+
+fn issue_at(content: &str, now: chrono::DateTime<chrono::Local>) -> Result<IssuedPrescription, String> {
+    // The sequence never restarts; the year in the number is only the year of issue
+    let mut archive = load_archive()?;
+    let seq = archive.iter().map(|p| p.seq).max().unwrap_or(0) + 1;
+    let issued = IssuedPrescription {
+        seq,
+        number: format!("{}-{:06}", now.format("%Y"), seq),
+        issued_at: now.to_rfc3339(),
+        issue_date: now.format("%Y-%m-%d").to_string(),
+        crm: prescriber_crm(),
+        sha256: content_hash(content),
+        content: content.trim().to_string(),
+    };
+    archive.push(issued.clone());
+    let json = serde_json::to_string_pretty(&archive).map_err(|e| e.to_string())?;
+    crate::storage::write(ARCHIVE_PATH, json).map_err(|e| format!("Falha ao gravar {}: {}", ARCHIVE_PATH, e))?;
+    Ok(issued)
+}
+
+pub fn parse_payload(text: &str) -> Result<Payload, String> {
+    let parts: Vec<&str> = text.trim().split('|').collect();
+    match parts.as_slice() {
+        [prefix, version, number, issue_date, crm, sha256] if *prefix == PAYLOAD_PREFIX && *version == PAYLOAD_VERSION => Ok(Payload {
+            number: number.to_string(),
+            issue_date: issue_date.to_string(),
+            crm: crm.to_string(),
+            sha256: sha256.to_lowercase(),
+        }),
+        _ => Err(format!("Código não reconhecido (esperado {}|{}|número|data|CRM|hash).", PAYLOAD_PREFIX, PAYLOAD_VERSION)),
+    }
+}
+
+pub fn verify_payload(text: &str) -> Result<IssuedPrescription, String> {
+    let payload = parse_payload(text)?;
+    let archive = load_archive()?;
+    let Some(issued) = archive.into_iter().find(|p| p.number == payload.number) else {
+        return Err(format!("Receita nº {} não consta no arquivo local.", payload.number));
+    };
+    if issued.issue_date != payload.issue_date {
+        return Err(format!("Data divergente: o código indica {}, o arquivo registra {}.", payload.issue_date, issued.issue_date));
+    }
+    if issued.crm != payload.crm {
+        return Err(format!("CRM divergente: o código indica {}, o arquivo registra {}.", payload.crm, issued.crm));
+    }
+    if issued.sha256 != payload.sha256 {
+        return Err("O conteúdo da receita não confere com o arquivo local.".to_string());
+    }
+    if content_hash(&issued.content) != issued.sha256 {
+        return Err(format!("O registro da receita nº {} foi alterado no arquivo local.", issued.number));
+    }
+    Ok(issued)
+}
+
+pub fn qr_matrix(payload: &str) -> Result<Vec<Vec<bool>>, String> {
+    let qr = QrCode::encode_text(payload, QrCodeEcc::Medium).map_err(|_| "Conteúdo longo demais para o QR code.".to_string())?;
+    let size = qr.size();
+    Ok((0..size).map(|y| (0..size).map(|x| qr.get_module(x, y)).collect()).collect())
+}
+
+pub fn qr_svg(payload: &str) -> String {
+    let Ok(matrix) = qr_matrix(payload) else {
+        return String::new();
+    };
+    // Four-module quiet zone around the symbol, as the QR specification requires
+    let border = 4;
+    let size = matrix.len() + 2 * border;
+    let mut path = String::new();
+    for (y, row) in matrix.iter().enumerate() {
+        for (x, dark) in row.iter().enumerate() {
+            if *dark {
+                path.push_str(&format!("M{},{}h1v1h-1z", x + border, y + border));
+            }
+        }
+    }
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {0} {0}\" shape-rendering=\"crispEdges\"><rect width=\"100%\" height=\"100%\" fill=\"#fff\"/><path d=\"{1}\" fill=\"#000\"/></svg>",
+        size, path
+    )
+}
+
+pub fn verification_lines(issued: &IssuedPrescription) -> Vec<String> {
+    let mut lines = vec![format!("Receita nº {}", issued.number), format!("Emitida em {}", issued.display_date())];
+    if !issued.crm.is_empty() {
+        lines.push(issued.crm.clone());
+    }
+    lines.push("Verifique com: medfiles verify-code".to_string());
+    lines
+}
+
+pub fn generate_verification_html(issued: &IssuedPrescription) -> String {
+    let lines = verification_lines(issued);
+    let Some((number, rest)) = lines.split_first() else {
+        return String::new();
+    };
+    let mut text = format!("<strong>{}</strong>", html_escape(number));
+    for line in rest {
+        text.push_str(&format!("<br>{}", html_escape(line)));
+    }
+    format!(
+        "\n    <div class=\"verification\">\n        <div class=\"qr\">{}</div>\n        <div class=\"verification-text\">{}</div>\n    </div>\n",
+        qr_svg(&issued.payload()),
+        text
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::testing::Workspace;
+
+    const RECIPE: &str = "TOPIRAMATO 25MG\nTOMAR 1 COMPRIMIDO À NOITE";
+
+    fn error(payload: &str) -> String {
+        verify_payload(payload).err().expect("a verificação deveria falhar")
+    }
+
+    fn at(year: i32, month: u32, day: u32) -> chrono::DateTime<chrono::Local> {
+        chrono::Local.with_ymd_and_hms(year, month, day, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn numbers_are_sequential_across_the_year_rollover() {
+        let _workspace = Workspace::new();
+        let first = issue_at(RECIPE, at(2025, 12, 31)).unwrap();
+        let second = issue_at(RECIPE, at(2025, 12, 31)).unwrap();
+        let third = issue_at(RECIPE, at(2026, 1, 1)).unwrap();
+        assert_eq!(first.number, "2025-000001");
+        assert_eq!(second.number, "2025-000002");
+        assert_eq!(third.number, "2026-000003");
+        assert_eq!(third.issue_date, "2026-01-01");
+        assert_eq!(third.display_date(), "01/01/2026");
+        assert_eq!(load_archive().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn payload_round_trips_through_verification() {
+        let _workspace = Workspace::new();
+        let issued = issue(RECIPE).unwrap();
+        let payload = issued.payload();
+        assert!(payload.starts_with("MEDFILES-RX|v1|"));
+        let verified = verify_payload(&format!("  {}\n", payload)).unwrap();
+        assert_eq!(verified.number, issued.number);
+        assert_eq!(verified.content, RECIPE);
+        assert!(qr_matrix(&payload).is_ok());
+
+        assert!(error("MEDFILES-RX|v1|2025-000001").contains("não reconhecido"));
+        let unknown = payload.replace(&issued.number, "1999-999999");
+        assert!(error(&unknown).contains("não consta"));
+    }
+
+    #[test]
+    fn mismatching_content_hash_is_rejected() {
+        let _workspace = Workspace::new();
+        let issued = issue(RECIPE).unwrap();
+        let forged = issued.payload().replace(&issued.sha256, &content_hash("TOPIRAMATO 250MG"));
+        assert!(error(&forged).contains("não confere"));
+
+        // An archive entry edited after issue no longer matches its own hash
+        let mut archive = load_archive().unwrap();
+        archive[0].content = "TOPIRAMATO 250MG".to_string();
+        crate::storage::write(ARCHIVE_PATH, serde_json::to_string(&archive).unwrap()).unwrap();
+        assert!(error(&issued.payload()).contains("foi alterado"));
+    }
+}
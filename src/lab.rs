@@ -0,0 +1,392 @@
+// Laboratory results with numeric values. Results are written one per line in
+// a `[LABORATORIO]` section or as `!LAB` directives anywhere, e.g.
+// `!LAB HB 12,5 G/DL [03/2025]`; the bracketed date defaults to the encounter
+// date. Analytes are looked up in assets/lab/analytes.tsv, whose reference
+// ranges depend on the patient's sex and age, taken from the `!SEXO`,
+// `!NASCIMENTO` or `!IDADE` directives of the encounters.
+
+use std::sync::OnceLock;
+
+use crate::charts::{self, Point, Series};
+use crate::medfile::{self, MedDocument};
+use crate::site::Encounter;
+use crate::vitals::{self, Flag};
+use crate::cid10;
+use crate::html::html_escape;
+
+const TABLE: &str = include_str!("../assets/lab/analytes.tsv");
+
+pub struct ReferenceRange {
+    pub code: String,
+    pub name: String,
+    pub unit: String,
+    pub aliases: Vec<String>,
+    // '*' applies to both sexes
+    pub sex: char,
+    pub age_min: Option<u32>,
+    pub age_max: Option<u32>,
+    pub low: Option<f64>,
+    pub high: Option<f64>,
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct Demographics {
+    // 'M' or 'F'
+    pub sex: Option<char>,
+    pub age: Option<u32>,
+}
+
+#[derive(Clone)]
+pub struct LabResult {
+    // Code in the analyte table; None for analytes it does not know
+    pub code: Option<String>,
+    pub name: String,
+    // "<" or ">" for results reported beyond the assay limits
+    pub qualifier: String,
+    pub value: f64,
+    pub unit: String,
+    pub date: String,
+}
+
+fn parse_optional<T: std::str::FromStr>(text: &str) -> Option<T> {
+    let text = text.trim();
+    if text == "-" || text.is_empty() { None } else { text.replace(',', ".").parse().ok() }
+}
+
+pub fn ranges() -> &'static [ReferenceRange] {
+    static RANGES: OnceLock<Vec<ReferenceRange>> = OnceLock::new();
+    RANGES.get_or_init(|| {
+        TABLE
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let columns: Vec<&str> = line.split('\t').collect();
+                let [code, name, unit, aliases, sex, age_min, age_max, low, high] = columns.as_slice() else {
+                    return None;
+                };
+                Some(ReferenceRange {
+                    code: code.trim().to_string(),
+                    name: name.trim().to_string(),
+                    unit: if unit.trim() == "-" { String::new() } else { unit.trim().to_string() },
+                    aliases: aliases.split('|').map(cid10::normalize).collect(),
+                    sex: sex.trim().chars().next().unwrap_or('*'),
+                    age_min: parse_optional(age_min),
+                    age_max: parse_optional(age_max),
+                    low: parse_optional(low),
+                    high: parse_optional(high),
+                })
+            })
+            .collect()
+    })
+}
+
+pub fn analyte(name: &str) -> Option<&'static ReferenceRange> {
+    let name = cid10::normalize(name);
+    ranges().iter().find(|range| cid10::normalize(&range.code) == name || range.aliases.contains(&name))
+}
+
+fn normalize_unit(unit: &str) -> String {
+    unit.to_lowercase()
+        .replace('µ', "u")
+        .replace('³', "3")
+        .replace('²', "2")
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect()
+}
+
+impl Demographics {
+    pub fn from_encounters(encounters: &[Encounter]) -> Demographics {
+        let mut demographics = Demographics::default();
+        for encounter in encounters {
+            demographics.read(&encounter.doc);
+        }
+        demographics
+    }
+
+    pub fn from_document(doc: &MedDocument) -> Demographics {
+        let mut demographics = Demographics::default();
+        demographics.read(doc);
+        demographics
+    }
+
+    fn read(&mut self, doc: &MedDocument) {
+        // Later encounters override earlier ones
+        for directive in doc.sections.iter().flat_map(|section| section.directives()) {
+            let value = directive.content.trim().to_uppercase();
+            match directive.keyword.as_str() {
+                "SEXO" => {
+                    self.sex = match value.chars().next() {
+                        Some('M') => Some('M'),
+                        Some('F') => Some('F'),
+                        _ => self.sex,
+                    }
+                }
+                "IDADE" => self.age = value.split_whitespace().next().and_then(|v| v.parse().ok()).or(self.age),
+                "NASCIMENTO" => {
+                    if let Ok(birth) = chrono::NaiveDate::parse_from_str(&value, "%d/%m/%Y") {
+                        let today = chrono::Local::now().date_naive();
+                        self.age = today.years_since(birth).or(self.age);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+pub fn reference_range(code: &str, demographics: &Demographics) -> Option<&'static ReferenceRange> {
+    ranges().iter().filter(|range| range.code == code).find(|range| {
+        let sex_matches = range.sex == '*' || demographics.sex == Some(range.sex);
+        // Without a known age only the ranges open to adults apply
+        let age_matches = match demographics.age {
+            Some(age) => range.age_min.is_none_or(|min| age >= min) && range.age_max.is_none_or(|max| age <= max),
+            None => range.age_max.is_none(),
+        };
+        sex_matches && age_matches
+    })
+}
+
+fn parse_decimal(number: &str) -> Option<f64> {
+    // "12,5" and "1.234,5" in the Brazilian style, "0.125" as typed by labs abroad. A lone dot is
+    // a decimal point: only a comma or several dot groups ("1.500.000") make it a thousands separator
+    let thousands = number.contains(',') || number.matches('.').count() > 1;
+    let number = if thousands { number.replace('.', "").replace(',', ".") } else { number.to_string() };
+    number.parse().ok()
+}
+
+pub fn parse_result(text: &str, default_date: &str) -> Option<LabResult> {
+    // HB 12,5 G/DL [03/2025]
+    let text = text.trim().trim_end_matches(';').trim();
+    let (text, date) = match (text.find('['), text.rfind(']')) {
+        (Some(open), Some(close)) if open < close => {
+            (format!("{} {}", &text[..open], &text[close + 1..]), text[open + 1..close].trim().to_string())
+        }
+        _ => (text.to_string(), default_date.to_string()),
+    };
+    let tokens: Vec<&str> = text.split_whitespace().collect();
+    let position = tokens
+        .iter()
+        .position(|token| token.trim_start_matches(['<', '>']).starts_with(|c: char| c.is_ascii_digit()))?;
+    if position == 0 {
+        return None;
+    }
+    let name = tokens[..position].join(" ").trim_end_matches(':').to_string();
+    let token = tokens[position];
+    let qualifier: String = token.chars().take_while(|c| *c == '<' || *c == '>').collect();
+    let number: String = token[qualifier.len()..].chars().take_while(|c| c.is_ascii_digit() || *c == ',' || *c == '.').collect();
+    let value = parse_decimal(&number)?;
+    let attached_unit = &token[qualifier.len() + number.len()..];
+    let unit = std::iter::once(attached_unit)
+        .chain(tokens[position + 1..].iter().copied())
+        .filter(|t| !t.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let known = analyte(&name);
+    Some(LabResult {
+        code: known.map(|a| a.code.clone()),
+        name: known.map(|a| a.name.clone()).unwrap_or(name),
+        qualifier,
+        value,
+        unit,
+        date,
+    })
+}
+
+impl LabResult {
+    pub fn range(&self, demographics: &Demographics) -> Option<&'static ReferenceRange> {
+        reference_range(self.code.as_deref()?, demographics)
+    }
+
+    fn unit_matches(&self, range: &ReferenceRange) -> bool {
+        self.unit.is_empty() || range.unit.is_empty() || normalize_unit(&self.unit) == normalize_unit(&range.unit)
+    }
+
+    pub fn flag(&self, demographics: &Demographics) -> Option<Flag> {
+        let range = self.range(demographics)?;
+        // Results in another unit are shown but not judged against the table
+        if !self.unit_matches(range) {
+            return None;
+        }
+        vitals::flag(self.value, (range.low.unwrap_or(f64::NEG_INFINITY), range.high.unwrap_or(f64::INFINITY)))
+    }
+
+    pub fn value_text(&self) -> String {
+        let unit = if self.unit.is_empty() { String::new() } else { format!(" {}", self.unit) };
+        format!("{}{}{}", self.qualifier, charts::format_value(self.value), unit)
+    }
+
+    pub fn reference_text(&self, demographics: &Demographics) -> String {
+        let Some(range) = self.range(demographics) else {
+            return String::new();
+        };
+        let limits = match (range.low, range.high) {
+            (Some(low), Some(high)) => format!("{}–{}", charts::format_value(low), charts::format_value(high)),
+            (Some(low), None) => format!("≥ {}", charts::format_value(low)),
+            (None, Some(high)) => format!("≤ {}", charts::format_value(high)),
+            (None, None) => return String::new(),
+        };
+        let mut text = format!("{} {}", limits, range.unit).trim().to_string();
+        if !self.unit_matches(range) {
+            text.push_str(" (unidade diferente)");
+        }
+        text
+    }
+
+    fn key(&self) -> String {
+        self.code.clone().unwrap_or_else(|| cid10::normalize(&self.name))
+    }
+
+    fn sort_date(&self) -> String {
+        crate::fhir::exam_date(&self.date).unwrap_or_default()
+    }
+}
+
+pub fn section_results(lines: &[String], default_date: &str) -> (Vec<LabResult>, Vec<String>) {
+    // Lines of a [LABORATORIO] section; the ones that are not results are returned as text
+    let mut results = vec![];
+    let mut text = vec![];
+    for line in lines {
+        let content = match medfile::parse_directive(line) {
+            Some(directive) if directive.keyword == "LAB" => directive.content,
+            Some(_) => {
+                text.push(line.clone());
+                continue;
+            }
+            None => line.clone(),
+        };
+        match parse_result(&content, default_date) {
+            Some(result) => results.push(result),
+            None => text.push(line.clone()),
+        }
+    }
+    (results, text)
+}
+
+pub fn document_results(doc: &MedDocument, default_date: &str) -> Vec<LabResult> {
+    let mut results = vec![];
+    for section in &doc.sections {
+        if section.name == "LABORATORIO" {
+            results.extend(section_results(&section.lines, default_date).0);
+        } else {
+            let directives = section.directives().into_iter().filter(|d| d.keyword == "LAB");
+            results.extend(directives.filter_map(|d| parse_result(&d.content, default_date)));
+        }
+    }
+    results
+}
+
+pub fn lab_history(encounters: &[Encounter]) -> Vec<LabResult> {
+    // Results copied forward into later encounters are counted once
+    let mut results: Vec<LabResult> = vec![];
+    for encounter in encounters {
+        for result in document_results(&encounter.doc, &encounter.date_str) {
+            let duplicate = results.iter().any(|r| r.key() == result.key() && r.date == result.date && r.value == result.value);
+            if !duplicate {
+                results.push(result);
+            }
+        }
+    }
+    results.sort_by_key(|r| r.sort_date());
+    results
+}
+
+pub fn generate_lab_table(results: &[LabResult], demographics: &Demographics) -> String {
+    if results.is_empty() {
+        return String::new();
+    }
+    let mut html = String::from(
+        "<table class=\"exams lab\"><thead><tr><th>Exame</th><th>Resultado</th><th>Referência</th><th>Data</th></tr></thead><tbody>",
+    );
+    for result in results {
+        let (class, badge) = match result.flag(demographics) {
+            Some(flag) => (" class=\"flagged\"", format!(" <span class=\"badge alert\">{}</span>", flag.label())),
+            None => ("", String::new()),
+        };
+        html.push_str(&format!(
+            "<tr{}><td>{}</td><td>{}{}</td><td>{}</td><td>{}</td></tr>",
+            class,
+            html_escape(&result.name),
+            html_escape(&result.value_text()),
+            badge,
+            html_escape(&result.reference_text(demographics)),
+            html_escape(&result.date)
+        ));
+    }
+    html.push_str("</tbody></table>");
+    html
+}
+
+pub fn result_text(result: &LabResult, demographics: &Demographics) -> String {
+    let mut text = format!("{}: {}", result.name, result.value_text());
+    let reference = result.reference_text(demographics);
+    if !reference.is_empty() {
+        text.push_str(&format!(" (ref. {})", reference));
+    }
+    if !result.date.is_empty() {
+        text.push_str(&format!(" [{}]", result.date));
+    }
+    if let Some(flag) = result.flag(demographics) {
+        text.push_str(&format!(" {}", flag.label().to_uppercase()));
+    }
+    text
+}
+
+pub fn generate_lab_trends(history: &[LabResult], demographics: &Demographics) -> String {
+    let mut keys: Vec<String> = vec![];
+    for result in history {
+        if !keys.contains(&result.key()) {
+            keys.push(result.key());
+        }
+    }
+    let mut html = String::new();
+    for key in keys {
+        let results: Vec<&LabResult> = history.iter().filter(|r| r.key() == key).collect();
+        // A single result has no trend to show
+        if results.len() < 2 {
+            continue;
+        }
+        let owned: Vec<LabResult> = results.iter().map(|r| (*r).clone()).collect();
+        html.push_str(&format!("<h3>{}</h3>", html_escape(&results[0].name)));
+        html.push_str(&generate_lab_table(&owned, demographics));
+
+        let labels: Vec<String> = results.iter().map(|r| r.date.clone()).collect();
+        let series = Series {
+            name: results[0].name.clone(),
+            points: results
+                .iter()
+                .map(|r| Some(Point { label: r.date.clone(), value: r.value, flagged: r.flag(demographics).is_some() }))
+                .collect(),
+        };
+        let band = results[0].range(demographics).filter(|range| results[0].unit_matches(range)).map(|range| {
+            let values = results.iter().map(|r| r.value);
+            let low = range.low.unwrap_or_else(|| values.clone().fold(range.high.unwrap_or(0.0), f64::min));
+            let high = range.high.unwrap_or_else(|| values.fold(range.low.unwrap_or(0.0), f64::max));
+            (low, high)
+        });
+        html.push_str(&charts::line_chart_svg(&labels, &[series], band));
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dots_are_thousands_separators_only_with_a_comma_or_several_groups() {
+        for (text, value) in [
+            ("0.125", 0.125),
+            ("0.005", 0.005),
+            ("12.500", 12.5),
+            ("1.2", 1.2),
+            ("12,5", 12.5),
+            ("1.234,5", 1234.5),
+            ("150.000,0", 150000.0),
+            ("1.500.000", 1500000.0),
+            ("250000", 250000.0),
+        ] {
+            assert_eq!(parse_decimal(text), Some(value), "{}", text);
+        }
+    }
+}
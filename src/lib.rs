@@ -0,0 +1,54 @@
+//! The medfiles library: the .med parser, the medication engine and the
+//! document renderers used by the `medfiles` command line. Nothing here reads
+//! the current directory or stdin; files, the doctor shown on the documents and
+//! the signature image are passed in by the caller.
+//!
+//! ```
+//! use std::path::PathBuf;
+//! use std::time::SystemTime;
+//!
+//! use medfiles::{prescription, site, MedDocument, Medications};
+//!
+//! let content = "[HIPOTESE DIAGNOSTICA]\nENXAQUECA {G43};\n\n[CONDUTA]\n!PRESCREVO TOPIRAMATO 25MG [1 COMPRIMIDO] NOITE;\n";
+//!
+//! // One document through the medication engine
+//! let doc = MedDocument::parse(content);
+//! let mut medications = Medications::new();
+//! let items = prescription::document_prescriptions(&doc);
+//! let (_, recipes, changes) = prescription::prescription_finalizer(items, &mut medications, chrono::Local::now());
+//! assert!(medications.contains_key("TOPIRAMATO"));
+//! assert_eq!(recipes.len(), 1);
+//! assert_eq!(changes[0].5, "PRESCRIBE");
+//!
+//! // A whole patient; the caller decides how each file is read, here from memory
+//! let files = vec![(PathBuf::from("JOAO SILVA 2025-01-10.med"), SystemTime::now())];
+//! let (encounters, skipped) = site::load_encounters(&files, |_| Ok(content.to_string()));
+//! assert!(skipped.is_empty());
+//! let (medications, history) = prescription::replay(&encounters);
+//! assert_eq!(history.len(), 1);
+//! let page = site::render_page("index.html", &encounters, &medications, None, None).unwrap();
+//! assert!(page.contains("TOPIRAMATO"));
+//! ```
+
+pub mod attendance;
+pub mod charts;
+pub mod cid10;
+pub mod doses;
+pub mod error;
+pub mod fhir;
+pub mod gantt;
+pub mod html;
+pub mod lab;
+pub mod medfile;
+pub mod pdf;
+pub mod prescription;
+pub mod problems;
+pub mod profile;
+pub mod site;
+pub mod vitals;
+
+pub use error::MedError;
+pub use medfile::MedDocument;
+pub use prescription::{ChangeRecord, Medications, Prescription};
+pub use profile::UserInfo;
+pub use site::Encounter;
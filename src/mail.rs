@@ -0,0 +1,311 @@
+// Email delivery through lettre. The SMTP settings come from smtp_config.json
+// in the working directory, with MEDFILES_SMTP_* environment variables taking
+// precedence, so a local SMTP stand-in (MailHog, smtp4dev...) can be used
+// with `tls: "none"` while testing offline.
+
+use std::env;
+use std::thread;
+use std::time::Duration;
+
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use serde::{Deserialize, Serialize};
+
+const SMTP_CONFIG_PATH: &str = "smtp_config.json";
+const SEND_ATTEMPTS: u32 = 3;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    // "none", "starttls" or "tls"
+    #[serde(default = "default_tls")]
+    pub tls: String,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+    pub from: String,
+}
+
+fn default_port() -> u16 {
+    587
+}
+
+fn default_tls() -> String {
+    "starttls".to_string()
+}
+
+pub fn load_smtp_config() -> Result<SmtpConfig, String> {
+    let mut config = match crate::storage::read_to_string(SMTP_CONFIG_PATH) {
+        Ok(content) => serde_json::from_str(&content)
+            .map_err(|e| format!("Falha ao parsear {}: {}", SMTP_CONFIG_PATH, e))?,
+        Err(_) => SmtpConfig {
+            host: String::new(),
+            port: default_port(),
+            tls: default_tls(),
+            username: String::new(),
+            password: String::new(),
+            from: String::new(),
+        },
+    };
+
+    if let Ok(host) = env::var("MEDFILES_SMTP_HOST") {
+        config.host = host;
+    }
+    if let Ok(port) = env::var("MEDFILES_SMTP_PORT") {
+        config.port = port.parse().map_err(|_| format!("MEDFILES_SMTP_PORT inválida: {}", port))?;
+    }
+    if let Ok(tls) = env::var("MEDFILES_SMTP_TLS") {
+        config.tls = tls;
+    }
+    if let Ok(username) = env::var("MEDFILES_SMTP_USER") {
+        config.username = username;
+    }
+    if let Ok(password) = env::var("MEDFILES_SMTP_PASSWORD") {
+        config.password = password;
+    }
+    if let Ok(from) = env::var("MEDFILES_SMTP_FROM") {
+        config.from = from;
+    }
+
+    if config.host.is_empty() || config.from.is_empty() {
+        return Err(format!(
+            "SMTP não configurado. Crie {} ou defina MEDFILES_SMTP_HOST e MEDFILES_SMTP_FROM.",
+            SMTP_CONFIG_PATH
+        ));
+    }
+    config.tls = config.tls.to_lowercase();
+    if !["none", "starttls", "tls"].contains(&config.tls.as_str()) {
+        return Err(format!("Modo TLS inválido: {} (use none, starttls ou tls).", config.tls));
+    }
+    Ok(config)
+}
+
+pub fn build_transport(config: &SmtpConfig) -> Result<SmtpTransport, String> {
+    let builder = match config.tls.as_str() {
+        "none" => SmtpTransport::builder_dangerous(&config.host),
+        "tls" => SmtpTransport::relay(&config.host).map_err(|e| e.to_string())?,
+        _ => SmtpTransport::starttls_relay(&config.host).map_err(|e| e.to_string())?,
+    };
+    let mut builder = builder.port(config.port).timeout(Some(Duration::from_secs(10)));
+    if !config.username.is_empty() {
+        builder = builder.credentials(Credentials::new(config.username.clone(), config.password.clone()));
+    }
+    Ok(builder.build())
+}
+
+pub fn verification_message(config: &SmtpConfig, to: &str, token: &str) -> Result<Message, String> {
+    let plain = format!(
+        "Olá!\n\nSeu código de verificação MedFiles é: {}\n\nO código expira em 10 minutos. Se você não solicitou este código, ignore esta mensagem.\n",
+        token
+    );
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html>
+<body style="font-family: 'Ubuntu', sans-serif; background-color: #f4f7fa; color: #333; padding: 20px;">
+    <div style="max-width: 480px; margin: 0 auto; background: white; padding: 30px; border-radius: 8px;">
+        <h2 style="color: #2c3e50; font-weight: 400;">Código de verificação MedFiles</h2>
+        <p>Use o código abaixo para confirmar sua conta:</p>
+        <p style="font-size: 2em; letter-spacing: 6px; text-align: center; color: #3498db;"><strong>{}</strong></p>
+        <p style="color: #7f8c8d;">O código expira em 10 minutos. Se você não solicitou este código, ignore esta mensagem.</p>
+    </div>
+</body>
+</html>"#,
+        token
+    );
+    build_message(config, to, "MedFiles - Código de verificação", plain, html)
+}
+
+fn build_message(config: &SmtpConfig, to: &str, subject: &str, plain: String, html: String) -> Result<Message, String> {
+    let from: Mailbox = config.from.parse().map_err(|e| format!("Remetente inválido {}: {}", config.from, e))?;
+    let to: Mailbox = to.parse().map_err(|e| format!("Destinatário inválido {}: {}", to, e))?;
+    Message::builder()
+        .from(from)
+        .to(to)
+        .subject(subject)
+        .multipart(MultiPart::alternative_plain_html(plain, html))
+        .map_err(|e| e.to_string())
+}
+
+pub fn send_with_retry(transport: &SmtpTransport, message: &Message) -> Result<(), String> {
+    let mut last_error = String::new();
+    for attempt in 1..=SEND_ATTEMPTS {
+        match transport.send(message) {
+            Ok(_) => return Ok(()),
+            Err(e) if e.is_permanent() => return Err(e.to_string()),
+            Err(e) => {
+                last_error = e.to_string();
+                eprintln!("Tentativa {} de {} falhou: {}", attempt, SEND_ATTEMPTS, last_error);
+                if attempt < SEND_ATTEMPTS {
+                    thread::sleep(Duration::from_secs(2 * attempt as u64));
+                }
+            }
+        }
+    }
+    Err(last_error)
+}
+
+pub fn send_verification_email(to: &str, token: &str) -> Result<(), String> {
+    let config = load_smtp_config()?;
+    let transport = build_transport(&config)?;
+    let message = verification_message(&config, to, token)?;
+    send_with_retry(&transport, &message)
+}
+
+pub fn smtp_test() -> Result<(), String> {
+    let config = load_smtp_config()?;
+    println!(
+        "Servidor: {}:{} (TLS: {}, usuário: {})",
+        config.host,
+        config.port,
+        config.tls,
+        if config.username.is_empty() { "-" } else { &config.username }
+    );
+    let transport = build_transport(&config)?;
+    match transport.test_connection() {
+        Ok(true) => println!("Conexão com o servidor SMTP estabelecida."),
+        Ok(false) => return Err("O servidor SMTP não respondeu ao teste de conexão.".to_string()),
+        Err(e) => return Err(format!("Falha ao conectar: {}", e)),
+    }
+    let message = build_message(
+        &config,
+        &config.from,
+        "MedFiles - Teste de SMTP",
+        "Esta é uma mensagem de teste do MedFiles.\n".to_string(),
+        "<p>Esta é uma mensagem de teste do <strong>MedFiles</strong>.</p>".to_string(),
+    )?;
+    send_with_retry(&transport, &message)?;
+    println!("Mensagem de teste enviada para {}.", config.from);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    // How the stand-in server answers each connection, in order; the last one repeats
+    #[derive(Clone, Copy)]
+    enum Reply {
+        Accept,
+        Busy,
+        RejectRecipient,
+    }
+
+    struct SmtpStandIn {
+        config: SmtpConfig,
+        connections: Arc<Mutex<u32>>,
+        messages: Arc<Mutex<Vec<String>>>,
+    }
+
+    fn smtp_stand_in(replies: Vec<Reply>) -> SmtpStandIn {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let connections = Arc::new(Mutex::new(0));
+        let messages = Arc::new(Mutex::new(vec![]));
+        let (counter, received) = (connections.clone(), messages.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let index = {
+                    let mut count = counter.lock().unwrap();
+                    *count += 1;
+                    *count as usize - 1
+                };
+                let reply = replies[index.min(replies.len() - 1)];
+                if let Reply::Busy = reply {
+                    let _ = stream.write_all(b"421 4.3.2 servidor ocupado\r\n");
+                    continue;
+                }
+                let _ = stream.write_all(b"220 localhost ESMTP\r\n");
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                    let command = line.trim_end().to_uppercase();
+                    let answer: &[u8] = if command.starts_with("EHLO") || command.starts_with("HELO") {
+                        b"250-localhost\r\n250 OK\r\n"
+                    } else if command.starts_with("RCPT") {
+                        match reply {
+                            Reply::RejectRecipient => b"550 5.1.1 destinatario desconhecido\r\n",
+                            _ => b"250 OK\r\n",
+                        }
+                    } else if command == "DATA" {
+                        let _ = stream.write_all(b"354 envie a mensagem\r\n");
+                        let mut data = String::new();
+                        let mut data_line = String::new();
+                        loop {
+                            data_line.clear();
+                            if reader.read_line(&mut data_line).unwrap_or(0) == 0 || data_line == ".\r\n" {
+                                break;
+                            }
+                            data.push_str(&data_line);
+                        }
+                        received.lock().unwrap().push(data);
+                        b"250 OK\r\n"
+                    } else if command == "QUIT" {
+                        let _ = stream.write_all(b"221 tchau\r\n");
+                        break;
+                    } else {
+                        b"250 OK\r\n"
+                    };
+                    let _ = stream.write_all(answer);
+                    line.clear();
+                }
+            }
+        });
+        let config = SmtpConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            tls: "none".to_string(),
+            username: String::new(),
+            password: String::new(),
+            from: "MedFiles <medfiles@example.com>".to_string(),
+        };
+        SmtpStandIn { config, connections, messages }
+    }
+
+    fn send(server: &SmtpStandIn) -> Result<(), String> {
+        let transport = build_transport(&server.config)?;
+        let message = verification_message(&server.config, "paciente@example.com", "482915")?;
+        send_with_retry(&transport, &message)
+    }
+
+    #[test]
+    fn verification_email_has_plain_text_and_html_parts() {
+        let server = smtp_stand_in(vec![Reply::Accept]);
+        send(&server).unwrap();
+        let messages = server.messages.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+        let message = &messages[0];
+        assert!(message.contains("multipart/alternative"));
+        let plain = message.split("Content-Type: text/plain").nth(1).expect("parte text/plain");
+        let html = message.split("Content-Type: text/html").nth(1).expect("parte text/html");
+        assert!(plain.split("Content-Type:").next().unwrap().contains("482915"));
+        assert!(html.contains("482915"));
+        assert!(html.contains("<strong>"));
+        assert!(message.contains("To: paciente@example.com"));
+    }
+
+    #[test]
+    fn transient_failures_are_retried() {
+        let server = smtp_stand_in(vec![Reply::Busy, Reply::Accept]);
+        send(&server).unwrap();
+        assert_eq!(*server.connections.lock().unwrap(), 2);
+        assert_eq!(server.messages.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn rejected_message_is_reported_without_retrying() {
+        let server = smtp_stand_in(vec![Reply::RejectRecipient]);
+        let error = send(&server).unwrap_err();
+        assert!(error.contains("destinatario desconhecido"), "{}", error);
+        assert_eq!(*server.connections.lock().unwrap(), 1);
+        assert!(server.messages.lock().unwrap().is_empty());
+    }
+}
@@ -1,12 +1,43 @@
-use clap::{Parser, Subcommand};
-use std::fs;
-use std::io::{self, Write};
-use serde::{Deserialize, Serialize};
+mod api;
+mod audit;
+mod crypto;
+mod issuance;
+mod mail;
+mod server;
+mod signing;
+mod storage;
+mod templates;
+#[cfg(test)]
+mod testing;
+mod tui;
+mod verification;
+mod watch;
+
 use std::collections::HashMap;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
 
+use clap::{Parser, Subcommand};
 use rand::Rng;
-use std::thread;
-use std::time::{Duration, SystemTime};
+
+use medfiles::error::{self, MedError};
+use medfiles::html::{
+    ansi_to_html, generate_changes_section, generate_html_footer, generate_html_header, generate_recipe_section,
+    generate_signature_section, generate_timeline_section, parse_prescription_to_list, prescription_graphs_html,
+};
+use medfiles::prescription::{
+    extract_medication_from_return, medication_json_populator, prescription_finalizer, prescription_grabber, prescription_graphs,
+    recipe_lines,
+};
+use medfiles::site::encounter_dates;
+use medfiles::{attendance, cid10, doses, fhir, gantt, medfile, pdf, problems, profile, site};
+use medfiles::{ChangeRecord, Medications, Prescription, UserInfo};
+
+// Files skipped along the way; the command still finishes, with EXIT_PARTIAL
+static SKIPPED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
 
 #[derive(Parser)]
 #[command(name = "medfile")]
@@ -18,279 +49,611 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
-    Config,
+    Config {
+        /// Edita os dados profissionais (CRM, especialidade, clínica, assinatura)
+        #[arg(long)]
+        profile: bool,
+        /// Testa a configuração SMTP (smtp_config.json ou MEDFILES_SMTP_*)
+        #[arg(long)]
+        smtp_test: bool,
+    },
+    /// Renderiza um atendimento (.med) completo em HTML
+    Attendance {
+        /// Arquivo .med do atendimento
+        file: PathBuf,
+        /// Grava o HTML neste caminho em vez de abrir no navegador
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Imprime a prescrição atual ou um atendimento, em HTML ou PDF
+    Print {
+        /// Arquivo .med a imprimir como atendimento completo (padrão: prescrição atual)
+        file: Option<PathBuf>,
+        /// Gera um PDF neste caminho em vez de abrir o navegador
+        #[arg(long)]
+        pdf: Option<PathBuf>,
+        /// Tamanho da página do PDF
+        #[arg(long, value_enum, default_value = "a4")]
+        page_size: pdf::PageSize,
+    },
+    /// Criptografa os arquivos .med e o estado do workspace com uma senha
+    Encrypt,
+    /// Remove a criptografia do workspace
+    Decrypt,
+    /// Gera o site estático do painel do paciente a partir dos arquivos .med
+    Site {
+        /// Diretório de saída do site
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Gera a chave Ed25519 local usada para assinar os documentos emitidos
+    Keygen,
+    /// Verifica a assinatura digital de um documento HTML ou PDF emitido
+    Verify {
+        /// Documento assinado (o arquivo .sig.json ao lado é usado se existir)
+        document: PathBuf,
+    },
+    /// Confere o código (QR) de uma receita emitida contra o arquivo local
+    VerifyCode {
+        /// Conteúdo lido do QR code (MEDFILES-RX|v1|número|data|CRM|hash)
+        payload: String,
+    },
+    /// Exporta os dados do workspace para outros sistemas
+    Export {
+        #[command(subcommand)]
+        format: ExportFormat,
+    },
+    /// Importa registros de outros sistemas para um novo arquivo .med
+    Import {
+        #[command(subcommand)]
+        format: ImportFormat,
+    },
+    /// Cria o arquivo .med de um novo atendimento a partir do modelo da especialidade
+    New {
+        /// Nome do paciente; os atendimentos anteriores dele preenchem o novo arquivo
+        #[arg(long)]
+        patient: String,
+        /// Modelo a usar (geral, neurologia, psiquiatria, cardiologia ou templates/<nome>.med)
+        #[arg(long)]
+        template: Option<String>,
+        /// Apenas cria o arquivo, sem abri-lo no editor
+        #[arg(long)]
+        no_edit: bool,
+    },
+    /// Servidor local do painel, com as páginas geradas a cada acesso e recarga automática
+    Serve {
+        /// Porta do servidor
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+        /// Endereço de escuta; o padrão só aceita conexões desta máquina
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+    },
+    /// Reprocessa cada paciente sempre que um arquivo .med é criado ou salvo
+    Watch {
+        /// Diretório das páginas HTML, com uma pasta por paciente
+        #[arg(long, default_value = "site")]
+        out: PathBuf,
+    },
+    /// Lista de problemas do paciente ao longo de todos os atendimentos
+    Problems {
+        /// Grava a lista em HTML neste caminho em vez de exibi-la no terminal
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// Considera apenas os atendimentos deste paciente
+        #[arg(long)]
+        patient: Option<String>,
+    },
+    /// Linha do tempo (Gantt) das medicações a partir do histórico de prescrições
+    Gantt {
+        /// Grava o gráfico em HTML neste caminho em vez de exibi-lo no terminal
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// Considera apenas os atendimentos deste paciente
+        #[arg(long)]
+        patient: Option<String>,
+    },
+    /// Consulta a tabela CID-10 local e codifica hipóteses diagnósticas
+    Cid {
+        #[command(subcommand)]
+        action: CidCommand,
+    },
+    /// Tokens e descrição OpenAPI da API JSON servida por `medfiles serve`
+    Api {
+        #[command(subcommand)]
+        action: ApiCommand,
+    },
+    /// Consulta e verifica o log de auditoria
+    Audit {
+        #[command(subcommand)]
+        action: AuditCommand,
+    },
 }
 
-#[derive(Serialize, Deserialize)]
-struct UserInfo {
-    name: String,
-    cpf: String,
-    phone: String,
-    email: String,
+#[derive(Subcommand)]
+enum ExportFormat {
+    /// Gera um Bundle FHIR R4 (JSON) com paciente, médico, atendimentos, diagnósticos, prescrições e exames
+    Fhir {
+        /// Arquivo JSON de saída
+        #[arg(long)]
+        out: PathBuf,
+        /// Nome do paciente (padrão: nome do diretório do workspace)
+        #[arg(long)]
+        patient: Option<String>,
+    },
 }
 
-#[derive(Serialize)]
-struct Prescription {
-    command: String,
-    medication: String,
-    dosage: String,
-    dosage_observations: String,
-    posologia: String,
-    posology_observations: String,
-    objective: String,
+#[derive(Subcommand)]
+enum ImportFormat {
+    /// Converte um Bundle FHIR (Patient, Condition, MedicationStatement, AllergyIntolerance, Observation) em .med
+    Fhir {
+        /// Bundle FHIR em JSON
+        bundle: PathBuf,
+        /// Arquivo .med a criar (padrão: nome do paciente)
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
 }
 
-impl From<HashMap<String, String>> for Prescription {
-    fn from(map: HashMap<String, String>) -> Self {
-        Prescription {
-            command: map.get("command").unwrap_or(&"".to_string()).clone(),
-            medication: map.get("medication").unwrap_or(&"".to_string()).clone(),
-            dosage: map.get("dosage").unwrap_or(&"".to_string()).clone(),
-            dosage_observations: map.get("dosage_observations").unwrap_or(&"".to_string()).clone(),
-            posologia: map.get("posologia").unwrap_or(&"".to_string()).clone(),
-            posology_observations: map.get("posology_observations").unwrap_or(&"".to_string()).clone(),
-            objective: map.get("objective").unwrap_or(&"".to_string()).clone(),
-        }
-    }
+#[derive(Subcommand)]
+enum CidCommand {
+    /// Sugere códigos CID-10 para um texto livre (ignora acentos e maiúsculas)
+    Search {
+        /// Texto da hipótese, ex.: "doenca de parkinson"
+        term: String,
+    },
+    /// Confere os códigos e sugere CID-10 para as hipóteses de um arquivo .med
+    Check {
+        /// Arquivo .med do atendimento
+        file: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum ApiCommand {
+    /// Cria um token de acesso para uma ferramenta; o token é exibido uma única vez
+    Token {
+        /// Nome da ferramenta que usará o token
+        #[arg(long)]
+        name: String,
+    },
+    /// Revoga o token de uma ferramenta
+    Revoke {
+        /// Nome dado ao token na criação
+        #[arg(long)]
+        name: String,
+    },
+    /// Gera a descrição OpenAPI 3 da API
+    Openapi {
+        /// Grava a descrição neste arquivo em vez de exibi-la
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum AuditCommand {
+    /// Verifica a integridade da cadeia de hashes do log de auditoria
+    Verify,
+    /// Lista as entradas do log de auditoria
+    Show {
+        /// Mostra apenas as entradas deste paciente (nome do arquivo .med)
+        #[arg(long)]
+        patient: Option<String>,
+    },
 }
 
 fn main() {
     let cli = Cli::parse();
 
-    if let Some(cmd) = cli.command {
-        match cmd {
-            Commands::Config => {
-                if !is_new_user() {
-                    create_user();
-                } else {
-                    println!("User already configured.");
+    if let Err(e) = run(cli.command) {
+        eprintln!("{}", e);
+        std::process::exit(e.exit_code());
+    }
+    let skipped = SKIPPED.lock().map(|skipped| skipped.len()).unwrap_or(0);
+    if skipped > 0 {
+        eprintln!("{} arquivo(s) ignorado(s) por erro; veja os avisos acima.", skipped);
+        std::process::exit(error::EXIT_PARTIAL);
+    }
+}
+
+fn run(command: Option<Commands>) -> Result<(), MedError> {
+    let Some(cmd) = command else {
+        if !is_new_user() {
+            return Err(MedError::config("user_info.json", "usuário não configurado. Execute 'medfiles config'."));
+        }
+        // The full-screen interface needs a terminal; piped input keeps the simple menu
+        if io::stdin().is_terminal() && io::stdout().is_terminal() {
+            return tui::run().map_err(|e| MedError::io("", e));
+        }
+        return greet();
+    };
+    match cmd {
+        Commands::Config { smtp_test: true, .. } => {
+            let result = mail::smtp_test();
+            audit::record("configurar", "", "teste SMTP");
+            result.map_err(|e| MedError::config("", format!("teste SMTP falhou: {}", e)))
+        }
+        Commands::Config { profile, .. } => {
+            if !is_new_user() {
+                return create_user();
+            }
+            let mut user_info = read_user_info()?;
+            if profile || !profile::has_professional_profile(&user_info) {
+                if !profile {
+                    println!("Perfil profissional incompleto. Vamos completar os dados.");
                 }
+                collect_professional_info(&mut user_info)?;
+                save_user_info(&user_info)?;
+                audit::record("configurar", "", "perfil profissional atualizado");
+                println!("Perfil profissional atualizado!");
+            } else {
+                println!("User already configured.");
             }
+            Ok(())
+        }
+        Commands::Attendance { file, out } => attendance_viewer(&file, out.as_deref()),
+        Commands::Print { file, pdf, page_size } => print_command(file.as_deref(), pdf.as_deref(), page_size),
+        Commands::Encrypt => {
+            let count = storage::encrypt_workspace().map_err(|e| MedError::config("", format!("falha ao criptografar o workspace: {}", e)))?;
+            audit::record("criptografar", "", &format!("{} arquivo(s)", count));
+            println!("Workspace criptografado: {} arquivo(s).", count);
+            Ok(())
+        }
+        Commands::Decrypt => {
+            let count = storage::decrypt_workspace().map_err(|e| MedError::config("", format!("falha ao descriptografar o workspace: {}", e)))?;
+            audit::record("descriptografar", "", &format!("{} arquivo(s)", count));
+            println!("Workspace descriptografado: {} arquivo(s).", count);
+            Ok(())
+        }
+        Commands::Site { out } => site_generator(&out),
+        Commands::Keygen => {
+            let fingerprint = signing::generate_key().map_err(|e| MedError::config(signing::KEY_PATH, e))?;
+            audit::record("configurar", "", "chave de assinatura gerada");
+            println!("Chave de assinatura gravada em {} (impressão digital {}).", signing::KEY_PATH, fingerprint);
+            Ok(())
+        }
+        Commands::Verify { document } => verify_command(&document),
+        Commands::VerifyCode { payload } => verify_code_command(&payload),
+        Commands::Export { format: ExportFormat::Fhir { out, patient } } => fhir_export(&out, patient.as_deref()),
+        Commands::Import { format: ImportFormat::Fhir { bundle, out } } => fhir_import(&bundle, out.as_deref()),
+        Commands::New { patient, template, no_edit } => new_encounter(&patient, template.as_deref(), !no_edit),
+        Commands::Serve { port, host } => server::run(&host, port).map_err(|e| MedError::config("", e)),
+        Commands::Watch { out } => watch::run(&out).map_err(|e| MedError::config("", e)),
+        Commands::Problems { out, patient } => problem_list(out.as_deref(), patient.as_deref()),
+        Commands::Gantt { out, patient } => medication_gantt(out.as_deref(), patient.as_deref()),
+        Commands::Cid { action: CidCommand::Search { term } } => {
+            cid_search(&term);
+            Ok(())
+        }
+        Commands::Cid { action: CidCommand::Check { file } } => cid_check(&file),
+        Commands::Api { action: ApiCommand::Token { name } } => {
+            let token = api::create_token(&name).map_err(|e| MedError::config(api::TOKENS_PATH, e))?;
+            audit::record("configurar", "", &format!("token da API criado para {}", name));
+            println!("Token de {} (guarde-o agora; ele não será exibido novamente):\n{}", name, token);
+            println!("Use o cabeçalho 'Authorization: Bearer <token>' nas requisições a {}.", api::PREFIX);
+            Ok(())
+        }
+        Commands::Api { action: ApiCommand::Revoke { name } } => {
+            if !api::revoke_token(&name).map_err(|e| MedError::config(api::TOKENS_PATH, e))? {
+                return Err(MedError::config(api::TOKENS_PATH, format!("nenhum token chamado '{}'", name)));
+            }
+            audit::record("configurar", "", &format!("token da API revogado para {}", name));
+            println!("Token de {} revogado.", name);
+            Ok(())
+        }
+        Commands::Api { action: ApiCommand::Openapi { out } } => {
+            let json = serde_json::to_string_pretty(&api::openapi()).map_err(|e| MedError::parse("openapi.json", e))?;
+            match out {
+                Some(path) => {
+                    fs::write(&path, json).map_err(|e| MedError::io(&path, e))?;
+                    println!("Descrição OpenAPI salva em {}", path.display());
+                }
+                None => println!("{}", json),
+            }
+            Ok(())
+        }
+        Commands::Audit { action: AuditCommand::Verify } => {
+            let verified = audit::verify().map_err(|e| MedError::invalid(audit::AUDIT_PATH, format!("log de auditoria comprometido: {}", e)))?;
+            if verified.keyed {
+                println!("Log de auditoria íntegro: {} entrada(s) verificada(s).", verified.entries);
+            } else {
+                println!("Cadeia de hashes consistente: {} entrada(s) verificada(s).", verified.entries);
+                eprintln!(
+                    "Aviso: workspace sem criptografia e sem MEDFILES_AUDIT_KEY; quem pode alterar {} e {} pode refazer um log coerente, então a integridade não é garantida.",
+                    audit::AUDIT_PATH,
+                    audit::HEAD_PATH
+                );
+            }
+            Ok(())
+        }
+        Commands::Audit { action: AuditCommand::Show { patient } } => {
+            audit::show(patient.as_deref()).map_err(|e| MedError::state(audit::AUDIT_PATH, e))
         }
-    } else if is_new_user() {
-        greet();
-    } else {
-        println!("Usuário não configurado. Execute 'medfiles config'.");
     }
 }
 
-fn greet() {
-    let content = fs::read_to_string("user_info.json").expect("Falha ao ler user_info.json");
-    let user_info: UserInfo = serde_json::from_str(&content).expect("Falha ao parsear user_info.json");
+fn greet() -> Result<(), MedError> {
+    let user_info = read_user_info()?;
     let first_name = user_info.name.split_whitespace().next().unwrap_or("Usuário");
     println!("Olá, {}!", first_name);
     println!("O que você deseja fazer?");
     println!("1. Ver atendimentos");
     println!("2. Ver prescrições");
-    print!("Digite sua escolha (1 ou 2): ");
-    io::stdout().flush().unwrap();
-    let mut choice = String::new();
-    io::stdin().read_line(&mut choice).unwrap();
-    let choice = choice.trim();
+    let choice = prompt("Digite sua escolha (1 ou 2): ")?;
     if choice == "1" {
         println!("Recurso ainda em desenvolvimento.");
     } else if choice == "2" {
-        let (prescriptions, prescription_returns, file_count) = conduta_handler();
+        let (prescriptions, prescription_returns, file_count) = conduta_handler()?;
         println!("{}", prescriptions);
-        print!("Deseja imprimir a prescrição? (s/n): ");
-        io::stdout().flush().unwrap();
-        let mut print_choice = String::new();
-        io::stdin().read_line(&mut print_choice).unwrap();
-        let print_choice = print_choice.trim().to_lowercase();
+        for (path, _) in medfile_finder() {
+            audit::record_file("visualizar", &path, "prescrições no terminal");
+        }
+        let print_choice = prompt("Deseja imprimir a prescrição? (s/n): ")?.to_lowercase();
         if print_choice == "s" || print_choice == "sim" {
-            // Load history data for the graph
-            let history_path = "history.json";
-            let history: Vec<(String, String, String, String, String, String)> = if std::path::Path::new(history_path).exists() {
-                let content = fs::read_to_string(history_path).unwrap_or("[]".to_string());
-                serde_json::from_str(&content).unwrap_or(vec![])
-            } else {
-                vec![]
-            };
-            prescription_printer(&prescription_returns, &history, file_count);
+            print_prescription_html(&prescription_returns, file_count);
         }
     } else {
         println!("Escolha inválida.");
     }
+    Ok(())
+}
+
+fn print_prescription_html(prescription_returns: &[String], file_count: usize) {
+    // The dose chart is rebuilt from the encounters themselves
+    let encounters = load_encounters(&medfile_finder());
+    prescription_printer(prescription_returns, &encounters, file_count);
 }
 
 fn is_new_user() -> bool {
     fs::metadata("user_info.json").is_ok()
 }
 
+fn read_user_info() -> Result<UserInfo, MedError> {
+    let content = storage::read_to_string("user_info.json").map_err(|e| MedError::read("user_info.json", e))?;
+    serde_json::from_str(&content).map_err(|e| MedError::config("user_info.json", e))
+}
+
+fn load_user_info() -> Option<UserInfo> {
+    read_user_info().ok()
+}
+
+fn signature_image(user: &UserInfo) -> Option<Vec<u8>> {
+    if user.signature_image.is_empty() {
+        return None;
+    }
+    fs::read(&user.signature_image).ok()
+}
+
+fn load_doctor() -> (Option<UserInfo>, Option<Vec<u8>>) {
+    // The doctor and signature image shown on the documents; both are optional
+    let user = load_user_info();
+    let image = user.as_ref().and_then(signature_image);
+    (user, image)
+}
+
+fn save_user_info(user_info: &UserInfo) -> Result<(), MedError> {
+    let json = serde_json::to_string_pretty(user_info).map_err(|e| MedError::config("user_info.json", e))?;
+    storage::write("user_info.json", json).map_err(|e| MedError::io("user_info.json", e))
+}
+
 fn generate_token() -> String {
     let mut rng = rand::thread_rng();
     format!("{:06}", rng.gen_range(0..1000000))
 }
 
 fn send_email(to: &str, token: &str) -> bool {
-    // For demo, use a dummy SMTP. In real, configure with actual credentials.
-    // This is placeholder; actual implementation needs SMTP server details.
-    println!("Enviando email para {} com token {}", to, token);
-    // Simulate success
-    true
+    println!("Enviando email para {}...", to);
+    match mail::send_verification_email(to, token) {
+        Ok(()) => {
+            println!("Email enviado. Verifique sua caixa de entrada.");
+            true
+        }
+        Err(e) => {
+            eprintln!("Falha ao enviar email: {}", e);
+            false
+        }
+    }
 }
 
-fn verify_token(_expected_token: &str) -> bool {
-    let start_time = SystemTime::now();
-    let duration = Duration::from_secs(600); // 10 minutes
-
+fn verify_token(pending: &mut verification::PendingVerification) -> Result<bool, MedError> {
     loop {
-        let elapsed = start_time.elapsed().unwrap();
-        if elapsed >= duration {
+        let remaining = pending.remaining_secs();
+        let label = format!("Tempo restante: {}:{:02} - Digite o token (ou 'r' para reenviar): ", remaining / 60, remaining % 60);
+        let Some(input) = read_input(&label)? else {
+            // Input closed: keep the pending verification so `config` can resume it
+            println!();
+            return Ok(false);
+        };
+        let input = input.as_str();
+
+        // Expiry is checked after the (blocking) read, when the code is actually entered
+        if pending.is_expired() {
             println!("Tempo expirado. Gere um novo token.");
-            return false;
-        }
-
-        let remaining = duration - elapsed;
-        let minutes = remaining.as_secs() / 60;
-        let seconds = remaining.as_secs() % 60;
-        print!("\rTempo restante: {}:{:02} - Digite o token: ", minutes, seconds);
-        io::stdout().flush().unwrap();
-
-        let mut input = String::new();
-        // Non-blocking read? For simplicity, use a short timeout or poll.
-        // But in Rust, stdin is blocking. For demo, use a loop with sleep.
-        thread::sleep(Duration::from_secs(1));
-
-        // To make it work, perhaps use a separate thread or something, but keep simple.
-        // For now, just prompt once.
-        print!("Digite o token: ");
-        io::stdout().flush().unwrap();
-        io::stdin().read_line(&mut input).unwrap();
-        let input = input.trim();
-        if input.len() == 5 && input.chars().all(|c| c.is_alphanumeric()) {
-            println!("\nToken válido!");
-            return true;
-        } else {
-            println!("Token inválido. Deve ter exatamente 5 números ou letras. Tente novamente.");
+            verification::clear_pending();
+            return Ok(false);
+        }
+
+        if input.eq_ignore_ascii_case("r") {
+            if let Some(refusal) = pending.resend_refusal() {
+                println!("{}", refusal);
+                continue;
+            }
+            let token = generate_token();
+            if send_email(&pending.user.email, &token) {
+                pending.reissue(&token);
+                if let Err(e) = verification::save_pending(pending) {
+                    eprintln!("Aviso: {}", e);
+                }
+            }
+            continue;
+        }
+
+        if pending.matches(input) {
+            println!("Token válido!");
+            verification::clear_pending();
+            return Ok(true);
+        }
+
+        let left = pending.register_failure();
+        if let Err(e) = verification::save_pending(pending) {
+            eprintln!("Aviso: {}", e);
+        }
+        if left == 0 {
+            println!("Número máximo de tentativas atingido.");
+            verification::clear_pending();
+            return Ok(false);
         }
+        println!("Token inválido. Restam {} tentativa(s).", left);
+    }
+}
+
+fn finish_verification(mut pending: verification::PendingVerification) -> Result<(), MedError> {
+    if verify_token(&mut pending)? {
+        save_user_info(&pending.user)?;
+        audit::record("configurar", "", "conta verificada");
+        println!("Usuário configurado com sucesso!");
+    } else {
+        println!("Token inválido ou expirado. Tente novamente.");
     }
+    Ok(())
 }
 
-fn create_user() {
+fn read_input(label: &str) -> Result<Option<String>, MedError> {
+    // None when the input is closed (Ctrl+D or end of a pipe)
+    print!("{}", label);
+    io::stdout().flush().map_err(|e| MedError::io("stdout", e))?;
+    let mut input = String::new();
+    match io::stdin().read_line(&mut input).map_err(|e| MedError::io("stdin", e))? {
+        0 => Ok(None),
+        _ => Ok(Some(input.trim().to_string())),
+    }
+}
+
+fn prompt(label: &str) -> Result<String, MedError> {
+    read_input(label)?.ok_or_else(|| MedError::io("stdin", io::Error::new(io::ErrorKind::UnexpectedEof, "entrada encerrada antes da resposta")))
+}
+
+fn prompt_valid(label: &str, validate: fn(&str) -> Result<String, String>) -> Result<String, MedError> {
+    loop {
+        match validate(&prompt(label)?) {
+            Ok(value) => return Ok(value),
+            Err(message) => println!("{}", message),
+        }
+    }
+}
+
+fn collect_professional_info(user: &mut UserInfo) -> Result<(), MedError> {
+    println!("Dados profissionais (exibidos nas prescrições e documentos):");
+    user.crm = prompt_valid("Digite seu número de CRM: ", profile::validate_crm)?;
+    user.crm_uf = prompt_valid("Digite a UF do CRM: ", profile::validate_uf)?;
+    user.specialty = prompt("Digite sua especialidade (opcional): ")?.to_uppercase();
+    user.rqe = prompt_valid("Digite seu RQE (opcional): ", profile::validate_rqe)?;
+    user.clinic_name = prompt("Digite o nome da clínica: ")?;
+    user.clinic_address = prompt("Digite o endereço da clínica: ")?;
+    user.clinic_phone = prompt("Digite o telefone da clínica: ")?;
+    user.signature_image = prompt_valid("Caminho da imagem da assinatura (PNG/JPEG, opcional): ", profile::validate_signature_image)?;
+    Ok(())
+}
+
+fn create_user() -> Result<(), MedError> {
+    if let Some(pending) = verification::load_pending() {
+        if pending.is_expired() {
+            println!("A verificação pendente para {} expirou.", pending.user.email);
+            verification::clear_pending();
+        } else {
+            let resume = prompt(&format!("Há uma verificação pendente para {}. Deseja continuar? (s/n): ", pending.user.email))?.to_lowercase();
+            if resume == "s" || resume == "sim" {
+                return finish_verification(pending);
+            }
+            verification::clear_pending();
+        }
+    }
+
     println!("Bem-vindo! Vamos configurar sua conta.");
-    print!("Digite seu nome completo: ");
-    io::stdout().flush().unwrap();
-    let mut name = String::new();
-    io::stdin().read_line(&mut name).unwrap();
-    let name = name.trim().to_string();
-
-    print!("Digite seu CPF: ");
-    io::stdout().flush().unwrap();
-    let mut cpf = String::new();
-    io::stdin().read_line(&mut cpf).unwrap();
-    let cpf = cpf.trim().to_string();
-
-    print!("Digite seu número de telefone: ");
-    io::stdout().flush().unwrap();
-    let mut phone = String::new();
-    io::stdin().read_line(&mut phone).unwrap();
-    let phone = phone.trim().to_string();
-
-    print!("Digite seu email: ");
-    io::stdout().flush().unwrap();
-    let mut email = String::new();
-    io::stdin().read_line(&mut email).unwrap();
-    let email = email.trim().to_string();
+    let name = prompt("Digite seu nome completo: ")?;
+    let cpf = prompt("Digite seu CPF: ")?;
+    let phone = prompt("Digite seu número de telefone: ")?;
+    let email = prompt("Digite seu email: ")?;
 
     println!("Escolha o método de confirmação:");
     println!("1. SMS (indisponível - taxa adicional)");
     println!("2. Email");
-    print!("Digite sua escolha (1 ou 2): ");
-    io::stdout().flush().unwrap();
-    let mut choice = String::new();
-    io::stdin().read_line(&mut choice).unwrap();
-    let choice = choice.trim();
+    let choice = prompt("Digite sua escolha (1 ou 2): ")?;
 
     if choice == "1" {
         println!("SMS indisponível. Escolha email.");
     } else if choice == "2" {
+        let mut user_info = UserInfo { name, cpf, phone, email, ..Default::default() };
+        collect_professional_info(&mut user_info)?;
         let token = generate_token();
-        if send_email(&email, &token) {
-            if verify_token(&token) {
-                let user_info = UserInfo { name, cpf, phone, email };
-                let json = serde_json::to_string_pretty(&user_info).unwrap();
-                fs::write("user_info.json", json).unwrap();
-                println!("Usuário configurado com sucesso!");
-            } else {
-                println!("Token inválido ou expirado. Tente novamente.");
+        if send_email(&user_info.email, &token) {
+            let pending = verification::PendingVerification::new(user_info, &token);
+            if let Err(e) = verification::save_pending(&pending) {
+                eprintln!("Aviso: {}", e);
             }
+            finish_verification(pending)?;
         } else {
-            println!("Falha ao enviar email. Tente novamente.");
+            eprintln!("Falha ao enviar email. Tente novamente.");
         }
     } else {
         println!("Escolha inválida.");
     }
+    Ok(())
 }
 
-fn is_prescription(line: &str) -> bool {
-    let prefixes = ["!PRESCREVO", "!AUMENTO", "!INCREMENTO", "!DECREMENTO", "!SUSPENDO", "!DESMAME"];
-    prefixes.iter().any(|&p| line.trim().starts_with(p))
+fn report_skipped(error: MedError) {
+    // Goes to stderr so the regular output of a command stays usable by scripts;
+    // a file read more than once in the same command is reported once
+    if let Ok(mut skipped) = SKIPPED.lock() {
+        if skipped.iter().any(|path| path == error.path()) {
+            return;
+        }
+        skipped.push(error.path().to_path_buf());
+    }
+    eprintln!("Aviso: {}", error);
 }
 
-fn extract_medication_from_return(return_msg: &str) -> Option<String> {
-    // Parse medication name from return messages like:
-    // "ADICIONADO MEDICATION, DOSAGE, : DOSAGE_OBS POSOLOGIA à lista de medicações em uso;"
-    // "Mudanças para MEDICATION:"
-
-    if return_msg.starts_with("ADICIONADO ") {
-        // Extract from "ADICIONADO MEDICATION, ..."
-        if let Some(comma_pos) = return_msg.find(',') {
-            let medication_part = &return_msg[11..comma_pos]; // Skip "ADICIONADO "
-            return Some(medication_part.trim().to_string());
+fn medfile_finder() -> Vec<(PathBuf, SystemTime)> {
+    match medfile::find_med_files(Path::new(".")) {
+        Ok((files, skipped)) => {
+            skipped.into_iter().for_each(report_skipped);
+            files
         }
-    } else if return_msg.starts_with("Mudanças para ") {
-        // Extract from "Mudanças para MEDICATION:"
-        if let Some(colon_pos) = return_msg.find(':') {
-            let medication_part = &return_msg[14..colon_pos]; // Skip "Mudanças para "
-            return Some(medication_part.trim().to_string());
+        Err(e) => {
+            report_skipped(e);
+            vec![]
         }
     }
-
-    None
 }
 
-fn prescription_grabber(conduta_lines: Vec<String>) -> Vec<String> {
-    conduta_lines.into_iter().filter(|line| is_prescription(line)).collect()
+fn load_encounters(files: &[(PathBuf, SystemTime)]) -> Vec<site::Encounter> {
+    let (encounters, skipped) = site::load_encounters(files, |path| storage::read_to_string(path));
+    skipped.into_iter().for_each(report_skipped);
+    encounters
 }
 
-fn medfile_finder() -> Vec<(std::path::PathBuf, std::time::SystemTime)> {
-    let mut files = vec![];
-    for entry in fs::read_dir(".").unwrap() {
-        let entry = entry.unwrap();
-        let path = entry.path();
-        if path.extension() == Some(std::ffi::OsStr::new("med")) {
-            let metadata = entry.metadata().unwrap();
-            let modified = metadata.modified().unwrap();
-            files.push((path, modified));
-        }
-    }
-    files.sort_by_key(|&(_, time)| time);
-    files
-}
-
-fn conduta_handler() -> (String, Vec<String>, usize) {
+fn conduta_handler() -> Result<(String, Vec<String>, usize), MedError> {
+    // Read before anything is written, so a corrupted state file is never overwritten
+    let mut history = load_history()?;
+    let mut medications = load_medications()?;
     let files = medfile_finder();
     let file_count = files.len();
     let mut results = vec![];
-    let mut all_changes: Vec<(String, String, String, String, String, String)> = vec![];
+    let mut all_changes: Vec<ChangeRecord> = vec![];
     let mut latest_prescription_returns: std::collections::HashMap<String, String> = std::collections::HashMap::new();
     for (path, modified) in files {
-        let content = fs::read_to_string(&path).unwrap();
-        let lines: Vec<&str> = content.lines().collect();
-        let mut in_conduta = false;
-        let mut conduta_lines = vec![];
-        for line in lines {
-            if line.trim() == "[CONDUTA]" {
-                in_conduta = true;
-            } else if line.trim().starts_with('[') && in_conduta {
-                break;
-            } else if in_conduta {
-                conduta_lines.push(line.to_string());
+        let content = match storage::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) => {
+                report_skipped(MedError::read(&path, e));
+                continue;
             }
-        }
+        };
+        let conduta_lines = medfile::MedDocument::parse(&content).section_lines("CONDUTA");
         let prescriptions = prescription_grabber(conduta_lines);
-        let (output, prescription_returns, changes) = prescription_handler(prescriptions, modified);
+        audit::record_file("processar", &path, &format!("{} prescrição(ões) na CONDUTA", prescriptions.len()));
+        let (output, prescription_returns, changes) = prescription_handler(prescriptions, &mut medications, modified)?;
         all_changes.extend(changes);
 
         // Filter to keep only the most recent change per medication
@@ -306,698 +669,684 @@ fn conduta_handler() -> (String, Vec<String>, usize) {
             results.push(format!("Arquivo: {} (Modificado: {})\n{}", path.display(), date_str, output));
         }
     }
-    // Append current changes to history.json
-    let history_path = "history.json";
-    let mut history: Vec<(String, String, String, String, String, String)> = if std::path::Path::new(history_path).exists() {
-        let content = fs::read_to_string(history_path).unwrap_or("[]".to_string());
-        serde_json::from_str(&content).unwrap_or(vec![])
-    } else {
-        vec![]
-    };
+    // Every run replays all the files, so most of these changes are already in history.json
     history.extend(all_changes);
-    let history_json = serde_json::to_string_pretty(&history).unwrap();
-    fs::write(history_path, history_json).unwrap();
+    let mut seen = std::collections::HashSet::new();
+    history.retain(|record| seen.insert(record.clone()));
+    write_state("history.json", &history)?;
 
     let graph = prescription_graphs(&history);
     let filtered_returns: Vec<String> = latest_prescription_returns.values().cloned().collect();
-    (format!("{}\n{}", graph, results.join("\n---\n")), filtered_returns, file_count)
+    Ok((format!("{}\n{}", graph, results.join("\n---\n")), filtered_returns, file_count))
 }
 
-fn prescription_handler(prescriptions: Vec<String>, modified: std::time::SystemTime) -> (String, Vec<String>, Vec<(String, String, String, String, String, String)>) {
-    let mut medications: HashMap<String, HashMap<String, String>> = if is_medication() {
-        let content = fs::read_to_string("medications.json").unwrap_or("".to_string());
-        serde_json::from_str(&content).unwrap_or(HashMap::new())
-    } else {
-        HashMap::new()
-    };
+fn read_state<T: serde::de::DeserializeOwned + Default>(path: &str) -> Result<T, MedError> {
+    if !Path::new(path).exists() {
+        return Ok(T::default());
+    }
+    let content = storage::read_to_string(path).map_err(|e| MedError::read(path, e))?;
+    serde_json::from_str(&content).map_err(|e| MedError::state(path, format!("{}; corrija ou remova o arquivo", e)))
+}
+
+fn write_state(path: &str, value: &impl serde::Serialize) -> Result<(), MedError> {
+    let json = serde_json::to_string_pretty(value).map_err(|e| MedError::state(path, e))?;
+    storage::write(path, json).map_err(|e| MedError::io(path, e))
+}
+
+fn load_history() -> Result<Vec<ChangeRecord>, MedError> {
+    read_state("history.json")
+}
+
+fn load_medications() -> Result<Medications, MedError> {
+    read_state("medications.json")
+}
+
+fn medication_state_replay(files: &[(PathBuf, SystemTime)]) -> (Medications, Vec<ChangeRecord>) {
+    // Rebuild the medication state from scratch without touching medications.json
+    medfiles::prescription::replay(&load_encounters(files))
+}
+
+fn prescription_handler(
+    prescriptions: Vec<String>,
+    medications: &mut Medications,
+    modified: std::time::SystemTime,
+) -> Result<(String, Vec<String>, Vec<ChangeRecord>), MedError> {
     let mut processed: Vec<Prescription> = vec![];
     for line in prescriptions {
         let item = medication_json_populator(&line);
         processed.push(item);
     }
-    let (returns, recipes, changes) = prescription_finalizer(processed, &mut medications, chrono::DateTime::<chrono::Local>::from(modified));
+    let (returns, recipes, changes) = prescription_finalizer(processed, medications, chrono::DateTime::<chrono::Local>::from(modified));
     // Save updated medications
-    let json = serde_json::to_string_pretty(&medications).unwrap();
-    fs::write("medications.json", json).unwrap();
+    write_state("medications.json", medications)?;
     // Optionally save recipes to file
     if !recipes.is_empty() {
         let recipe_content = recipes.join("\n\n");
-        fs::write("prescription_recipe.txt", recipe_content).unwrap();
+        storage::write("prescription_recipe.txt", recipe_content).map_err(|e| MedError::io("prescription_recipe.txt", e))?;
     }
-    (returns.join("\n"), returns, changes)
+    Ok((returns.join("\n"), returns, changes))
 }
 
-fn is_medication() -> bool {
-    fs::metadata("medications.json").is_ok()
-}
 
-fn medication_json_creator() {
-    fs::write("medications.json", "[]").unwrap();
-}
+fn prescription_printer(prescription_returns: &[String], encounters: &[site::Encounter], file_count: usize) {
+    // Read prescription recipe content
+    let recipe_content = storage::read_to_string("prescription_recipe.txt")
+        .unwrap_or_else(|_| "Nenhuma receita encontrada.".to_string());
+    let (html_content, issued_number) = prescription_document(&recipe_content, prescription_returns, encounters, file_count);
+
+    let html_content = match signing::sign_page(&signing::page_name(&issued_number), &html_content) {
+        Ok(signed) => signed,
+        Err(e) => {
+            eprintln!("{}", MedError::config(signing::KEY_PATH, e));
+            return;
+        }
+    };
 
-fn medication_json_populator(line: &str) -> Prescription {
-    let map = medication_list_tokenizer(line);
-    Prescription::from(map)
+    for (path, _) in medfile_finder() {
+        audit::record_file("imprimir", &path, &format!("prescrição nº {} no navegador", issued_number));
+    }
+    // Served from memory, so there is no temporary file to clean up
+    if let Err(e) = serve_html_once(&html_content) {
+        eprintln!("{}", e);
+    }
 }
 
-fn calculate_difference(current: &Prescription, prev: &HashMap<String, String>) -> String {
-    // Simple parsing for dosage_observations
-    let current_dosage = parse_dosage(&current.dosage_observations);
-    let prev_dosage = parse_dosage(&prev.get("dosage_observations").unwrap_or(&"".to_string()));
-    let diff = current_dosage - prev_dosage;
-    format!("{:.1} COMPRIMIDOS", diff)
+fn prescription_document(
+    recipe_content: &str,
+    prescription_returns: &[String],
+    encounters: &[site::Encounter],
+    file_count: usize,
+) -> (String, String) {
+    // Format prescription returns with ANSI to HTML conversion
+    let returns_content = if prescription_returns.is_empty() {
+        "Nenhuma alteração encontrada.".to_string()
+    } else {
+        prescription_returns
+            .iter()
+            .map(|line| ansi_to_html(line))
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+
+    // Parse prescription recipe into numbered list
+    let recipe_list_html = parse_prescription_to_list(recipe_content);
+
+    // Generate the dose-over-time charts
+    let graph_html = prescription_graphs_html(encounters);
+
+    // Create HTML content using modular functions
+    let (doctor, signature) = load_doctor();
+    let mut html_content = generate_html_header("Prescrição Médica", doctor.as_ref());
+    html_content.push_str(&generate_recipe_section(&recipe_list_html));
+    html_content.push_str(&generate_signature_section(doctor.as_ref(), signature.as_deref()));
+    let issued_number = match issuance::issue(recipe_content) {
+        Ok(issued) => {
+            html_content.push_str(&issuance::generate_verification_html(&issued));
+            issued.number
+        }
+        Err(e) => {
+            eprintln!("Falha ao numerar a receita: {}", e);
+            String::new()
+        }
+    };
+    if file_count >= 2 {
+        html_content.push_str(&generate_changes_section(&returns_content));
+        html_content.push_str(&generate_timeline_section(&graph_html));
+        let today = chrono::Local::now().date_naive();
+        let bars = gantt::bars_from_doses(&doses::dose_history(encounters), today);
+        html_content.push_str(&format!(
+            "\n    <div class=\"prescription\">\n        <h2>Exposição às Medicações</h2>\n        {}\n    </div>\n",
+            gantt::generate_gantt_html(&bars, &encounter_dates(encounters.iter().map(|e| e.modified)), today)
+        ));
+    }
+    html_content.push_str(&generate_html_footer());
+    (html_content, issued_number)
 }
 
-fn prescription_graphs(changes: &Vec<(String, String, String, String, String, String)>) -> String {
-    if changes.is_empty() {
-        return String::new();
+fn open_in_editor(path: &Path) -> Result<String, String> {
+    if storage::is_encrypted_workspace() {
+        // The editor would only see the sealed bytes, and decrypted records never go to disk
+        return Err("Workspace criptografado: use 'medfiles decrypt' antes de editar os arquivos .med.".to_string());
     }
-    let mut medications: std::collections::HashSet<String> = std::collections::HashSet::new();
-    for (med, _, _, _, _, _) in changes {
-        medications.insert(med.clone());
+    let editor = std::env::var("VISUAL").or_else(|_| std::env::var("EDITOR")).unwrap_or_else(|_| "vi".to_string());
+    match std::process::Command::new(&editor).arg(path).status() {
+        Ok(_) => {
+            audit::record_file("editar", path, &format!("aberto em {}", editor));
+            Ok(editor)
+        }
+        Err(e) => Err(format!("Falha ao abrir {}: {}", editor, e)),
     }
-    let mut sorted_changes: Vec<_> = changes.iter().map(|(med, time_str, field, old, new, cmd)| {
-        let time = chrono::NaiveDateTime::parse_from_str(time_str, "%Y-%m-%d %H:%M:%S").unwrap_or(chrono::NaiveDateTime::from_timestamp_opt(0, 0).unwrap());
-        (med.clone(), time, field.clone(), old.clone(), new.clone(), cmd.clone())
-    }).collect::<Vec<_>>();
-    sorted_changes.sort_by_key(|(_, time, _, _, _, _)| *time);
-    let mut unique_times: Vec<_> = sorted_changes.iter().map(|(_, time, _, _, _, _)| *time).collect();
-    unique_times.sort();
-    unique_times.dedup();
-    let mut graph = String::from("Gráfico de Evolução das Prescrições:\n");
-    for med in medications {
-        graph.push_str(&format!("{}: ", med));
-        let med_changes: Vec<_> = sorted_changes.iter().filter(|(m, _, _, _, _, _)| m == &med).collect();
-        // Group by timestamp and command
-        let mut unique_events: Vec<(chrono::NaiveDateTime, String)> = vec![];
-        for (_, time, _, _, _, command) in &med_changes {
-            if !unique_events.iter().any(|(t, c)| t == time && c == command) {
-                unique_events.push((*time, command.clone()));
+}
+
+fn serve_html_once(html_content: &str) -> Result<(), MedError> {
+    // Every page opened in the browser goes through here: records are never written to temporary files
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").map_err(|e| MedError::io("127.0.0.1", e))?;
+    let url = format!("http://{}/", listener.local_addr().map_err(|e| MedError::io("127.0.0.1", e))?);
+    println!("Documento disponível em {} (aguardando o navegador)", url);
+    open_in_browser(&url);
+
+    let _ = listener.set_nonblocking(true);
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(120);
+    while std::time::Instant::now() < deadline {
+        match listener.accept() {
+            Ok((mut stream, _)) => {
+                let _ = stream.set_nonblocking(false);
+                let mut buffer = [0u8; 2048];
+                let read = io::Read::read(&mut stream, &mut buffer).unwrap_or(0);
+                let request = String::from_utf8_lossy(&buffer[..read]);
+                if !request.starts_with("GET / ") {
+                    let _ = stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+                    continue;
+                }
+                let header = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+                    html_content.len()
+                );
+                let _ = stream.write_all(header.as_bytes());
+                let _ = stream.write_all(html_content.as_bytes());
+                return Ok(());
             }
-        }
-        unique_events.sort_by_key(|(time, _)| *time);
-        let spacing = if unique_events.len() > 1 { 80 / (unique_events.len() - 1) } else { 10 };
-        let mut line = String::new();
-        let mut positions = vec![];
-        for (i, (time, command)) in unique_events.iter().enumerate() {
-            let pos = i * spacing;
-            let date_str = time.format("%d/%m").to_string();
-            let symbol = match command.as_str() {
-                "INCREASE" => '▲',
-                "DECREASE" => '▼',
-                "PRESCRIBE" => '●',
-                _ => '●',
-            };
-            let label = format!("{}{}", date_str, symbol);
-            positions.push((pos, label));
-        }
-        let mut last_pos = 0;
-        for (pos, label) in positions {
-            for _ in last_pos..pos {
-                line.push('─');
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                std::thread::sleep(std::time::Duration::from_millis(100));
             }
-            line.push_str(&label);
-            last_pos = pos + label.len();
+            Err(e) => return Err(MedError::io("127.0.0.1", e)),
         }
-        graph.push_str(&line);
-        graph.push('\n');
     }
-
-    graph
+    println!("O navegador não abriu o documento a tempo.");
+    Ok(())
 }
 
-fn generate_diff(medication: &str, old: &HashMap<String, String>, new: &Prescription) -> String {
-    let mut diff_lines = vec![];
-    let fields = vec![
-        ("dosage", &new.dosage),
-        ("dosage_observations", &new.dosage_observations),
-        ("posologia", &new.posologia),
-        ("posology_observations", &new.posology_observations),
-    ];
-    for (field, new_value) in fields {
-        let old_value = old.get(field).map(|s| s.as_str()).unwrap_or("");
-        if old_value != new_value {
-            diff_lines.push(format!("\x1b[31m- {}: {}\x1b[0m", field, old_value));
-            diff_lines.push(format!("\x1b[32m+ {}: {}\x1b[0m", field, new_value));
-        }
+fn open_in_browser(path: &str) {
+    #[cfg(target_os = "linux")]
+    {
+        let _ = std::process::Command::new("xdg-open")
+            .arg(path)
+            .status();
     }
-    if diff_lines.is_empty() {
-        format!("Nenhuma mudança detectada para {}", medication)
-    } else {
-        format!("Mudanças para {}:\n{}", medication, diff_lines.join("\n"))
+
+    #[cfg(target_os = "windows")]
+    {
+        let _ = std::process::Command::new("cmd")
+            .args(["/C", "start", path])
+            .status();
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        let _ = std::process::Command::new("open")
+            .arg(path)
+            .status();
     }
 }
 
-fn ansi_to_html(text: &str) -> String {
-    text.replace("\x1b[31m", "<span class=\"removed\">")
-        .replace("\x1b[32m", "<span class=\"added\">")
-        .replace("\x1b[0m", "</span>")
+fn encounter_history(file: &Path) -> Vec<site::Encounter> {
+    // Encounters of the workspace up to and including `file`, for trend charts
+    let files = medfile_finder();
+    let Some(position) = files.iter().position(|(path, _)| fs::canonicalize(path).ok() == fs::canonicalize(file).ok()) else {
+        return vec![];
+    };
+    load_encounters(&files[..=position])
 }
 
-fn parse_dosage(dosage: &str) -> f64 {
-    // Simple parser for [number] or [fraction]
-    let cleaned = dosage.trim_start_matches('[').trim_end_matches(']').trim();
-    let first_part = cleaned.split_whitespace().next().unwrap_or("");
-    if let Some(slash_pos) = first_part.find('/') {
-        let parts: Vec<&str> = first_part.split('/').collect();
-        if parts.len() == 2 {
-            let num: f64 = parts[0].parse().unwrap_or(0.0);
-            let den: f64 = parts[1].parse().unwrap_or(1.0);
-            num / den
-        } else {
-            first_part.parse().unwrap_or(0.0)
+fn attendance_viewer(file: &Path, out: Option<&Path>) -> Result<(), MedError> {
+    let content = storage::read_to_string(file).map_err(|e| MedError::read(file, e))?;
+    let date_str = fs::metadata(file)
+        .and_then(|m| m.modified())
+        .map(|modified| chrono::DateTime::<chrono::Local>::from(modified).format("%d/%m/%Y").to_string())
+        .unwrap_or_default();
+    let title = file.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let doc = medfile::MedDocument::parse(&content);
+    let history = encounter_history(file);
+    let html = attendance::generate_attendance_html(&title, &date_str, &doc, "", &history, load_user_info().as_ref());
+
+    match out {
+        Some(out_path) => {
+            let sidecar = signing::write_document(out_path, html.as_bytes())?;
+            audit::record_file("exportar", file, &format!("atendimento HTML em {}", out_path.display()));
+            println!("Atendimento salvo em {}", out_path.display());
+            report_signature(sidecar.as_deref());
+            Ok(())
+        }
+        None => {
+            audit::record_file("visualizar", file, "atendimento no navegador");
+            serve_html_once(&html)
         }
-    } else {
-        first_part.parse().unwrap_or(0.0)
     }
 }
 
-fn prescription_finalizer(items: Vec<Prescription>, medications: &mut HashMap<String, HashMap<String, String>>, file_time: chrono::DateTime<chrono::Local>) -> (Vec<String>, Vec<String>, Vec<(String, String, String, String, String, String)>) {
-    let mut prescription_return = vec![];
-    let mut prescription_recipe = vec![];
-    let mut changes = vec![];
-    for item in items {
-        if item.command == "PRESCRIBE" {
-            let mut diff_lines = vec![];
-            if !item.dosage.is_empty() {
-                diff_lines.push(format!("\x1b[32m+ dosage: {}\x1b[0m", item.dosage));
-            }
-            if !item.dosage_observations.trim().is_empty() {
-                diff_lines.push(format!("\x1b[32m+ dosage_observations: {}\x1b[0m", item.dosage_observations.trim()));
-            }
-            if !item.posologia.is_empty() {
-                diff_lines.push(format!("\x1b[32m+ posologia: {}\x1b[0m", item.posologia));
-            }
-            if !item.posology_observations.trim().is_empty() {
-                diff_lines.push(format!("\x1b[32m+ posology_observations: {}\x1b[0m", item.posology_observations.trim()));
+fn medication_gantt(out: Option<&Path>, patient: Option<&str>) -> Result<(), MedError> {
+    // With a patient, the timeline is rebuilt from their own encounters; history.json mixes every patient of the workspace
+    let (history, medications, files) = match patient {
+        Some(patient) => {
+            let files = patient_files(patient);
+            if files.is_empty() {
+                return Err(MedError::config("", format!("nenhum arquivo .med do paciente {}", patient)));
             }
-            if !diff_lines.is_empty() {
-                let ret = format!("Mudanças para {}:\n{}", item.medication, diff_lines.join("\n"));
-                prescription_return.push(ret);
-            }
-
-            let line1 = if item.dosage == "1 UNIDADE" {
-                item.medication.to_uppercase()
-            } else {
-                format!("{} {}", item.medication.to_uppercase(), item.dosage)
-            };
-
-            let dosage_obs = if item.dosage_observations.is_empty() {
-                "1 UNIDADE".to_string()
-            } else {
-                item.dosage_observations.clone()
-            };
-
-            let line2 = if item.posology_observations.is_empty() {
-                format!("{} {}", dosage_obs, item.posologia)
-            } else {
-                format!("{} {}, por {}", dosage_obs, item.posologia, item.posology_observations)
-            };
-            let recipe = format!("{}\n{}", line1, line2);
-            prescription_recipe.push(recipe);
-
-            // Save to medications
-            let mut med_map = HashMap::new();
-            med_map.insert("dosage".to_string(), item.dosage.clone());
-            med_map.insert("dosage_observations".to_string(), item.dosage_observations.clone());
-            med_map.insert("posologia".to_string(), item.posologia.clone());
-            med_map.insert("posology_observations".to_string(), item.posology_observations.clone());
-            medications.insert(item.medication.clone(), med_map);
-
-            // Add to changes for initial prescription
-            let timestamp_str = file_time.format("%Y-%m-%d %H:%M:%S").to_string();
-            changes.push((item.medication.clone(), timestamp_str, "initial".to_string(), "".to_string(), item.dosage_observations.clone(), item.command.clone()));
-        } else if item.command == "INCREASE" {
-            if let Some(prev) = medications.get(&item.medication) {
-                let diff_output = generate_diff(&item.medication, prev, &item);
-                prescription_return.push(diff_output);
-                // Collect changes for graph
-                let fields = vec![
-                    ("dosage", item.dosage.clone()),
-                    ("dosage_observations", item.dosage_observations.clone()),
-                    ("posologia", item.posologia.clone()),
-                    ("posology_observations", item.posology_observations.clone()),
-                ];
-                for (field, new_value) in fields {
-                    let old_value = prev.get(field).map(|s| s.as_str()).unwrap_or("");
-                    if old_value != new_value {
-                        let timestamp_str = file_time.format("%Y-%m-%d %H:%M:%S").to_string();
-                        changes.push((item.medication.clone(), timestamp_str, field.to_string(), old_value.to_string(), new_value, item.command.clone()));
-                    }
-                }
+            let (medications, history) = medication_state_replay(&files);
+            (history, medications, files)
+        }
+        None => (load_history()?, load_medications()?, medfile_finder()),
+    };
+    if history.is_empty() {
+        match patient {
+            Some(patient) => println!("Nenhuma prescrição nos atendimentos de {}.", patient),
+            None => println!("Nenhum histórico de prescrições (history.json). Abra 'Ver prescrições' no menu para gerá-lo."),
+        }
+        return Ok(());
+    }
+    let today = chrono::Local::now().date_naive();
+    let bars = gantt::build_bars(&history, &medications, today);
+    let dates = encounter_dates(files.into_iter().map(|(_, modified)| modified));
+    let subject = patient.map(str::to_string).unwrap_or_else(workspace_name);
+    match out {
+        Some(out_path) => {
+            let mut html = generate_html_header("Exposição às Medicações", load_user_info().as_ref());
+            html.push_str(&gantt::generate_gantt_html(&bars, &dates, today));
+            html.push_str(&generate_html_footer());
+            let sidecar = signing::write_document(out_path, html.as_bytes())?;
+            audit::record("exportar", &subject, &format!("linha do tempo das medicações em {}", out_path.display()));
+            println!("Linha do tempo das medicações salva em {}", out_path.display());
+            report_signature(sidecar.as_deref());
+        }
+        None => {
+            audit::record("visualizar", &subject, "linha do tempo das medicações");
+            gantt::print_gantt(&bars, &dates, today);
+        }
+    }
+    Ok(())
+}
 
-                // Update medications
-                let mut med_map = HashMap::new();
-                med_map.insert("dosage".to_string(), item.dosage.clone());
-                med_map.insert("dosage_observations".to_string(), item.dosage_observations.clone());
-                med_map.insert("posologia".to_string(), item.posologia.clone());
-                med_map.insert("posology_observations".to_string(), item.posology_observations.clone());
-                medications.insert(item.medication.clone(), med_map);
-            }
+fn problem_list(out: Option<&Path>, patient: Option<&str>) -> Result<(), MedError> {
+    let files = match patient {
+        Some(patient) => patient_files(patient),
+        None => medfile_finder(),
+    };
+    if let Some(patient) = patient
+        && files.is_empty()
+    {
+        return Err(MedError::config("", format!("nenhum arquivo .med do paciente {}", patient)));
+    }
+    let encounters = load_encounters(&files);
+    let (problems, warnings) = problems::build_problem_list_with_warnings(&encounters);
+    for warning in &warnings {
+        eprintln!("Aviso: {}", warning);
+    }
+    let subject = patient.map(str::to_string).unwrap_or_else(workspace_name);
+    match out {
+        Some(out_path) => {
+            let mut html = generate_html_header("Lista de Problemas", load_user_info().as_ref());
+            html.push_str(&problems::generate_problem_list_html(&problems));
+            html.push_str(&generate_html_footer());
+            let sidecar = signing::write_document(out_path, html.as_bytes())?;
+            audit::record("exportar", &subject, &format!("lista de problemas em {}", out_path.display()));
+            println!("Lista de problemas salva em {}", out_path.display());
+            report_signature(sidecar.as_deref());
+        }
+        None => {
+            audit::record("visualizar", &subject, "lista de problemas");
+            problems::print_problem_list(&problems);
         }
     }
-    (prescription_return, prescription_recipe, changes)
+    Ok(())
 }
 
-fn medication_list_tokenizer(line: &str) -> HashMap<String, String> {
-    let mut map = HashMap::new();
-    let trimmed = line.trim_start_matches('!').trim_end_matches(';');
-    let parts: Vec<&str> = trimmed.split_whitespace().collect();
-    let mut idx = 0;
+fn cid_search(term: &str) {
+    let suggestions = cid10::suggest(term, 10);
+    if suggestions.is_empty() {
+        println!("Nenhum código CID-10 encontrado para \"{}\".", term);
+        return;
+    }
+    for suggestion in suggestions {
+        println!("{:<6} {} ({:.0}%)", suggestion.entry.code, suggestion.entry.description, suggestion.score * 100.0);
+    }
+}
 
-    // Command
-    if idx < parts.len() {
-        let cmd = parts[idx];
-        let command = match cmd {
-            "PRESCREVO" => "PRESCRIBE",
-            "AUMENTO" => "INCREASE",
-            "REDUZO" => "DECREASE",
-            "SUSPENDO" => "SUSPEND",
-            _ => cmd,
-        };
-        map.insert("command".to_string(), command.to_string());
-        idx += 1;
-    }
-
-    // Medication
-    if idx < parts.len() {
-        let mut med = parts[idx].to_string();
-        if med.starts_with('"') || med.starts_with('\'') {
-            let quote = med.chars().next().unwrap();
-            med = med.trim_start_matches(quote).to_string();
-            while idx + 1 < parts.len() && !med.ends_with(quote) {
-                idx += 1;
-                med.push(' ');
-                med.push_str(parts[idx]);
+fn cid_check(file: &Path) -> Result<(), MedError> {
+    let content = storage::read_to_string(file).map_err(|e| MedError::read(file, e))?;
+    let doc = medfile::MedDocument::parse(&content);
+    let diagnoses = medfile::diagnosis_items(&doc.section_lines("HIPOTESE DIAGNOSTICA"));
+    if diagnoses.is_empty() {
+        println!("Nenhuma hipótese diagnóstica em {}.", file.display());
+        return Ok(());
+    }
+    audit::record_file("visualizar", file, "conferência CID-10");
+    let mut invalid = 0;
+    for diagnosis in diagnoses {
+        let status = if diagnosis.suspected { " (suspeita)" } else { "" };
+        match (&diagnosis.code, diagnosis.cid_code()) {
+            (Some(written), None) => {
+                invalid += 1;
+                println!("{}{}: código {{{}}} inválido.", diagnosis.name, status, written);
             }
-            med = med.trim_end_matches(quote).to_string();
-        }
-        map.insert("medication".to_string(), med);
-        idx += 1;
-    }
-
-    // Dosage
-    let mut dosage = "1 UNIDADE".to_string();
-    if idx < parts.len() && !parts[idx].starts_with('[') && !parts[idx].contains(">>") {
-        dosage = parts[idx].to_string();
-        idx += 1;
-    }
-    map.insert("dosage".to_string(), dosage);
-
-    // Dosage observations
-    let mut dosage_obs = String::new();
-    if idx < parts.len() && parts[idx].starts_with('[') {
-        dosage_obs = parts[idx].to_string();
-        idx += 1;
-        while idx < parts.len() && !dosage_obs.ends_with(']') {
-            dosage_obs.push(' ');
-            dosage_obs.push_str(parts[idx]);
-            idx += 1;
-        }
-        dosage_obs = dosage_obs.trim_start_matches('[').trim_end_matches(']').to_string();
-    }
-    map.insert("dosage_observations".to_string(), dosage_obs);
-
-    // Posology
-    let mut posology = String::new();
-    if idx < parts.len() && !parts[idx].contains(">>") {
-        posology = parts[idx].to_string();
-        idx += 1;
-        // If there's a second group
-        if idx < parts.len() && !parts[idx].contains(">>") {
-            posology.push(' ');
-            posology.push_str(parts[idx]);
-            idx += 1;
-        }
-    }
-    map.insert("posologia".to_string(), posology);
-
-    // Posology observations
-    let mut pos_obs = String::new();
-    if idx < parts.len() && parts[idx].starts_with('[') {
-        pos_obs = parts[idx].trim_start_matches('[').trim_end_matches(']').to_string();
-        idx += 1;
-    }
-    map.insert("posology_observations".to_string(), pos_obs);
-
-    // Objective
-    let mut objective = String::new();
-    if idx < parts.len() && parts[idx] == ">>" {
-        idx += 1;
-        while idx < parts.len() {
-            if objective.is_empty() {
-                objective = parts[idx].to_string();
-            } else {
-                objective.push(' ');
-                objective.push_str(parts[idx]);
+            (Some(_), Some(code)) => match cid10::description(&code) {
+                Some(description) => println!("{}{}: {} - {}", diagnosis.name, status, code, description),
+                None => println!("{}{}: {} (não consta na tabela local)", diagnosis.name, status, code),
+            },
+            (None, Some(code)) => println!(
+                "{}{}: {} - {} (pelo nome; anote {{{}}} para fixar)",
+                diagnosis.name,
+                status,
+                code,
+                cid10::description(&code).unwrap_or_default(),
+                code
+            ),
+            (None, None) => {
+                let suggestions = cid10::suggest(&diagnosis.name, 3);
+                if suggestions.is_empty() {
+                    println!("{}{}: sem código e sem sugestões.", diagnosis.name, status);
+                    continue;
+                }
+                println!("{}{}: sem código. Sugestões:", diagnosis.name, status);
+                for suggestion in suggestions {
+                    println!("    {{{}}} {} ({:.0}%)", suggestion.entry.code, suggestion.entry.description, suggestion.score * 100.0);
+                }
             }
-            idx += 1;
         }
     }
-    map.insert("objective".to_string(), objective);
+    if invalid > 0 {
+        return Err(MedError::parse(file, format!("{} código(s) CID-10 inválido(s)", invalid)));
+    }
+    Ok(())
+}
 
-    map
+fn site_generator(out_dir: &Path) -> Result<(), MedError> {
+    let files = medfile_finder();
+    let encounters = load_encounters(&files);
+    let (medications, _) = medfiles::prescription::replay(&encounters);
+    let (doctor, signature) = load_doctor();
+    let report = site::generate_site(out_dir, &encounters, &medications, doctor.as_ref(), signature.as_deref())?;
+    // The pages of a file that could not be read are kept until it can be
+    let removed = if encounters.len() == files.len() { site::remove_stale_pages(out_dir, &encounters)? } else { 0 };
+    for encounter in &encounters {
+        audit::record_file("exportar", Path::new(&encounter.name), &format!("site em {}", out_dir.display()));
+    }
+    println!(
+        "Site gerado em {}: {} páginas atualizadas, {} inalteradas, {} removidas.",
+        out_dir.display(),
+        report.written,
+        report.unchanged,
+        removed
+    );
+    Ok(())
 }
 
-fn parse_prescription_to_list(recipe_content: &str) -> String {
-    let mut html = String::from("<ol class=\"prescription-list\">");
+fn workspace_name() -> String {
+    std::env::current_dir()
+        .ok()
+        .and_then(|dir| dir.file_name().map(|name| name.to_string_lossy().to_string()))
+        .unwrap_or_default()
+}
 
-    for medication_block in recipe_content.split("\n\n") {
-        let lines: Vec<&str> = medication_block.lines().collect();
-        if lines.len() >= 2 {
-            let medication_name = lines[0];
-            let dosage_info = lines[1];
-            html.push_str(&format!(
-                "<li><strong>{}</strong><br>{}</li>",
-                medication_name, dosage_info
-            ));
+fn fhir_export(out: &Path, patient: Option<&str>) -> Result<(), MedError> {
+    // With --patient, only that patient's encounters go into the bundle
+    let files = match patient {
+        Some(patient) => patient_files(patient),
+        None => medfile_finder(),
+    };
+    if let Some(patient) = patient
+        && files.is_empty()
+    {
+        return Err(MedError::config("", format!("nenhum arquivo .med do paciente {}", patient)));
+    }
+    let encounters = load_encounters(&files);
+    let (medications, _) = medfiles::prescription::replay(&encounters);
+    let patient_name = patient.map(str::to_string).unwrap_or_else(workspace_name);
+    let user_info = load_user_info().unwrap_or_default();
+    let bundle = fhir::build_bundle(&patient_name, &user_info, &encounters, &medications);
+
+    let errors = fhir::validate_bundle(&bundle);
+    if !errors.is_empty() {
+        eprintln!("O Bundle FHIR não passou na validação do esquema:");
+        for error in &errors {
+            eprintln!("  {}", error);
         }
+        return Err(MedError::invalid(out, format!("{} erro(s) de validação; nada foi gravado", errors.len())));
     }
-
-    html.push_str("</ol>");
-    html
+    let json = serde_json::to_string_pretty(&bundle).map_err(|e| MedError::parse(out, e))?;
+    fs::write(out, json).map_err(|e| MedError::io(out, e))?;
+    for encounter in &encounters {
+        audit::record_file("exportar", Path::new(&encounter.name), &format!("FHIR em {}", out.display()));
+    }
+    let count = bundle["entry"].as_array().map(|entries| entries.len()).unwrap_or(0);
+    println!("Bundle FHIR R4 salvo em {} ({} recursos, validado).", out.display(), count);
+    Ok(())
 }
 
-fn prescription_graphs_html(changes: &Vec<(String, String, String, String, String, String)>) -> String {
-    if changes.is_empty() {
-        return String::new();
+fn fhir_import(bundle_path: &Path, out: Option<&Path>) -> Result<(), MedError> {
+    let content = fs::read_to_string(bundle_path).map_err(|e| MedError::read(bundle_path, e))?;
+    let bundle: serde_json::Value = serde_json::from_str(&content).map_err(|e| MedError::parse(bundle_path, e))?;
+    let source = bundle_path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let record = fhir::bundle_to_med(&bundle, &source).map_err(|e| MedError::parse(bundle_path, e))?;
+    let out_path = match out {
+        Some(path) => path.to_path_buf(),
+        None => {
+            let name = record.patient_name.clone().unwrap_or_else(|| format!("importado_{}", chrono::Local::now().format("%Y%m%d_%H%M%S")));
+            PathBuf::from(format!("{}.med", name.replace(['/', '\\'], "_").to_uppercase()))
+        }
+    };
+    if out_path.exists() {
+        return Err(MedError::config(&out_path, "o arquivo já existe; use --out para escolher outro"));
     }
+    storage::write(&out_path, &record.content).map_err(|e| MedError::io(&out_path, e))?;
+    audit::record_file("importar", &out_path, &format!("bundle FHIR {}", source));
+    println!(
+        "Registro importado em {}: {} item(ns) mapeado(s), {} nota(s) em [NOTAS DE IMPORTACAO].",
+        out_path.display(),
+        record.mapped,
+        record.notes
+    );
+    Ok(())
+}
 
-    let mut medications: std::collections::HashSet<String> = std::collections::HashSet::new();
-    for (med, _, _, _, _, _) in changes {
-        medications.insert(med.clone());
+fn patient_files(patient: &str) -> Vec<(PathBuf, SystemTime)> {
+    let key = cid10::normalize(patient);
+    let all_files = medfile_finder();
+    let files: Vec<(PathBuf, SystemTime)> = all_files
+        .iter()
+        .filter(|(path, _)| {
+            let file_name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            cid10::normalize(&site::patient_name(&file_name)) == key
+        })
+        .cloned()
+        .collect();
+    // A workspace named after the patient holds only their encounters, whatever the file names
+    if files.is_empty() && cid10::normalize(&workspace_name()) == key {
+        return all_files;
     }
+    files
+}
 
-    let mut sorted_changes: Vec<_> = changes.iter().map(|(med, time_str, field, old, new, cmd)| {
-        let time = chrono::NaiveDateTime::parse_from_str(time_str, "%Y-%m-%d %H:%M:%S").unwrap_or(chrono::NaiveDateTime::from_timestamp_opt(0, 0).unwrap());
-        (med.clone(), time, field.clone(), old.clone(), new.clone(), cmd.clone())
-    }).collect::<Vec<_>>();
-    sorted_changes.sort_by_key(|(_, time, _, _, _, _)| *time);
-
-    let mut html = String::from("<div class=\"prescription-timeline\">");
+fn new_encounter(patient: &str, template: Option<&str>, edit: bool) -> Result<(), MedError> {
+    let key = cid10::normalize(patient);
+    let files = patient_files(patient);
+    let name = files
+        .first()
+        .and_then(|(path, _)| path.file_name())
+        .map(|n| site::patient_name(&n.to_string_lossy()))
+        .filter(|name| cid10::normalize(name) == key)
+        .unwrap_or_else(|| patient.trim().to_uppercase());
+
+    let specialty = load_user_info().map(|user| user.specialty).unwrap_or_default();
+    let template_name = template.unwrap_or_else(|| templates::for_specialty(&specialty));
+    let template_text = templates::load(template_name).map_err(|e| MedError::config(templates::TEMPLATE_DIR, e))?;
+
+    let encounters = load_encounters(&files);
+    let (replayed, _) = medfiles::prescription::replay(&encounters);
+    let stored = load_medications()?;
+    let mut medications: Vec<(String, HashMap<String, String>)> = if stored.is_empty() {
+        replayed.into_iter().collect()
+    } else {
+        // medications.json covers the whole workspace: keep what this patient is taking
+        stored.into_iter().filter(|(medication, _)| replayed.contains_key(medication)).collect()
+    };
+    medications.sort_by(|a, b| a.0.cmp(&b.0));
+    let problems: Vec<problems::Problem> = problems::build_problem_list(&encounters)
+        .into_iter()
+        .filter(|problem| problem.status.is_active())
+        .collect();
+    let pending = templates::pending_requests(&encounters);
+
+    let today = chrono::Local::now();
+    let header = format!("{} - atendimento de {} (modelo {})", name, today.format("%d/%m/%Y"), template_name);
+    let content = templates::build(&template_text, &header, &medications, &problems, &pending);
+    let stem = format!("{} {}", name.replace(['/', '\\'], "_"), today.format("%Y-%m-%d"));
+    let mut out_path = PathBuf::from(format!("{}.med", stem));
+    let mut suffix = 2;
+    while out_path.exists() {
+        out_path = PathBuf::from(format!("{}-{}.med", stem, suffix));
+        suffix += 1;
+    }
+    storage::write(&out_path, &content).map_err(|e| MedError::io(&out_path, e))?;
+    audit::record_file("criar", &out_path, &format!("novo atendimento pelo modelo {}", template_name));
+    println!(
+        "Atendimento criado em {}: {} medicação(ões) em uso, {} diagnóstico(s) e {} exame(s) pendente(s) pré-preenchidos como comentários.",
+        out_path.display(),
+        medications.len(),
+        problems.len(),
+        pending.len()
+    );
+    if edit {
+        open_in_editor(&out_path).map_err(|e| MedError::config("", e))?;
+    }
+    Ok(())
+}
 
-    for med in medications {
-        html.push_str(&format!("<h3>{}</h3>", med));
-        html.push_str("<div class=\"timeline\">");
+fn doctor_header_lines() -> Vec<String> {
+    load_user_info().map(|user| profile::header_lines(&user)).unwrap_or_default()
+}
 
-        let med_changes: Vec<_> = sorted_changes.iter().filter(|(m, _, _, _, _, _)| m == &med).collect();
+fn prescription_pdf_blocks(prescriptions: &[Prescription], date_str: &str) -> Vec<pdf::PdfBlock> {
+    let mut blocks = vec![];
+    for (i, item) in prescriptions.iter().enumerate() {
+        let (line1, line2) = recipe_lines(item);
+        blocks.push(pdf::PdfBlock::Item(format!("{}. {}", i + 1, line1), line2));
+    }
+    blocks.push(pdf::PdfBlock::Paragraph(format!("Data: {}", date_str)));
+    let user_info = load_user_info().unwrap_or_default();
+    blocks.push(pdf::PdfBlock::Signature {
+        name: user_info.name.clone(),
+        details: profile::registration_line(&user_info),
+        image: signature_image(&user_info),
+    });
+    blocks
+}
 
-        // Group by timestamp and command
-        let mut unique_events: Vec<(chrono::NaiveDateTime, String, String, String)> = vec![];
-        for (_, time, field, old, new, command) in &med_changes {
-            if !unique_events.iter().any(|(t, c, _, _)| t == time && c == command) {
-                unique_events.push((*time, command.clone(), field.clone(), format!("{} → {}", old, new)));
+fn print_command(file: Option<&Path>, pdf_path: Option<&Path>, page_size: pdf::PageSize) -> Result<(), MedError> {
+    let Some(pdf_path) = pdf_path else {
+        return match file {
+            Some(file) => attendance_viewer(file, None),
+            None => {
+                let (_, prescription_returns, file_count) = conduta_handler()?;
+                print_prescription_html(&prescription_returns, file_count);
+                Ok(())
             }
-        }
-        unique_events.sort_by_key(|(time, _, _, _)| *time);
+        };
+    };
 
-        for (time, command, field, details) in unique_events {
-            let date_str = time.format("%d/%m").to_string();
-            let (marker_class, marker_symbol, event_description) = match command.as_str() {
-                "PRESCRIBE" => ("initial", "●", "Prescrição Inicial".to_string()),
-                "INCREASE" => ("increase", "▲", format!("Aumento - {}", details)),
-                "DECREASE" => ("decrease", "▼", format!("Diminuição - {}", details)),
-                _ => ("other", "●", format!("{} - {}", command, details)),
+    let (title, source, blocks) = match file {
+        Some(file) => {
+            let content = storage::read_to_string(file).map_err(|e| MedError::read(file, e))?;
+            let doc = medfile::MedDocument::parse(&content);
+            ("Atendimento".to_string(), file.to_path_buf(), attendance::attendance_pdf_blocks(&doc))
+        }
+        None => {
+            let encounters = load_encounters(&medfile_finder());
+            let latest = encounters.iter().rev().find(|e| !site::encounter_prescriptions(e).is_empty());
+            let Some(encounter) = latest else {
+                println!("Nenhuma prescrição encontrada.");
+                return Ok(());
             };
-
-            html.push_str(&format!(
-                "<div class=\"timeline-item\">
-                    <div class=\"timeline-marker {}\">{}</div>
-                    <div class=\"timeline-content\">
-                        <strong>{}</strong> - {}
-                    </div>
-                </div>",
-                marker_class, marker_symbol, date_str, event_description
-            ));
+            let prescriptions = site::encounter_prescriptions(encounter);
+            let mut blocks = prescription_pdf_blocks(&prescriptions, &encounter.date_str);
+            let content = prescriptions
+                .iter()
+                .map(|item| {
+                    let (line1, line2) = recipe_lines(item);
+                    format!("{}\n{}", line1, line2)
+                })
+                .collect::<Vec<String>>()
+                .join("\n\n");
+            match issuance::issue(&content) {
+                Ok(issued) => match issuance::qr_matrix(&issued.payload()) {
+                    Ok(modules) => blocks.push(pdf::PdfBlock::Verification { lines: issuance::verification_lines(&issued), modules }),
+                    Err(e) => eprintln!("Falha ao gerar o QR code: {}", e),
+                },
+                Err(e) => eprintln!("Falha ao numerar a receita: {}", e),
+            }
+            ("Receituário".to_string(), PathBuf::from(&encounter.name), blocks)
         }
-
-        html.push_str("</div>");
-    }
-
-    html.push_str("</div>");
-    html
-}
-
-
-
-fn generate_html_header(title: &str) -> String {
-    format!(r#"<!DOCTYPE html>
-<html>
-<head>
-    <meta charset="UTF-8">
-    <title>{}</title>
-    <meta charset="UTF-8">
-    <link href="https://fonts.googleapis.com/css2?family=Ubuntu:wght@300;400;500;600&display=swap" rel="stylesheet">
-    <style>
-        body {{
-            font-family: 'Ubuntu', sans-serif;
-            margin: 0;
-            padding: 20px;
-            background-color: #f4f7fa;
-            color: #333;
-            line-height: 1.6;
-        }}
-        .container {{
-            max-width: 800px;
-            margin: 0 auto;
-            background: white;
-            padding: 40px;
-            box-shadow: 0 0 20px rgba(0,0,0,0.1);
-            border-radius: 8px;
-        }}
-        h1 {{
-            color: #2c3e50;
-            text-align: center;
-            margin-bottom: 40px;
-            font-size: 2.5em;
-            font-weight: 300;
-        }}
-        h2 {{
-            color: #34495e;
-            border-bottom: 2px solid #3498db;
-            padding-bottom: 10px;
-            margin-top: 40px;
-            margin-bottom: 20px;
-            font-size: 1.8em;
-            font-weight: 400;
-        }}
-        .prescription {{
-            border: 1px solid #e1e8ed;
-            padding: 25px;
-            margin-bottom: 30px;
-            background: #fafbfc;
-            border-radius: 6px;
-        }}
-        .changes {{
-            background-color: #ecf0f1;
-            padding: 20px;
-            border-left: 4px solid #3498db;
-            border-radius: 0 6px 6px 0;
-        }}
-        pre {{
-            white-space: pre-wrap;
-            font-family: 'Ubuntu', sans-serif;
-            background: #f8f9fa;
-            color: #333;
-            padding: 15px;
-            border-radius: 4px;
-            border: 1px solid #e1e8ed;
-            overflow-x: auto;
-        }}
-        .removed {{ color: #e74c3c; font-weight: bold; }}
-        .added {{ color: #27ae60; font-weight: bold; }}
-
-        .prescription-list {{
-            padding-left: 0;
-            list-style: none;
-        }}
-        .prescription-list li {{
-            margin-bottom: 20px;
-            padding: 15px;
-            background: white;
-            border: 1px solid #e1e8ed;
-            border-radius: 4px;
-            box-shadow: 0 1px 3px rgba(0,0,0,0.1);
-        }}
-        .prescription-list strong {{
-            color: #2c3e50;
-            font-size: 1.1em;
-            display: block;
-            margin-bottom: 5px;
-        }}
-        .prescription-timeline {{
-            margin-top: 40px;
-        }}
-        .prescription-timeline h3 {{
-            color: #2c3e50;
-            margin-bottom: 20px;
-            font-size: 1.3em;
-            font-weight: 500;
-        }}
-        .timeline {{
-            position: relative;
-            padding-left: 40px;
-            margin-bottom: 40px;
-        }}
-        .timeline::before {{
-            content: '';
-            position: absolute;
-            left: 20px;
-            top: 0;
-            bottom: 0;
-            width: 3px;
-            background: #bdc3c7;
-        }}
-        .timeline-item {{
-            position: relative;
-            margin-bottom: 25px;
-            padding-left: 20px;
-        }}
-        .timeline-marker {{
-            position: absolute;
-            left: -25px;
-            width: 40px;
-            height: 40px;
-            border-radius: 50%;
-            display: flex;
-            align-items: center;
-            justify-content: center;
-            font-weight: bold;
-            color: white;
-            border: 3px solid white;
-            box-shadow: 0 2px 6px rgba(0,0,0,0.2);
-            font-size: 1.2em;
-        }}
-        .timeline-marker.initial {{ background: #27ae60; }}
-        .timeline-marker.increase {{ background: #3498db; }}
-        .timeline-marker.decrease {{ background: #e74c3c; }}
-        .timeline-marker.other {{ background: #95a5a6; }}
-        .timeline-content {{
-            background: #ecf0f1;
-            padding: 15px 20px;
-            border-radius: 6px;
-            border-left: 4px solid #3498db;
-        }}
-        .timeline-content strong {{
-            color: #2c3e50;
-            font-weight: 600;
-        }}
-    </style>
-</head>
-<body>
-    <div class="container">
-        <h1>{}</h1>
-"#, title, title)
-}
-
-fn generate_recipe_section(recipe_list_html: &str) -> String {
-    format!(r#"
-    <div class="prescription">
-        <h2>Receita</h2>
-        {}
-    </div>
-"#, recipe_list_html)
-}
-
-fn generate_changes_section(returns_content: &str) -> String {
-    format!(r#"
-    <div class="prescription changes">
-        <h2>Alterações</h2>
-        <pre>{}</pre>
-    </div>
-"#, returns_content)
-}
-
-fn generate_timeline_section(graph_html: &str) -> String {
-    format!(r#"
-    <div class="prescription">
-        <h2>Evolução das Prescrições</h2>
-        {}
-    </div>
-"#, graph_html)
-}
-
-fn generate_html_footer() -> String {
-    "\n    </div>\n</body>\n</html>".to_string()
-}
-
-fn generate_complete_html(
-    recipe_list_html: &str,
-    returns_content: &str,
-    graph_html: &str
-) -> String {
-    let mut html = generate_html_header("Prescrição Médica");
-    html.push_str(&generate_recipe_section(recipe_list_html));
-    html.push_str(&generate_changes_section(returns_content));
-    html.push_str(&generate_timeline_section(graph_html));
-    html.push_str(&generate_html_footer());
-    html
-}
-
-fn prescription_printer(prescription_returns: &[String], graph_data: &Vec<(String, String, String, String, String, String)>, file_count: usize) {
-    // Read prescription recipe content
-    let recipe_content = fs::read_to_string("prescription_recipe.txt")
-        .unwrap_or_else(|_| "Nenhuma receita encontrada.".to_string());
-
-    // Format prescription returns with ANSI to HTML conversion
-    let returns_content = if prescription_returns.is_empty() {
-        "Nenhuma alteração encontrada.".to_string()
-    } else {
-        prescription_returns
-            .iter()
-            .map(|line| ansi_to_html(line))
-            .collect::<Vec<String>>()
-            .join("\n")
     };
 
-    // Parse prescription recipe into numbered list
-    let recipe_list_html = parse_prescription_to_list(&recipe_content);
-
-    // Generate HTML timeline graph
-    let graph_html = prescription_graphs_html(graph_data);
+    let bytes = pdf::render_pdf(&title, &doctor_header_lines(), &blocks, page_size)
+        .map_err(|e| MedError::parse(&source, format!("falha ao gerar o PDF: {}", e)))?;
+    let sidecar = signing::write_document(pdf_path, &bytes)?;
+    audit::record_file("imprimir", &source, &format!("{} em PDF ({})", title.to_lowercase(), pdf_path.display()));
+    println!("PDF salvo em {}", pdf_path.display());
+    report_signature(sidecar.as_deref());
+    Ok(())
+}
 
-    // Create HTML content using modular functions
-    let mut html_content = generate_html_header("Prescrição Médica");
-    html_content.push_str(&generate_recipe_section(&recipe_list_html));
-    if file_count >= 2 {
-        html_content.push_str(&generate_changes_section(&returns_content));
-        html_content.push_str(&generate_timeline_section(&graph_html));
+fn report_signature(sidecar: Option<&Path>) {
+    match sidecar {
+        Some(sidecar) => println!("Documento assinado digitalmente (assinatura em {}).", sidecar.display()),
+        None => println!("Documento não assinado: execute 'medfiles keygen' para criar uma chave de assinatura."),
     }
-    html_content.push_str(&generate_html_footer());
-
-    // Create temporary HTML file
-    let temp_file = "temp_prescription.html";
-    fs::write(temp_file, html_content).unwrap();
+}
 
-    // Open in default browser
-    #[cfg(target_os = "linux")]
-    {
-        std::process::Command::new("xdg-open")
-            .arg(temp_file)
-            .spawn()
-            .unwrap();
+fn verify_command(document: &Path) -> Result<(), MedError> {
+    let report = signing::verify(document).map_err(|e| MedError::invalid(document, e))?;
+    match &report.trust {
+        Some(_) => println!("Assinatura válida."),
+        None => println!("Documento íntegro, mas o assinante NÃO foi verificado."),
     }
-
-    #[cfg(target_os = "windows")]
-    {
-        std::process::Command::new("cmd")
-            .args(&["/C", "start", temp_file])
-            .spawn()
-            .unwrap();
+    println!("Documento: {} (SHA-256 {})", report.record.document, report.record.sha256);
+    if !report.record.signer.is_empty() {
+        // Free text chosen by whoever signed; only the key and the certificate identify the signer
+        println!("Signatário declarado: {}", report.record.signer);
     }
-
-    #[cfg(target_os = "macos")]
-    {
-        std::process::Command::new("open")
-            .arg(temp_file)
-            .spawn()
-            .unwrap();
+    println!("Assinado em: {}", report.record.signed_at);
+    println!("Chave: {} ({})", report.fingerprint, report.record.algorithm);
+    if let Some(subject) = &report.certificate_subject {
+        println!("Certificado: {}", subject);
     }
-
-    // Clean up temp file after a short delay
-    std::thread::spawn(move || {
-        std::thread::sleep(std::time::Duration::from_secs(1));
-        let _ = fs::remove_file(temp_file);
-    });
+    let sources = match (report.embedded, report.sidecar) {
+        (true, true) => "embutida no documento e arquivo .sig.json",
+        (true, false) => "embutida no documento",
+        _ => "arquivo .sig.json",
+    };
+    println!("Origem da assinatura: {}", sources);
+    match report.trust {
+        Some(signing::Trust::LocalKey) => println!("Assinante: chave local ({}).", signing::KEY_PATH),
+        Some(signing::Trust::PinnedFingerprint) => {
+            println!("Assinante: impressão digital confiável em {}.", signing::TRUSTED_SIGNERS_PATH)
+        }
+        Some(signing::Trust::CertificateAuthority(ca)) => println!("Assinante: certificado emitido por {}.", ca),
+        None => {
+            return Err(MedError::invalid(
+                document,
+                format!(
+                    "Assinante não verificado: a chave {} não é a chave local, não está em {} e não tem certificado emitido pela AC de {}.",
+                    report.fingerprint,
+                    signing::TRUSTED_SIGNERS_PATH,
+                    signing::TRUSTED_CA_PATH
+                ),
+            ));
+        }
+    }
+    Ok(())
 }
 
+fn verify_code_command(payload: &str) -> Result<(), MedError> {
+    match issuance::verify_payload(payload) {
+        Ok(issued) => {
+            audit::record("verificar", "", &format!("código da receita nº {}", issued.number));
+            println!("Receita autêntica: nº {} emitida em {}.", issued.number, issued.display_date());
+            if !issued.crm.is_empty() {
+                println!("Prescritor: {}", issued.crm);
+            }
+            println!("Conteúdo registrado:\n{}", issued.content);
+            Ok(())
+        }
+        Err(e) => Err(MedError::invalid(issuance::ARCHIVE_PATH, format!("código inválido: {}", e))),
+    }
+}
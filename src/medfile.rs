@@ -0,0 +1,208 @@
+// Parsing of .med documents into sections and directives.
+//
+// A .med file is a sequence of sections opened by a `[NOME DA SEÇÃO]` line.
+// Inside a section, lines starting with `!` are directives (`!HPP`, `!EX`,
+// `!PRESCREVO`...) and everything else is free text. Lines starting with `#`
+// are comments, used by the templates of `medfiles new`, and are ignored.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::error::MedError;
+
+pub struct MedSection {
+    pub name: String,
+    pub lines: Vec<String>,
+}
+
+pub struct MedDocument {
+    pub sections: Vec<MedSection>,
+}
+
+pub struct Directive {
+    pub keyword: String,
+    pub content: String,
+}
+
+pub struct Diagnosis {
+    pub name: String,
+    // Code as written in the `{...}` annotation, possibly malformed
+    pub code: Option<String>,
+    pub suspected: bool,
+}
+
+impl Diagnosis {
+    pub fn cid_code(&self) -> Option<String> {
+        // The annotation wins; otherwise only an unambiguous name match is coded
+        match &self.code {
+            Some(code) => crate::cid10::normalize_code(code),
+            None => crate::cid10::exact_match(&self.name).map(|entry| entry.code.clone()),
+        }
+    }
+}
+
+pub fn diagnosis_codes(doc: &MedDocument) -> Vec<String> {
+    let mut codes: Vec<String> = vec![];
+    for diagnosis in diagnosis_items(&doc.section_lines("HIPOTESE DIAGNOSTICA")) {
+        if let Some(code) = diagnosis.cid_code()
+            && !codes.contains(&code)
+        {
+            codes.push(code);
+        }
+    }
+    codes
+}
+
+pub struct ExamEntry {
+    pub name: String,
+    pub date: String,
+    pub result: String,
+}
+
+impl MedDocument {
+    pub fn parse(content: &str) -> MedDocument {
+        let mut sections: Vec<MedSection> = vec![];
+        for line in content.lines() {
+            if let Some(name) = section_name(line) {
+                sections.push(MedSection { name, lines: vec![] });
+                continue;
+            }
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+            // Text written before the first header is kept in an unnamed section
+            if sections.is_empty() {
+                sections.push(MedSection { name: String::new(), lines: vec![] });
+            }
+            if let Some(section) = sections.last_mut() {
+                section.lines.push(line.trim().to_string());
+            }
+        }
+        MedDocument { sections }
+    }
+
+    pub fn section(&self, name: &str) -> Option<&MedSection> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+
+    pub fn section_lines(&self, name: &str) -> Vec<String> {
+        self.section(name).map(|s| s.lines.clone()).unwrap_or_default()
+    }
+}
+
+impl MedSection {
+    pub fn directives(&self) -> Vec<Directive> {
+        self.lines.iter().filter_map(|line| parse_directive(line)).collect()
+    }
+
+    pub fn free_text(&self) -> Vec<String> {
+        self.lines.iter().filter(|line| !line.starts_with('!')).cloned().collect()
+    }
+}
+
+// .med files with their modification time
+pub type MedFiles = Vec<(PathBuf, SystemTime)>;
+
+pub fn find_med_files(dir: &Path) -> Result<(MedFiles, Vec<MedError>), MedError> {
+    // The .med files of `dir`, oldest first: encounters are replayed in this order.
+    // Files whose modification time cannot be read are skipped and returned with the errors.
+    let mut files = vec![];
+    let mut skipped = vec![];
+    for entry in fs::read_dir(dir).map_err(|e| MedError::io(dir, e))? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                skipped.push(MedError::io(dir, e));
+                continue;
+            }
+        };
+        let path = entry.path();
+        if path.extension().is_some_and(|e| e == "med") {
+            match entry.metadata().and_then(|m| m.modified()) {
+                Ok(modified) => files.push((path, modified)),
+                Err(e) => skipped.push(MedError::io(&path, e)),
+            }
+        }
+    }
+    files.sort_by_key(|&(_, time)| time);
+    Ok((files, skipped))
+}
+
+pub fn section_name(line: &str) -> Option<String> {
+    let trimmed = line.trim();
+    if trimmed.starts_with('[') && trimmed.ends_with(']') {
+        let inner = &trimmed[1..trimmed.len() - 1];
+        if !inner.is_empty() && !inner.contains('[') && !inner.contains(']') {
+            return Some(inner.trim().to_uppercase());
+        }
+    }
+    None
+}
+
+pub fn parse_directive(line: &str) -> Option<Directive> {
+    let trimmed = line.trim();
+    let rest = trimmed.strip_prefix('!')?;
+    let (keyword, content) = match rest.split_once(char::is_whitespace) {
+        Some((k, c)) => (k, c),
+        None => (rest, ""),
+    };
+    Some(Directive {
+        keyword: keyword.trim_end_matches(';').to_uppercase(),
+        content: content.trim().trim_end_matches(';').trim().to_string(),
+    })
+}
+
+pub fn parse_exam(content: &str) -> ExamEntry {
+    // Expected shape: @NOME_DO_EXAME[MM/AAAA]: RESULTADO
+    let (head, result) = match content.split_once(':') {
+        Some((h, r)) => (h.trim(), r.trim()),
+        None => (content.trim(), ""),
+    };
+    let head = head.trim_start_matches('@');
+    let (name, date) = match head.find('[') {
+        Some(pos) => (&head[..pos], head[pos + 1..].trim_end_matches(']')),
+        None => (head, ""),
+    };
+    ExamEntry {
+        name: name.replace('_', " ").trim().to_string(),
+        date: date.trim().to_string(),
+        result: result.to_string(),
+    }
+}
+
+pub fn diagnosis_items(lines: &[String]) -> Vec<Diagnosis> {
+    // Each hypothesis ends with `;`; a trailing `?` marks it as suspected.
+    // Directives such as `!DESCARTO` are not hypotheses and are skipped.
+    lines
+        .iter()
+        .filter(|line| !line.starts_with('!'))
+        .flat_map(|line| line.split(';'))
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .map(parse_diagnosis)
+        .collect()
+}
+
+pub fn parse_diagnosis(item: &str) -> Diagnosis {
+    // `NOME {G20}`, `NOME? {G20}` and `NOME {G20}?` are all accepted
+    let mut text = item.trim();
+    let mut suspected = false;
+    if let Some(rest) = text.strip_suffix('?') {
+        suspected = true;
+        text = rest.trim_end();
+    }
+    let mut code = None;
+    let mut name = text.to_string();
+    if let Some(open) = text.rfind('{')
+        && let Some(close) = text[open..].find('}')
+    {
+        code = Some(text[open + 1..open + close].trim().to_uppercase()).filter(|c| !c.is_empty());
+        name = format!("{} {}", &text[..open], &text[open + close + 1..]).trim().to_string();
+    }
+    if let Some(rest) = name.strip_suffix('?') {
+        suspected = true;
+        name = rest.trim_end().to_string();
+    }
+    Diagnosis { name, code, suspected }
+}
@@ -0,0 +1,411 @@
+// Native PDF rendering for prescriptions and other documents. Layout is done
+// in two passes: blocks are first placed on pages (so the total page count is
+// known) and then written with printpdf using the bundled DejaVu Sans fonts,
+// which cover every accented character used in Portuguese.
+
+use printpdf::{Image, ImageTransform, IndirectFontRef, Line, Mm, PdfDocument, PdfLayerReference, Point, Rect};
+
+const FONT_REGULAR: &[u8] = include_bytes!("../assets/fonts/DejaVuSans.ttf");
+const FONT_BOLD: &[u8] = include_bytes!("../assets/fonts/DejaVuSans-Bold.ttf");
+
+const PT_TO_MM: f32 = 25.4 / 72.0;
+const LINE_SPACING: f32 = 1.35;
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum PageSize {
+    A4,
+    A5,
+}
+
+impl PageSize {
+    fn dimensions(self) -> (f32, f32) {
+        match self {
+            PageSize::A4 => (210.0, 297.0),
+            PageSize::A5 => (148.0, 210.0),
+        }
+    }
+
+    fn margin(self) -> f32 {
+        match self {
+            PageSize::A4 => 20.0,
+            PageSize::A5 => 12.0,
+        }
+    }
+
+    fn base_font_size(self) -> f32 {
+        match self {
+            PageSize::A4 => 11.0,
+            PageSize::A5 => 9.5,
+        }
+    }
+}
+
+pub enum PdfBlock {
+    Heading(String),
+    Paragraph(String),
+    // Bold first line followed by a detail line, as in the recipe list
+    Item(String, String),
+    Signature {
+        name: String,
+        details: String,
+        image: Option<Vec<u8>>,
+    },
+    // QR code (dark modules, row by row) with caption lines beside it
+    Verification {
+        lines: Vec<String>,
+        modules: Vec<Vec<bool>>,
+    },
+}
+
+struct TextOp {
+    text: String,
+    size: f32,
+    bold: bool,
+    x: f32,
+    y: f32,
+}
+
+struct ImageOp {
+    data: Vec<u8>,
+    x_center: f32,
+    y: f32,
+    height: f32,
+}
+
+struct PageLayout {
+    texts: Vec<TextOp>,
+    rules: Vec<(f32, f32, f32)>,
+    images: Vec<ImageOp>,
+    // Filled rectangles as (x, y, width, height), used for QR modules
+    boxes: Vec<(f32, f32, f32, f32)>,
+}
+
+struct Layouter<'a> {
+    regular: ttf_parser::Face<'a>,
+    bold: ttf_parser::Face<'a>,
+    page_size: PageSize,
+    header_lines: &'a [String],
+    pages: Vec<PageLayout>,
+    y: f32,
+}
+
+impl<'a> Layouter<'a> {
+    fn width(&self) -> f32 {
+        self.page_size.dimensions().0 - 2.0 * self.page_size.margin()
+    }
+
+    fn bottom_limit(&self) -> f32 {
+        // Room for the page number footer
+        self.page_size.margin() + 8.0
+    }
+
+    fn line_height(size: f32) -> f32 {
+        size * PT_TO_MM * LINE_SPACING
+    }
+
+    fn text_width(&self, text: &str, size: f32, bold: bool) -> f32 {
+        let face = if bold { &self.bold } else { &self.regular };
+        let units = face.units_per_em() as f32;
+        let advance: f32 = text
+            .chars()
+            .map(|c| {
+                face.glyph_index(c)
+                    .and_then(|g| face.glyph_hor_advance(g))
+                    .map(|a| a as f32)
+                    .unwrap_or(units / 2.0)
+            })
+            .sum();
+        advance / units * size * PT_TO_MM
+    }
+
+    fn wrap(&self, text: &str, size: f32, bold: bool, max_width: f32) -> Vec<String> {
+        let mut lines = vec![];
+        let mut current = String::new();
+        for word in text.split_whitespace() {
+            let candidate = if current.is_empty() { word.to_string() } else { format!("{} {}", current, word) };
+            if !current.is_empty() && self.text_width(&candidate, size, bold) > max_width {
+                lines.push(current);
+                current = word.to_string();
+            } else {
+                current = candidate;
+            }
+        }
+        if !current.is_empty() {
+            lines.push(current);
+        }
+        lines
+    }
+
+    fn new_page(&mut self) {
+        let (width, height) = self.page_size.dimensions();
+        let margin = self.page_size.margin();
+        let base = self.page_size.base_font_size();
+        self.pages.push(PageLayout { texts: vec![], rules: vec![], images: vec![], boxes: vec![] });
+        self.y = height - margin;
+
+        if self.header_lines.is_empty() {
+            return;
+        }
+        for (i, line) in self.header_lines.iter().enumerate() {
+            let (size, bold) = if i == 0 { (base + 3.0, true) } else { (base - 2.0, false) };
+            self.y -= Self::line_height(size);
+            let x = (width - self.text_width(line, size, bold)) / 2.0;
+            self.push_text(line.clone(), size, bold, x);
+        }
+        self.y -= 3.0;
+        let y = self.y;
+        if let Some(page) = self.pages.last_mut() {
+            page.rules.push((margin, width - margin, y));
+        }
+        self.y -= 6.0;
+    }
+
+    fn ensure_space(&mut self, needed: f32) {
+        if self.pages.is_empty() || self.y - needed < self.bottom_limit() {
+            self.new_page();
+        }
+    }
+
+    fn push_text(&mut self, text: String, size: f32, bold: bool, x: f32) {
+        let y = self.y;
+        if let Some(page) = self.pages.last_mut() {
+            page.texts.push(TextOp { text, size, bold, x, y });
+        }
+    }
+
+    fn write_lines(&mut self, lines: Vec<String>, size: f32, bold: bool, indent: f32) {
+        let margin = self.page_size.margin();
+        for line in lines {
+            self.ensure_space(Self::line_height(size));
+            self.y -= Self::line_height(size);
+            self.push_text(line, size, bold, margin + indent);
+        }
+    }
+
+    fn title(&mut self, title: &str) {
+        let size = self.page_size.base_font_size() + 5.0;
+        self.ensure_space(Self::line_height(size) * 2.0);
+        self.y -= Self::line_height(size);
+        let x = (self.page_size.dimensions().0 - self.text_width(title, size, true)) / 2.0;
+        self.push_text(title.to_string(), size, true, x);
+        self.y -= 4.0;
+    }
+
+    fn block(&mut self, block: &PdfBlock) {
+        let base = self.page_size.base_font_size();
+        let width = self.width();
+        match block {
+            PdfBlock::Heading(text) => {
+                let size = base + 2.0;
+                // Keep a heading together with at least two lines of content
+                self.ensure_space(Self::line_height(size) + Self::line_height(base) * 2.0 + 3.0);
+                self.y -= 3.0;
+                let lines = self.wrap(text, size, true, width);
+                self.write_lines(lines, size, true, 0.0);
+                self.y -= 1.0;
+            }
+            PdfBlock::Paragraph(text) => {
+                let lines = self.wrap(text, base, false, width);
+                self.write_lines(lines, base, false, 0.0);
+                self.y -= 1.5;
+            }
+            PdfBlock::Item(title, detail) => {
+                let title_lines = self.wrap(title, base, true, width);
+                let detail_lines = self.wrap(detail, base, false, width - 5.0);
+                let needed = Self::line_height(base) * (title_lines.len() + detail_lines.len()) as f32;
+                self.ensure_space(needed.min(Self::line_height(base) * 3.0));
+                self.write_lines(title_lines, base, true, 0.0);
+                self.write_lines(detail_lines, base, false, 5.0);
+                self.y -= 3.0;
+            }
+            PdfBlock::Signature { name, details, image } => {
+                let page_width = self.page_size.dimensions().0;
+                let image_height = if image.is_some() { 18.0 } else { 0.0 };
+                self.ensure_space(30.0 + image_height);
+                self.y -= 15.0 + image_height;
+                let y = self.y;
+                let half = (width * 0.6).min(80.0) / 2.0;
+                if let Some(page) = self.pages.last_mut() {
+                    page.rules.push((page_width / 2.0 - half, page_width / 2.0 + half, y));
+                    if let Some(data) = image {
+                        page.images.push(ImageOp { data: data.clone(), x_center: page_width / 2.0, y: y + 1.0, height: image_height });
+                    }
+                }
+                for (line, bold) in [(name, true), (details, false)] {
+                    if line.is_empty() {
+                        continue;
+                    }
+                    self.y -= Self::line_height(base);
+                    let x = (page_width - self.text_width(line, base, bold)) / 2.0;
+                    self.push_text(line.clone(), base, bold, x);
+                }
+            }
+            PdfBlock::Verification { lines, modules } => {
+                let margin = self.page_size.margin();
+                let qr_size = if matches!(self.page_size, PageSize::A4) { 28.0 } else { 22.0 };
+                self.ensure_space(qr_size + 8.0);
+                self.y -= 8.0;
+                let top = self.y;
+                let module = qr_size / modules.len().max(1) as f32;
+                if let Some(page) = self.pages.last_mut() {
+                    for (row_index, row) in modules.iter().enumerate() {
+                        let y = top - (row_index + 1) as f32 * module;
+                        // Runs of dark modules become one rectangle, avoiding seams between squares
+                        let mut x = 0;
+                        while x < row.len() {
+                            if !row[x] {
+                                x += 1;
+                                continue;
+                            }
+                            let start = x;
+                            while x < row.len() && row[x] {
+                                x += 1;
+                            }
+                            page.boxes.push((margin + start as f32 * module, y, (x - start) as f32 * module, module));
+                        }
+                    }
+                }
+                let size = base - 1.0;
+                for (i, line) in lines.iter().enumerate() {
+                    self.y -= Self::line_height(size);
+                    self.push_text(line.clone(), size, i == 0, margin + qr_size + 5.0);
+                }
+                self.y = top - qr_size;
+            }
+        }
+    }
+}
+
+fn layout(title: &str, header_lines: &[String], blocks: &[PdfBlock], page_size: PageSize) -> Result<Vec<PageLayout>, String> {
+    let regular = ttf_parser::Face::parse(FONT_REGULAR, 0).map_err(|e| e.to_string())?;
+    let bold = ttf_parser::Face::parse(FONT_BOLD, 0).map_err(|e| e.to_string())?;
+    let mut layouter = Layouter { regular, bold, page_size, header_lines, pages: vec![], y: 0.0 };
+    layouter.new_page();
+    layouter.title(title);
+    for block in blocks {
+        layouter.block(block);
+    }
+
+    // Only now is the page count known
+    let total = layouter.pages.len();
+    let size = page_size.base_font_size() - 2.0;
+    for index in 0..total {
+        let footer = format!("Página {} de {}", index + 1, total);
+        let x = (page_size.dimensions().0 - layouter.text_width(&footer, size, false)) / 2.0;
+        layouter.pages[index].texts.push(TextOp { text: footer, size, bold: false, x, y: page_size.margin() });
+    }
+    Ok(layouter.pages)
+}
+
+pub fn render_pdf(title: &str, header_lines: &[String], blocks: &[PdfBlock], page_size: PageSize) -> Result<Vec<u8>, String> {
+    let pages = layout(title, header_lines, blocks, page_size)?;
+    let (width, height) = page_size.dimensions();
+    let (doc, first_page, first_layer) = PdfDocument::new(title, Mm(width), Mm(height), "Camada 1");
+    let regular_font = doc.add_external_font(FONT_REGULAR).map_err(|e| e.to_string())?;
+    let bold_font = doc.add_external_font(FONT_BOLD).map_err(|e| e.to_string())?;
+
+    for (index, page) in pages.iter().enumerate() {
+        let layer = if index == 0 {
+            doc.get_page(first_page).get_layer(first_layer)
+        } else {
+            let (page_index, layer_index) = doc.add_page(Mm(width), Mm(height), "Camada 1");
+            doc.get_page(page_index).get_layer(layer_index)
+        };
+        draw_page(&layer, page, &regular_font, &bold_font);
+    }
+    doc.save_to_bytes().map_err(|e| e.to_string())
+}
+
+fn draw_page(layer: &PdfLayerReference, page: &PageLayout, regular: &IndirectFontRef, bold: &IndirectFontRef) {
+    for op in &page.texts {
+        let font = if op.bold { bold } else { regular };
+        layer.use_text(op.text.clone(), op.size, Mm(op.x), Mm(op.y), font);
+    }
+    layer.set_outline_thickness(0.5);
+    for (x1, x2, y) in &page.rules {
+        layer.add_line(Line {
+            points: vec![(Point::new(Mm(*x1), Mm(*y)), false), (Point::new(Mm(*x2), Mm(*y)), false)],
+            is_closed: false,
+        });
+    }
+    for (x, y, width, height) in &page.boxes {
+        layer.add_rect(Rect::new(Mm(*x), Mm(*y), Mm(x + width), Mm(y + height)));
+    }
+    for op in &page.images {
+        // An unreadable signature image is skipped rather than failing the document
+        let Ok(decoded) = printpdf::image_crate::load_from_memory(&op.data) else {
+            continue;
+        };
+        let dpi = 300.0;
+        let natural_height = decoded.height() as f32 / dpi * 25.4;
+        let scale = op.height / natural_height;
+        let width = decoded.width() as f32 / dpi * 25.4 * scale;
+        Image::from_dynamic_image(&decoded).add_to_layer(
+            layer.clone(),
+            ImageTransform {
+                translate_x: Some(Mm(op.x_center - width / 2.0)),
+                translate_y: Some(Mm(op.y)),
+                scale_x: Some(scale),
+                scale_y: Some(scale),
+                dpi: Some(dpi),
+                ..Default::default()
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(page: &PageLayout) -> Vec<&str> {
+        page.texts.iter().map(|op| op.text.as_str()).collect()
+    }
+
+    #[test]
+    fn long_documents_are_paged_with_the_page_count_in_the_footer() {
+        let blocks: Vec<PdfBlock> = (1..=60)
+            .map(|i| PdfBlock::Item(format!("{}. TOPIRAMATO 25MG", i), "Tomar 1 comprimido à noite".to_string()))
+            .collect();
+        let pages = layout("Receituário", &["Dra. Teste".to_string()], &blocks, PageSize::A5).unwrap();
+        let total = pages.len();
+        assert!(total >= 3, "{} página(s)", total);
+        for (index, page) in pages.iter().enumerate() {
+            assert_eq!(texts(page).last().copied(), Some(format!("Página {} de {}", index + 1, total).as_str()));
+            // The header is repeated on every page
+            assert_eq!(texts(page)[0], "Dra. Teste");
+        }
+        assert!(texts(&pages[total - 1]).contains(&"60. TOPIRAMATO 25MG"));
+
+        let pdf = render_pdf("Receituário", &[], &blocks, PageSize::A5).unwrap();
+        assert!(pdf.starts_with(b"%PDF"));
+    }
+
+    #[test]
+    fn long_item_details_are_wrapped_within_the_margins() {
+        let detail = "Tomar 1 comprimido por via oral à noite, após o jantar, durante trinta dias; suspender se houver parestesia intensa ou alteração de humor";
+        let blocks = [PdfBlock::Item("TOPIRAMATO 25MG".to_string(), detail.to_string())];
+        let pages = layout("Receituário", &[], &blocks, PageSize::A5).unwrap();
+        let page = &pages[0];
+        let detail_ops: Vec<&TextOp> = page.texts.iter().filter(|op| !op.bold && !op.text.starts_with("Página")).collect();
+        assert!(detail_ops.len() > 1);
+        let rejoined: Vec<&str> = detail_ops.iter().map(|op| op.text.as_str()).collect();
+        assert_eq!(rejoined.join(" "), detail);
+
+        let (width, _) = PageSize::A5.dimensions();
+        let margin = PageSize::A5.margin();
+        let measure = Layouter {
+            regular: ttf_parser::Face::parse(FONT_REGULAR, 0).unwrap(),
+            bold: ttf_parser::Face::parse(FONT_BOLD, 0).unwrap(),
+            page_size: PageSize::A5,
+            header_lines: &[],
+            pages: vec![],
+            y: 0.0,
+        };
+        for op in detail_ops {
+            // Indented under the bold title, and never past the right margin
+            assert_eq!(op.x, margin + 5.0);
+            assert!(op.x + measure.text_width(&op.text, op.size, false) <= width - margin);
+        }
+    }
+}
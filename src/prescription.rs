@@ -0,0 +1,496 @@
+// The medication engine: `!PRESCREVO`, `!AUMENTO` and `!SUSPENDO` lines of
+// the CONDUTA section are tokenized into prescriptions and applied, in the
+// order of the encounters, to the state of the medications in use. Every
+// change becomes a history record; the recipes and the colored diffs shown to
+// the doctor come out of the same pass.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Local, NaiveDate};
+use serde::Serialize;
+
+use crate::medfile::MedDocument;
+use crate::site::Encounter;
+
+// (medication, timestamp, field, old value, new value, command)
+pub type ChangeRecord = (String, String, String, String, String, String);
+
+// Medication name -> dosage, dosage_observations, posologia and posology_observations
+pub type Medications = HashMap<String, HashMap<String, String>>;
+
+#[derive(Serialize)]
+pub struct Prescription {
+    pub command: String,
+    pub medication: String,
+    pub dosage: String,
+    pub dosage_observations: String,
+    pub posologia: String,
+    pub posology_observations: String,
+    pub objective: String,
+}
+
+impl From<HashMap<String, String>> for Prescription {
+    fn from(map: HashMap<String, String>) -> Self {
+        Prescription {
+            command: map.get("command").unwrap_or(&"".to_string()).clone(),
+            medication: map.get("medication").unwrap_or(&"".to_string()).clone(),
+            dosage: map.get("dosage").unwrap_or(&"".to_string()).clone(),
+            dosage_observations: map.get("dosage_observations").unwrap_or(&"".to_string()).clone(),
+            posologia: map.get("posologia").unwrap_or(&"".to_string()).clone(),
+            posology_observations: map.get("posology_observations").unwrap_or(&"".to_string()).clone(),
+            objective: map.get("objective").unwrap_or(&"".to_string()).clone(),
+        }
+    }
+}
+
+pub fn is_prescription(line: &str) -> bool {
+    let prefixes = ["!PRESCREVO", "!AUMENTO", "!INCREMENTO", "!DECREMENTO", "!SUSPENDO", "!DESMAME"];
+    prefixes.iter().any(|&p| line.trim().starts_with(p))
+}
+
+pub fn extract_medication_from_return(return_msg: &str) -> Option<String> {
+    // Parse medication name from return messages like:
+    // "ADICIONADO MEDICATION, DOSAGE, : DOSAGE_OBS POSOLOGIA à lista de medicações em uso;"
+    // "Mudanças para MEDICATION:"
+
+    if return_msg.starts_with("ADICIONADO ") {
+        // Extract from "ADICIONADO MEDICATION, ..."
+        if let Some(comma_pos) = return_msg.find(',') {
+            let medication_part = &return_msg[11..comma_pos]; // Skip "ADICIONADO "
+            return Some(medication_part.trim().to_string());
+        }
+    } else if return_msg.starts_with("Mudanças para ") {
+        // Extract from "Mudanças para MEDICATION:"
+        if let Some(colon_pos) = return_msg.find(':') {
+            let medication_part = &return_msg[14..colon_pos]; // Skip "Mudanças para "
+            return Some(medication_part.trim().to_string());
+        }
+    }
+
+    None
+}
+
+pub fn prescription_grabber(conduta_lines: Vec<String>) -> Vec<String> {
+    conduta_lines.into_iter().filter(|line| is_prescription(line)).collect()
+}
+
+pub fn medication_json_populator(line: &str) -> Prescription {
+    let map = medication_list_tokenizer(line);
+    Prescription::from(map)
+}
+
+pub fn prescription_graphs(changes: &[ChangeRecord]) -> String {
+    if changes.is_empty() {
+        return String::new();
+    }
+    let mut medications: std::collections::BTreeSet<String> = std::collections::BTreeSet::new();
+    for (med, _, _, _, _, _) in changes {
+        medications.insert(med.clone());
+    }
+    let mut sorted_changes: Vec<_> = changes.iter().map(|(med, time_str, field, old, new, cmd)| {
+        let time = chrono::NaiveDateTime::parse_from_str(time_str, "%Y-%m-%d %H:%M:%S").unwrap_or_default();
+        (med.clone(), time, field.clone(), old.clone(), new.clone(), cmd.clone())
+    }).collect::<Vec<_>>();
+    sorted_changes.sort_by_key(|(_, time, _, _, _, _)| *time);
+    let mut unique_times: Vec<_> = sorted_changes.iter().map(|(_, time, _, _, _, _)| *time).collect();
+    unique_times.sort();
+    unique_times.dedup();
+    let mut graph = String::from("Gráfico de Evolução das Prescrições:\n");
+    for med in medications {
+        graph.push_str(&format!("{}: ", med));
+        let med_changes: Vec<_> = sorted_changes.iter().filter(|(m, _, _, _, _, _)| m == &med).collect();
+        // Group by timestamp and command
+        let mut unique_events: Vec<(chrono::NaiveDateTime, String)> = vec![];
+        for (_, time, _, _, _, command) in &med_changes {
+            if !unique_events.iter().any(|(t, c)| t == time && c == command) {
+                unique_events.push((*time, command.clone()));
+            }
+        }
+        unique_events.sort_by_key(|(time, _)| *time);
+        // Columns are proportional to the real time elapsed, shared by every medication
+        let first = unique_times.first().copied().unwrap_or_default();
+        let span = unique_times.last().map(|last| (*last - first).num_seconds()).unwrap_or(0).max(1);
+        let mut line = String::new();
+        let mut positions = vec![];
+        for (time, command) in unique_events.iter() {
+            let pos = ((*time - first).num_seconds() * 80 / span) as usize;
+            let date_str = time.format("%d/%m").to_string();
+            let symbol = match command.as_str() {
+                "INCREASE" => '▲',
+                "DECREASE" => '▼',
+                "SUSPEND" => '✕',
+                "PRESCRIBE" => '●',
+                _ => '●',
+            };
+            let label = format!("{}{}", date_str, symbol);
+            positions.push((pos, label));
+        }
+        let mut last_pos = 0;
+        for (pos, label) in positions {
+            for _ in last_pos..pos {
+                line.push('─');
+            }
+            line.push_str(&label);
+            last_pos = pos + label.chars().count();
+        }
+        graph.push_str(&line);
+        graph.push('\n');
+    }
+
+    graph
+}
+
+pub fn generate_diff(medication: &str, old: &HashMap<String, String>, new: &Prescription) -> String {
+    let mut diff_lines = vec![];
+    let fields = vec![
+        ("dosage", &new.dosage),
+        ("dosage_observations", &new.dosage_observations),
+        ("posologia", &new.posologia),
+        ("posology_observations", &new.posology_observations),
+    ];
+    for (field, new_value) in fields {
+        let old_value = old.get(field).map(|s| s.as_str()).unwrap_or("");
+        if old_value != new_value {
+            diff_lines.push(format!("\x1b[31m- {}: {}\x1b[0m", field, old_value));
+            diff_lines.push(format!("\x1b[32m+ {}: {}\x1b[0m", field, new_value));
+        }
+    }
+    if diff_lines.is_empty() {
+        format!("Nenhuma mudança detectada para {}", medication)
+    } else {
+        format!("Mudanças para {}:\n{}", medication, diff_lines.join("\n"))
+    }
+}
+
+pub fn parse_dosage(dosage: &str) -> f64 {
+    // Simple parser for [number] or [fraction]
+    let cleaned = dosage.trim_start_matches('[').trim_end_matches(']').trim();
+    let first_part = cleaned.split_whitespace().next().unwrap_or("");
+    if first_part.contains('/') {
+        let parts: Vec<&str> = first_part.split('/').collect();
+        if parts.len() == 2 {
+            let num: f64 = parts[0].parse().unwrap_or(0.0);
+            let den: f64 = parts[1].parse().unwrap_or(1.0);
+            num / den
+        } else {
+            first_part.parse().unwrap_or(0.0)
+        }
+    } else {
+        first_part.parse().unwrap_or(0.0)
+    }
+}
+
+pub fn recipe_lines(item: &Prescription) -> (String, String) {
+    let line1 = if item.dosage == "1 UNIDADE" {
+        item.medication.to_uppercase()
+    } else {
+        format!("{} {}", item.medication.to_uppercase(), item.dosage)
+    };
+
+    let dosage_obs = if item.dosage_observations.is_empty() {
+        "1 UNIDADE".to_string()
+    } else {
+        item.dosage_observations.clone()
+    };
+
+    let line2 = if item.posology_observations.is_empty() {
+        format!("{} {}", dosage_obs, item.posologia)
+    } else {
+        format!("{} {}, por {}", dosage_obs, item.posologia, item.posology_observations)
+    };
+    (line1, line2)
+}
+
+pub fn prescription_finalizer(items: Vec<Prescription>, medications: &mut Medications, file_time: chrono::DateTime<chrono::Local>) -> (Vec<String>, Vec<String>, Vec<ChangeRecord>) {
+    let mut prescription_return = vec![];
+    let mut prescription_recipe = vec![];
+    let mut changes = vec![];
+    for item in items {
+        if item.command == "PRESCRIBE" && let Some(prev) = medications.get(&item.medication) {
+            // Prescribed again while still in use: recorded field by field, like a change of dose,
+            // so the state before it can be rebuilt from history
+            prescription_return.push(generate_diff(&item.medication, prev, &item));
+            changes.extend(field_changes(&item, prev, file_time));
+            let (line1, line2) = recipe_lines(&item);
+            prescription_recipe.push(format!("{}\n{}", line1, line2));
+            medications.insert(item.medication.clone(), state_of(&item));
+        } else if item.command == "PRESCRIBE" {
+            let mut diff_lines = vec![];
+            if !item.dosage.is_empty() {
+                diff_lines.push(format!("\x1b[32m+ dosage: {}\x1b[0m", item.dosage));
+            }
+            if !item.dosage_observations.trim().is_empty() {
+                diff_lines.push(format!("\x1b[32m+ dosage_observations: {}\x1b[0m", item.dosage_observations.trim()));
+            }
+            if !item.posologia.is_empty() {
+                diff_lines.push(format!("\x1b[32m+ posologia: {}\x1b[0m", item.posologia));
+            }
+            if !item.posology_observations.trim().is_empty() {
+                diff_lines.push(format!("\x1b[32m+ posology_observations: {}\x1b[0m", item.posology_observations.trim()));
+            }
+            if !diff_lines.is_empty() {
+                let ret = format!("Mudanças para {}:\n{}", item.medication, diff_lines.join("\n"));
+                prescription_return.push(ret);
+            }
+
+            let (line1, line2) = recipe_lines(&item);
+            let recipe = format!("{}\n{}", line1, line2);
+            prescription_recipe.push(recipe);
+
+            // Save to medications
+            medications.insert(item.medication.clone(), state_of(&item));
+
+            // Add to changes for initial prescription
+            let timestamp_str = file_time.format("%Y-%m-%d %H:%M:%S").to_string();
+            changes.push((item.medication.clone(), timestamp_str, "initial".to_string(), "".to_string(), item.dosage_observations.clone(), item.command.clone()));
+        } else if item.command == "INCREASE" && let Some(prev) = medications.get(&item.medication) {
+            let diff_output = generate_diff(&item.medication, prev, &item);
+            prescription_return.push(diff_output);
+            // Collect changes for graph
+            changes.extend(field_changes(&item, prev, file_time));
+
+            // Update medications
+            medications.insert(item.medication.clone(), state_of(&item));
+        } else if item.command == "SUSPEND" && let Some(suspension) = suspend(&item, medications, file_time) {
+            prescription_return.push(format!("Mudanças para {}:\n\x1b[31m- suspenso\x1b[0m", item.medication));
+            changes.extend(suspension);
+        }
+    }
+    (prescription_return, prescription_recipe, changes)
+}
+
+fn state_of(item: &Prescription) -> HashMap<String, String> {
+    HashMap::from([
+        ("dosage".to_string(), item.dosage.clone()),
+        ("dosage_observations".to_string(), item.dosage_observations.clone()),
+        ("posologia".to_string(), item.posologia.clone()),
+        ("posology_observations".to_string(), item.posology_observations.clone()),
+    ])
+}
+
+fn field_changes(item: &Prescription, prev: &HashMap<String, String>, file_time: chrono::DateTime<chrono::Local>) -> Vec<ChangeRecord> {
+    // One record per field that differs from the state in force
+    let timestamp_str = file_time.format("%Y-%m-%d %H:%M:%S").to_string();
+    let fields = [
+        ("dosage", &item.dosage),
+        ("dosage_observations", &item.dosage_observations),
+        ("posologia", &item.posologia),
+        ("posology_observations", &item.posology_observations),
+    ];
+    fields
+        .into_iter()
+        .filter(|(field, new_value)| prev.get(*field).map(String::as_str).unwrap_or("") != new_value.as_str())
+        .map(|(field, new_value)| {
+            let old_value = prev.get(field).cloned().unwrap_or_default();
+            (item.medication.clone(), timestamp_str.clone(), field.to_string(), old_value, new_value.clone(), item.command.clone())
+        })
+        .collect()
+}
+
+fn suspend(item: &Prescription, medications: &mut Medications, file_time: chrono::DateTime<chrono::Local>) -> Option<Vec<ChangeRecord>> {
+    // !SUSPENDO of a medication not in use changes nothing. Otherwise it leaves the state, and
+    // every field goes back to empty so the state before the suspension can be rebuilt from history
+    let prev = medications.remove(&item.medication)?;
+    let timestamp_str = file_time.format("%Y-%m-%d %H:%M:%S").to_string();
+    let changes = ["dosage", "dosage_observations", "posologia", "posology_observations"]
+        .into_iter()
+        .map(|field| {
+            let old_value = prev.get(field).cloned().unwrap_or_default();
+            (item.medication.clone(), timestamp_str.clone(), field.to_string(), old_value, String::new(), item.command.clone())
+        })
+        .collect();
+    Some(changes)
+}
+
+pub fn medication_list_tokenizer(line: &str) -> HashMap<String, String> {
+    let mut map = HashMap::new();
+    let trimmed = line.trim_start_matches('!').trim_end_matches(';');
+    let parts: Vec<&str> = trimmed.split_whitespace().collect();
+    let mut idx = 0;
+
+    // Command
+    if idx < parts.len() {
+        let cmd = parts[idx];
+        let command = match cmd {
+            "PRESCREVO" => "PRESCRIBE",
+            "AUMENTO" => "INCREASE",
+            "REDUZO" => "DECREASE",
+            "SUSPENDO" => "SUSPEND",
+            _ => cmd,
+        };
+        map.insert("command".to_string(), command.to_string());
+        idx += 1;
+    }
+
+    // Medication
+    if idx < parts.len() {
+        let mut med = parts[idx].to_string();
+        if med.starts_with('"') || med.starts_with('\'') {
+            let quote = med.chars().next().unwrap();
+            med = med.trim_start_matches(quote).to_string();
+            while idx + 1 < parts.len() && !med.ends_with(quote) {
+                idx += 1;
+                med.push(' ');
+                med.push_str(parts[idx]);
+            }
+            med = med.trim_end_matches(quote).to_string();
+        }
+        map.insert("medication".to_string(), med);
+        idx += 1;
+    }
+
+    // Dosage
+    let mut dosage = "1 UNIDADE".to_string();
+    if idx < parts.len() && !parts[idx].starts_with('[') && !parts[idx].contains(">>") {
+        dosage = parts[idx].to_string();
+        idx += 1;
+    }
+    map.insert("dosage".to_string(), dosage);
+
+    // Dosage observations
+    let mut dosage_obs = String::new();
+    if idx < parts.len() && parts[idx].starts_with('[') {
+        dosage_obs = parts[idx].to_string();
+        idx += 1;
+        while idx < parts.len() && !dosage_obs.ends_with(']') {
+            dosage_obs.push(' ');
+            dosage_obs.push_str(parts[idx]);
+            idx += 1;
+        }
+        dosage_obs = dosage_obs.trim_start_matches('[').trim_end_matches(']').to_string();
+    }
+    map.insert("dosage_observations".to_string(), dosage_obs);
+
+    // Posology
+    let mut posology = String::new();
+    if idx < parts.len() && !parts[idx].contains(">>") {
+        posology = parts[idx].to_string();
+        idx += 1;
+        // If there's a second group
+        if idx < parts.len() && !parts[idx].contains(">>") {
+            posology.push(' ');
+            posology.push_str(parts[idx]);
+            idx += 1;
+        }
+    }
+    map.insert("posologia".to_string(), posology);
+
+    // Posology observations
+    let mut pos_obs = String::new();
+    if idx < parts.len() && parts[idx].starts_with('[') {
+        pos_obs = parts[idx].trim_start_matches('[').trim_end_matches(']').to_string();
+        idx += 1;
+    }
+    map.insert("posology_observations".to_string(), pos_obs);
+
+    // Objective
+    let mut objective = String::new();
+    if idx < parts.len() && parts[idx] == ">>" {
+        idx += 1;
+        while idx < parts.len() {
+            if objective.is_empty() {
+                objective = parts[idx].to_string();
+            } else {
+                objective.push(' ');
+                objective.push_str(parts[idx]);
+            }
+            idx += 1;
+        }
+    }
+    map.insert("objective".to_string(), objective);
+
+    map
+}
+
+pub fn document_prescriptions(doc: &MedDocument) -> Vec<Prescription> {
+    prescription_grabber(doc.section_lines("CONDUTA"))
+        .iter()
+        .map(|line| medication_json_populator(line))
+        .collect()
+}
+
+pub fn replay(encounters: &[Encounter]) -> (Medications, Vec<ChangeRecord>) {
+    // Rebuilds the medication state from scratch, in the order of the encounters
+    let mut medications = HashMap::new();
+    let mut changes = vec![];
+    for encounter in encounters {
+        changes.extend(replay_encounter(encounter, &mut medications));
+    }
+    (medications, changes)
+}
+
+pub fn replay_encounter(encounter: &Encounter, medications: &mut Medications) -> Vec<ChangeRecord> {
+    // Applies one encounter to the state; only the changes the engine accepted are returned
+    let items = document_prescriptions(&encounter.doc);
+    let (_, _, changes) = prescription_finalizer(items, medications, DateTime::<Local>::from(encounter.modified));
+    changes
+}
+
+pub fn medication_history<'a>(history: &'a [ChangeRecord], medication: &str) -> Vec<&'a ChangeRecord> {
+    history.iter().filter(|record| record.0 == medication).collect()
+}
+
+pub fn changes_between(history: &[ChangeRecord], from: NaiveDate, to: NaiveDate) -> Vec<&ChangeRecord> {
+    // Records whose timestamp cannot be read are left out
+    history
+        .iter()
+        .filter(|record| {
+            chrono::NaiveDateTime::parse_from_str(&record.1, "%Y-%m-%d %H:%M:%S")
+                .is_ok_and(|time| time.date() >= from && time.date() <= to)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn run(lines: &[&str], medications: &mut Medications, day: u32) -> (Vec<String>, Vec<ChangeRecord>) {
+        let items = lines.iter().map(|line| medication_json_populator(line)).collect();
+        let time = Local.with_ymd_and_hms(2025, 1, day, 9, 0, 0).unwrap();
+        let (returns, _, changes) = prescription_finalizer(items, medications, time);
+        (returns, changes)
+    }
+
+    #[test]
+    fn suspension_removes_the_medication_and_records_every_field() {
+        let mut medications = Medications::new();
+        run(&["!PRESCREVO TOPIRAMATO 25MG [1 COMPRIMIDO] NOITE;"], &mut medications, 10);
+        let before = medications["TOPIRAMATO"].clone();
+
+        let (returns, changes) = run(&["!SUSPENDO TOPIRAMATO;"], &mut medications, 20);
+        assert!(!medications.contains_key("TOPIRAMATO"));
+        assert_eq!(returns.len(), 1);
+        assert!(returns[0].contains("suspenso"));
+        assert_eq!(changes.len(), 4);
+        for (medication, timestamp, field, old, new, command) in &changes {
+            assert_eq!(medication, "TOPIRAMATO");
+            assert_eq!(timestamp, "2025-01-20 09:00:00");
+            assert_eq!(old, &before[field]);
+            assert!(new.is_empty());
+            assert_eq!(command, "SUSPEND");
+        }
+    }
+
+    #[test]
+    fn suspension_of_a_medication_not_in_use_changes_nothing() {
+        let mut medications = Medications::new();
+        run(&["!PRESCREVO TOPIRAMATO 25MG [1 COMPRIMIDO] NOITE;"], &mut medications, 10);
+        let (returns, changes) = run(&["!SUSPENDO PROPRANOLOL;"], &mut medications, 20);
+        assert!(returns.is_empty() && changes.is_empty());
+        assert_eq!(medications.len(), 1);
+    }
+
+    #[test]
+    fn suspended_medication_can_be_prescribed_again() {
+        let mut medications = Medications::new();
+        run(&["!PRESCREVO TOPIRAMATO 25MG [1 COMPRIMIDO] NOITE;"], &mut medications, 10);
+        run(&["!SUSPENDO TOPIRAMATO;"], &mut medications, 20);
+        let (_, changes) = run(&["!AUMENTO TOPIRAMATO 50MG [1 COMPRIMIDO] NOITE;"], &mut medications, 25);
+        assert!(changes.is_empty(), "!AUMENTO de medicação suspensa não deve valer");
+        run(&["!PRESCREVO TOPIRAMATO 50MG [1 COMPRIMIDO] NOITE;"], &mut medications, 30);
+        assert_eq!(medications["TOPIRAMATO"]["dosage"], "50MG");
+    }
+}
@@ -0,0 +1,326 @@
+// Longitudinal problem list. The encounters of the workspace are replayed in
+// chronological order: every hypothesis of `[HIPOTESE DIAGNOSTICA]` opens or
+// updates a problem, dropping the `?` confirms it, and `!DESCARTO` / `!RESOLVIDO`
+// close it. Problems are identified by their CID-10 code when there is one and
+// by the accent-insensitive name otherwise. Medications are linked through a
+// prescription objective that mentions the problem or an explicit
+// `!INDICACAO MEDICAMENTO: PROBLEMA` directive.
+
+use crate::medfile::{self, Diagnosis};
+use crate::site::{self, Encounter};
+use crate::cid10;
+use crate::html::html_escape;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ProblemStatus {
+    Suspected,
+    Confirmed,
+    RuledOut,
+    Resolved,
+}
+
+impl ProblemStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ProblemStatus::Suspected => "suspeita",
+            ProblemStatus::Confirmed => "confirmado",
+            ProblemStatus::RuledOut => "descartado",
+            ProblemStatus::Resolved => "resolvido",
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        matches!(self, ProblemStatus::Suspected | ProblemStatus::Confirmed)
+    }
+}
+
+pub struct ProblemEvent {
+    pub date: String,
+    pub encounter: String,
+    pub description: String,
+}
+
+pub struct Problem {
+    pub name: String,
+    pub code: Option<String>,
+    pub status: ProblemStatus,
+    pub first_seen: String,
+    pub first_encounter: String,
+    pub last_seen: String,
+    pub confirmed_on: Option<String>,
+    // Date of the `!DESCARTO` / `!RESOLVIDO` that closed the problem
+    pub closed_on: Option<String>,
+    pub medications: Vec<String>,
+    pub events: Vec<ProblemEvent>,
+}
+
+impl Problem {
+    fn new(diagnosis: &Diagnosis, status: ProblemStatus, encounter: &Encounter) -> Problem {
+        Problem {
+            name: diagnosis.name.clone(),
+            code: diagnosis.cid_code(),
+            status,
+            first_seen: encounter.date_str.clone(),
+            first_encounter: encounter.name.clone(),
+            last_seen: encounter.date_str.clone(),
+            confirmed_on: None,
+            closed_on: None,
+            medications: vec![],
+            events: vec![],
+        }
+    }
+
+    fn matches(&self, diagnosis: &Diagnosis) -> bool {
+        // Same code, or same name: "ENXAQUECA {G43}" and a bare "ENXAQUECA" (coded G43.9) are one problem
+        if let (Some(code), Some(other)) = (&self.code, diagnosis.cid_code())
+            && *code == other
+        {
+            return true;
+        }
+        cid10::normalize(&self.name) == cid10::normalize(&diagnosis.name)
+    }
+
+    fn mentioned_in(&self, text: &str) -> bool {
+        // Free text such as a prescription objective: the code or the whole name must appear
+        let text = format!(" {} ", cid10::normalize(text));
+        if let Some(code) = &self.code
+            && text.contains(&format!(" {} ", cid10::normalize(code)))
+        {
+            return true;
+        }
+        let name = cid10::normalize(&self.name);
+        !name.is_empty() && text.contains(&format!(" {} ", name))
+    }
+
+    fn event(&mut self, encounter: &Encounter, description: &str) {
+        self.events.push(ProblemEvent {
+            date: encounter.date_str.clone(),
+            encounter: encounter.name.clone(),
+            description: description.to_string(),
+        });
+    }
+
+    fn link_medication(&mut self, medication: &str) {
+        let medication = medication.trim().trim_matches(|c| c == '\'' || c == '"').to_uppercase();
+        if !medication.is_empty() && !self.medications.contains(&medication) {
+            self.medications.push(medication);
+        }
+    }
+}
+
+pub fn find_problem<'a>(problems: &'a [Problem], diagnosis: &Diagnosis) -> Option<&'a Problem> {
+    problems.iter().find(|p| p.matches(diagnosis))
+}
+
+fn find_or_open<'a>(problems: &'a mut Vec<Problem>, diagnosis: &Diagnosis, status: ProblemStatus, encounter: &Encounter) -> &'a mut Problem {
+    match problems.iter().position(|p| p.matches(diagnosis)) {
+        Some(index) => &mut problems[index],
+        None => {
+            problems.push(Problem::new(diagnosis, status, encounter));
+            let problem = problems.last_mut().unwrap();
+            let opened = if status == ProblemStatus::Suspected { "hipótese levantada (suspeita)" } else { "hipótese levantada" };
+            problem.event(encounter, opened);
+            problem
+        }
+    }
+}
+
+fn record_hypothesis(problems: &mut Vec<Problem>, diagnosis: &Diagnosis, encounter: &Encounter) {
+    let status = if diagnosis.suspected { ProblemStatus::Suspected } else { ProblemStatus::Confirmed };
+    let problem = find_or_open(problems, diagnosis, status, encounter);
+    problem.last_seen = encounter.date_str.clone();
+    if problem.code.is_none() {
+        problem.code = diagnosis.cid_code();
+    }
+    if !problem.status.is_active() {
+        problem.status = status;
+        problem.closed_on = None;
+        problem.event(encounter, "hipótese retomada");
+    }
+    if !diagnosis.suspected && problem.confirmed_on.is_none() {
+        problem.status = ProblemStatus::Confirmed;
+        problem.confirmed_on = Some(encounter.date_str.clone());
+        if problem.first_seen != encounter.date_str || problem.first_encounter != encounter.name {
+            problem.event(encounter, "confirmado");
+        }
+    }
+}
+
+fn record_closure(problems: &mut [Problem], warnings: &mut Vec<String>, diagnosis: &Diagnosis, status: ProblemStatus, encounter: &Encounter) {
+    // Only a hypothesis already listed can be closed; a typo must not create a problem
+    let Some(problem) = problems.iter_mut().find(|p| p.matches(diagnosis)) else {
+        warnings.push(format!("{}: {} não corresponde a nenhuma hipótese anterior", encounter.name, diagnosis.name));
+        return;
+    };
+    problem.status = status;
+    problem.last_seen = encounter.date_str.clone();
+    problem.closed_on = Some(encounter.date_str.clone());
+    problem.event(encounter, status.label());
+}
+
+fn record_indication(problems: &mut [Problem], warnings: &mut Vec<String>, content: &str, encounter: &Encounter) {
+    // !INDICACAO PROLOPA BD: DOENÇA DE PARKINSON {G20}
+    let Some((medication, target)) = content.split_once(':') else {
+        return;
+    };
+    let diagnosis = medfile::parse_diagnosis(target);
+    match problems.iter_mut().find(|p| p.matches(&diagnosis)) {
+        Some(problem) => problem.link_medication(medication),
+        None => warnings.push(format!(
+            "{}: indicação de {} para {}, que não corresponde a nenhuma hipótese anterior",
+            encounter.name,
+            medication.trim(),
+            diagnosis.name
+        )),
+    }
+}
+
+pub fn build_problem_list(encounters: &[Encounter]) -> Vec<Problem> {
+    build_problem_list_with_warnings(encounters).0
+}
+
+pub fn build_problem_list_with_warnings(encounters: &[Encounter]) -> (Vec<Problem>, Vec<String>) {
+    // The warnings name the !DESCARTO, !RESOLVIDO and !INDICACAO that matched no hypothesis
+    let mut problems: Vec<Problem> = vec![];
+    let mut warnings = vec![];
+    for encounter in encounters {
+        for diagnosis in medfile::diagnosis_items(&encounter.doc.section_lines("HIPOTESE DIAGNOSTICA")) {
+            record_hypothesis(&mut problems, &diagnosis, encounter);
+        }
+        for directive in encounter.doc.sections.iter().flat_map(|section| section.directives()) {
+            match directive.keyword.as_str() {
+                "DESCARTO" => {
+                    record_closure(&mut problems, &mut warnings, &medfile::parse_diagnosis(&directive.content), ProblemStatus::RuledOut, encounter)
+                }
+                "RESOLVIDO" => {
+                    record_closure(&mut problems, &mut warnings, &medfile::parse_diagnosis(&directive.content), ProblemStatus::Resolved, encounter)
+                }
+                "INDICACAO" => record_indication(&mut problems, &mut warnings, &directive.content, encounter),
+                _ => {}
+            }
+        }
+        for prescription in site::encounter_prescriptions(encounter) {
+            if prescription.objective.is_empty() {
+                continue;
+            }
+            for problem in problems.iter_mut().filter(|p| p.mentioned_in(&prescription.objective)) {
+                problem.link_medication(&prescription.medication);
+            }
+        }
+    }
+    (problems, warnings)
+}
+
+fn problem_summary(problem: &Problem) -> Vec<String> {
+    let mut lines = vec![format!("Desde {} ({})", problem.first_seen, problem.first_encounter)];
+    if let Some(date) = &problem.confirmed_on
+        && *date != problem.first_seen
+    {
+        lines.push(format!("Confirmado em {}", date));
+    }
+    if let Some(date) = &problem.closed_on {
+        let verb = if problem.status == ProblemStatus::RuledOut { "Descartado" } else { "Resolvido" };
+        lines.push(format!("{} em {}", verb, date));
+    } else {
+        lines.push(format!("Último registro em {}", problem.last_seen));
+    }
+    if !problem.medications.is_empty() {
+        lines.push(format!("Medicações: {}", problem.medications.join(", ")));
+    }
+    lines
+}
+
+fn problem_title(problem: &Problem) -> String {
+    match &problem.code {
+        Some(code) => format!("{} ({})", problem.name, code),
+        None => problem.name.clone(),
+    }
+}
+
+pub fn print_problem_list(problems: &[Problem]) {
+    if problems.is_empty() {
+        println!("Nenhuma hipótese diagnóstica registrada nos atendimentos.");
+        return;
+    }
+    for (title, active) in [("Problemas ativos:", true), ("Problemas encerrados:", false)] {
+        let group: Vec<&Problem> = problems.iter().filter(|p| p.status.is_active() == active).collect();
+        if group.is_empty() {
+            continue;
+        }
+        println!("{}", title);
+        for problem in group {
+            println!("  {} [{}]", problem_title(problem), problem.status.label());
+            for line in problem_summary(problem) {
+                println!("      {}", line);
+            }
+        }
+    }
+}
+
+pub fn generate_problem_list_html(problems: &[Problem]) -> String {
+    if problems.is_empty() {
+        return "<p>Nenhuma hipótese diagnóstica registrada nos atendimentos.</p>".to_string();
+    }
+    let mut html = String::from(
+        "<table class=\"exams problems\"><thead><tr><th>Problema</th><th>Situação</th><th>Início</th><th>Evolução</th><th>Medicações</th></tr></thead><tbody>",
+    );
+    for problem in problems {
+        let code = match &problem.code {
+            Some(code) => format!(" <span class=\"cid\">CID-10 {}</span>", code),
+            None => String::new(),
+        };
+        let events: Vec<String> = problem
+            .events
+            .iter()
+            .map(|event| format!("<span title=\"{}\">{}</span>: {}", html_escape(&event.encounter), event.date, html_escape(&event.description)))
+            .collect();
+        let row_class = if problem.status.is_active() { "" } else { " class=\"closed\"" };
+        html.push_str(&format!(
+            "<tr{}><td>{}{}</td><td><span class=\"badge\">{}</span></td><td>{}<br><small>{}</small></td><td>{}</td><td>{}</td></tr>",
+            row_class,
+            html_escape(&problem.name),
+            code,
+            problem.status.label(),
+            problem.first_seen,
+            html_escape(&problem.first_encounter),
+            events.join("<br>"),
+            html_escape(&problem.medications.join(", "))
+        ));
+    }
+    html.push_str("</tbody></table>");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::medfile::MedDocument;
+
+    fn encounter(name: &str, content: &str) -> Encounter {
+        Encounter {
+            modified: SystemTime::UNIX_EPOCH,
+            name: name.to_string(),
+            slug: site::page_slug(name),
+            date_str: name.rsplit(' ').next().unwrap_or_default().to_string(),
+            doc: MedDocument::parse(content),
+        }
+    }
+
+    #[test]
+    fn closures_and_indications_only_apply_to_listed_hypotheses() {
+        let encounters = [
+            encounter("ANA 2025-01-10", "[HIPOTESE DIAGNOSTICA]\nENXAQUECA {G43};\nINSONIA;\n"),
+            encounter("ANA 2025-02-10", "[CONDUTA]\n!RESOLVIDO INSONIA;\n!DESCARTO ANSIEDADE;\n!INDICACAO TOPIRAMATO: ENXAQUECA;\n!INDICACAO ZOLPIDEM: INSONA;\n"),
+        ];
+        let (problems, warnings) = build_problem_list_with_warnings(&encounters);
+        let names: Vec<&str> = problems.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["ENXAQUECA", "INSONIA"]);
+        assert!(problems[1].status == ProblemStatus::Resolved);
+        assert_eq!(problems[0].medications, ["TOPIRAMATO"]);
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("ANSIEDADE"));
+        assert!(warnings[1].contains("ZOLPIDEM"));
+    }
+}
@@ -0,0 +1,164 @@
+// Professional data shown on every generated document: CRM and state,
+// specialty and RQE, clinic identification and an optional signature image.
+
+use std::fs;
+use std::path::Path;
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::html::html_escape;
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct UserInfo {
+    pub name: String,
+    pub cpf: String,
+    pub phone: String,
+    pub email: String,
+    // Professional profile; older user_info.json files lack these fields
+    #[serde(default)]
+    pub crm: String,
+    #[serde(default)]
+    pub crm_uf: String,
+    #[serde(default)]
+    pub specialty: String,
+    #[serde(default)]
+    pub rqe: String,
+    #[serde(default)]
+    pub clinic_name: String,
+    #[serde(default)]
+    pub clinic_address: String,
+    #[serde(default)]
+    pub clinic_phone: String,
+    #[serde(default)]
+    pub signature_image: String,
+}
+
+
+pub const UF_LIST: [&str; 27] = [
+    "AC", "AL", "AM", "AP", "BA", "CE", "DF", "ES", "GO", "MA", "MG", "MS", "MT", "PA", "PB", "PE", "PI", "PR",
+    "RJ", "RN", "RO", "RR", "RS", "SC", "SE", "SP", "TO",
+];
+
+pub fn validate_crm(crm: &str) -> Result<String, String> {
+    // Accepts "123456", "52.12345-6" and similar, storing only the digits
+    let digits: String = crm.chars().filter(|c| !matches!(c, '.' | '-' | ' ')).collect();
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err("O CRM deve conter apenas números.".to_string());
+    }
+    if digits.len() < 4 || digits.len() > 8 {
+        return Err("O CRM deve ter entre 4 e 8 dígitos.".to_string());
+    }
+    Ok(digits)
+}
+
+pub fn validate_uf(uf: &str) -> Result<String, String> {
+    let uf = uf.trim().to_uppercase();
+    if UF_LIST.contains(&uf.as_str()) {
+        Ok(uf)
+    } else {
+        Err(format!("UF inválida. Use uma de: {}.", UF_LIST.join(", ")))
+    }
+}
+
+pub fn validate_rqe(rqe: &str) -> Result<String, String> {
+    let rqe = rqe.trim();
+    if rqe.is_empty() || rqe.chars().all(|c| c.is_ascii_digit()) {
+        Ok(rqe.to_string())
+    } else {
+        Err("O RQE deve conter apenas números.".to_string())
+    }
+}
+
+pub fn validate_signature_image(path: &str) -> Result<String, String> {
+    let path = path.trim();
+    if path.is_empty() {
+        return Ok(String::new());
+    }
+    let lower = path.to_lowercase();
+    if !(lower.ends_with(".png") || lower.ends_with(".jpg") || lower.ends_with(".jpeg")) {
+        return Err("A assinatura deve ser uma imagem PNG ou JPEG.".to_string());
+    }
+    if !Path::new(path).is_file() {
+        return Err(format!("Arquivo {} não encontrado.", path));
+    }
+    // Stored as an absolute path, so it does not depend on where medfiles runs
+    fs::canonicalize(path)
+        .map(|absolute| absolute.to_string_lossy().to_string())
+        .map_err(|e| format!("Falha ao localizar {}: {}", path, e))
+}
+
+pub fn has_professional_profile(user: &UserInfo) -> bool {
+    !user.crm.is_empty() && !user.crm_uf.is_empty()
+}
+
+pub fn registration_line(user: &UserInfo) -> String {
+    let mut parts = vec![];
+    if !user.crm.is_empty() {
+        parts.push(format!("CRM-{} {}", user.crm_uf, user.crm));
+    }
+    if !user.specialty.is_empty() {
+        parts.push(user.specialty.clone());
+    }
+    if !user.rqe.is_empty() {
+        parts.push(format!("RQE {}", user.rqe));
+    }
+    parts.join(" · ")
+}
+
+pub fn header_lines(user: &UserInfo) -> Vec<String> {
+    let contact = if user.clinic_phone.is_empty() {
+        format!("Tel: {} · {}", user.phone, user.email)
+    } else {
+        format!("Tel: {} · {}", user.clinic_phone, user.email)
+    };
+    [
+        user.name.clone(),
+        registration_line(user),
+        user.clinic_name.clone(),
+        user.clinic_address.clone(),
+        contact,
+    ]
+    .into_iter()
+    .filter(|line| !line.is_empty())
+    .collect()
+}
+
+pub fn generate_doctor_header_html(user: &UserInfo) -> String {
+    let lines = header_lines(user);
+    let Some((name, rest)) = lines.split_first() else {
+        return String::new();
+    };
+    let mut html = format!("        <div class=\"doctor-header\"><strong>{}</strong>", html_escape(name));
+    for line in rest {
+        html.push_str(&format!("<br>{}", html_escape(line)));
+    }
+    html.push_str("</div>\n");
+    html
+}
+
+pub fn signature_image_mime(bytes: &[u8]) -> &'static str {
+    // validate_signature_image only accepts PNG and JPEG
+    if bytes.starts_with(b"\x89PNG") { "image/png" } else { "image/jpeg" }
+}
+
+pub fn generate_signature_html(user: &UserInfo, signature_image: Option<&[u8]>) -> String {
+    // The image bytes are read by the caller; the library does not open user_info paths
+    let image = match signature_image {
+        Some(bytes) => {
+            let mime = signature_image_mime(bytes);
+            format!(
+                "<img src=\"data:{};base64,{}\" alt=\"Assinatura\"><br>",
+                mime,
+                base64::engine::general_purpose::STANDARD.encode(bytes)
+            )
+        }
+        None => String::new(),
+    };
+    format!(
+        "\n    <div class=\"signature\">{}<span class=\"signature-line\"></span><br>{}<br>{}</div>\n",
+        image,
+        html_escape(&user.name),
+        html_escape(&registration_line(user))
+    )
+}
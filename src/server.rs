@@ -0,0 +1,461 @@
+// Local web server for the dashboard (`medfiles serve`). Every request reads
+// the current .med files and renders the page with the same generators as the
+// static site, so nothing is written to disk. Pages poll /__versao, a
+// fingerprint of the .med files, and reload when it changes. /imprimir/<página>
+// is the print-friendly version of any page. /imprimir/prescricao shows the
+// current medications with a form; only its POST, carrying a single-use nonce
+// from that form, issues the numbered prescription. The server only listens
+// on loopback and refuses requests whose Host is not a local name, so a page
+// from another site cannot reach it through DNS rebinding. /api/v1 is the
+// JSON API (api.rs). Requests are answered one at a time, which keeps the
+// audit log chain in order.
+
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Write};
+use std::net::{IpAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use medfiles::html::{generate_html_footer, generate_html_header, generate_recipe_section, html_escape, parse_prescription_to_list};
+use medfiles::{prescription, site, MedDocument};
+
+use crate::{audit, crypto, signing, storage};
+
+const VERSION_ROUTE: &str = "/__versao";
+const PRINT_PREFIX: &str = "/imprimir/";
+const ISSUE_ROUTE: &str = "/imprimir/prescricao";
+const MAX_NONCES: usize = 32;
+const MAX_BODY: usize = 4 * 1024;
+
+// Nonces handed out with the issuance form and not used yet
+static NONCES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+const LIVE_RELOAD: &str = r#"<script>
+    (function () {
+        let version = null;
+        setInterval(() => {
+            fetch('/__versao').then(r => r.text()).then(current => {
+                if (version !== null && current !== version) location.reload();
+                version = current;
+            }).catch(() => {});
+        }, 1000);
+    })();
+</script>
+"#;
+
+const PRINT_MODE: &str = "<style>.navbar, .print-button, .filter { display: none; }</style>\n<script>window.addEventListener('load', () => window.print());</script>\n";
+
+pub struct Response {
+    pub status: &'static str,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    fn html(body: String) -> Response {
+        Response { status: "200 OK", content_type: "text/html; charset=utf-8", body }
+    }
+
+    pub fn json(status: &'static str, value: &serde_json::Value) -> Response {
+        Response {
+            status,
+            content_type: "application/json; charset=utf-8",
+            body: serde_json::to_string_pretty(value).unwrap_or_default(),
+        }
+    }
+
+    fn forbidden(message: &str) -> Response {
+        Response { status: "403 Forbidden", content_type: "text/plain; charset=utf-8", body: message.to_string() }
+    }
+
+    fn error(message: &str) -> Response {
+        Response { status: "500 Internal Server Error", content_type: "text/plain; charset=utf-8", body: message.to_string() }
+    }
+
+    fn not_found(path: &str) -> Response {
+        Response {
+            status: "404 Not Found",
+            content_type: "text/html; charset=utf-8",
+            body: format!("<p>Página não encontrada: {}</p><p><a href=\"/\">Voltar ao início</a></p>", medfiles::html::html_escape(path)),
+        }
+    }
+}
+
+pub fn workspace_version() -> String {
+    // Changes whenever a .med file is created, saved or removed
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    for (path, modified) in crate::medfile_finder() {
+        path.hash(&mut hasher);
+        modified.hash(&mut hasher);
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+fn inject(page: &str, snippet: &str, before: &str) -> String {
+    match page.rfind(before) {
+        Some(position) => format!("{}{}{}", &page[..position], snippet, &page[position..]),
+        None => format!("{}{}", page, snippet),
+    }
+}
+
+fn current_prescription(files: &[(std::path::PathBuf, std::time::SystemTime)]) -> (Vec<String>, String) {
+    // Same replay as conduta_handler, without touching medications.json or history.json
+    let mut medications = HashMap::new();
+    let mut latest_returns: HashMap<String, String> = HashMap::new();
+    let mut recipe_content = String::new();
+    for (path, modified) in files {
+        let Ok(content) = storage::read_to_string(path) else {
+            continue;
+        };
+        let items = prescription::document_prescriptions(&MedDocument::parse(&content));
+        let (returns, recipes, _) = prescription::prescription_finalizer(items, &mut medications, chrono::DateTime::<chrono::Local>::from(*modified));
+        for return_msg in returns {
+            if let Some(medication) = prescription::extract_medication_from_return(&return_msg) {
+                latest_returns.insert(medication, return_msg);
+            }
+        }
+        if !recipes.is_empty() {
+            recipe_content = recipes.join("\n\n");
+        }
+    }
+    if recipe_content.is_empty() {
+        recipe_content = "Nenhuma receita encontrada.".to_string();
+    }
+    (latest_returns.into_values().collect(), recipe_content)
+}
+
+fn new_nonce() -> String {
+    let nonce = crypto::random_hex(16);
+    if let Ok(mut nonces) = NONCES.lock() {
+        if nonces.len() >= MAX_NONCES {
+            nonces.remove(0);
+        }
+        nonces.push(nonce.clone());
+    }
+    nonce
+}
+
+fn take_nonce(nonce: &str) -> bool {
+    let Ok(mut nonces) = NONCES.lock() else {
+        return false;
+    };
+    match nonces.iter().position(|n| n == nonce) {
+        Some(index) => {
+            nonces.remove(index);
+            true
+        }
+        None => false,
+    }
+}
+
+fn form_field<'a>(body: &'a str, name: &str) -> Option<&'a str> {
+    body.split('&').filter_map(|pair| pair.split_once('=')).find(|(key, _)| *key == name).map(|(_, value)| value)
+}
+
+fn issue_form(files: &[(std::path::PathBuf, std::time::SystemTime)]) -> Response {
+    // Only shows what would be issued; nothing is numbered, archived or audited here
+    let (_, recipe_content) = current_prescription(files);
+    let (doctor, _) = crate::load_doctor();
+    let mut html = generate_html_header("Emitir Prescrição", doctor.as_ref());
+    html.push_str(&generate_recipe_section(&parse_prescription_to_list(&recipe_content)));
+    html.push_str(&format!(
+        "        <form method=\"post\" action=\"{}\">\n            <input type=\"hidden\" name=\"nonce\" value=\"{}\">\n            <button class=\"print-button\" type=\"submit\">Emitir e imprimir</button>\n        </form>\n",
+        ISSUE_ROUTE,
+        html_escape(&new_nonce())
+    ));
+    html.push_str(&generate_html_footer());
+    Response::html(html)
+}
+
+fn issue_prescription(body: &str) -> Response {
+    if !form_field(body, "nonce").is_some_and(take_nonce) {
+        return Response::forbidden("Formulário expirado ou inválido; abra /imprimir/prescricao novamente.");
+    }
+    let files = crate::medfile_finder();
+    let encounters = crate::load_encounters(&files);
+    let (returns, recipe_content) = current_prescription(&files);
+    let (html, issued_number) = crate::prescription_document(&recipe_content, &returns, &encounters, files.len());
+    // Signed as served, print mode included, so the saved page verifies
+    let html = match signing::sign_page(&signing::page_name(&issued_number), &inject(&html, PRINT_MODE, "</head>")) {
+        Ok(signed) => signed,
+        Err(e) => return Response::error(&e),
+    };
+    for (file, _) in &files {
+        audit::record_file("imprimir", file, &format!("prescrição nº {} no servidor local", issued_number));
+    }
+    Response::html(html)
+}
+
+pub fn route(path: &str) -> Response {
+    let path = path.split('?').next().unwrap_or(path);
+    if path == VERSION_ROUTE {
+        return Response { status: "200 OK", content_type: "text/plain; charset=utf-8", body: workspace_version() };
+    }
+    let files = crate::medfile_finder();
+    if path == ISSUE_ROUTE {
+        return issue_form(&files);
+    }
+    let encounters = crate::load_encounters(&files);
+
+    let (print, name) = match path.strip_prefix(PRINT_PREFIX) {
+        Some(name) => (true, name),
+        None => (false, path.trim_start_matches('/')),
+    };
+    let name = if name.is_empty() { "index.html" } else { name };
+    let (medications, _) = crate::medication_state_replay(&files);
+    let (doctor, signature) = crate::load_doctor();
+    let Some(page) = site::render_page(name, &encounters, &medications, doctor.as_ref(), signature.as_deref()) else {
+        return Response::not_found(path);
+    };
+    // Views of a single encounter are audited against its file, the others against the workspace
+    match encounters.iter().find(|e| site::attendance_page_name(e) == name || site::prescription_page_name(e) == name) {
+        Some(encounter) => audit::record_file("visualizar", Path::new(&encounter.name), "servidor local"),
+        None => audit::record("visualizar", &crate::workspace_name(), &format!("{} no servidor local", name)),
+    }
+    if print {
+        // Links of the printed page lead back to the live pages
+        let page = inject(&page, "<base href=\"/\">\n", "</head>");
+        Response::html(inject(&page, PRINT_MODE, "</head>"))
+    } else {
+        Response::html(inject(&page, LIVE_RELOAD, "</body>"))
+    }
+}
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub host: Option<String>,
+    pub authorization: Option<String>,
+    pub body: String,
+}
+
+fn header(lines: &[&str], name: &str) -> Option<String> {
+    lines
+        .iter()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim().to_string())
+}
+
+fn read_request(stream: &mut TcpStream) -> Option<Request> {
+    let mut buffer = vec![];
+    let mut chunk = [0u8; 2048];
+    while !buffer.windows(4).any(|w| w == b"\r\n\r\n") && buffer.len() < 16 * 1024 {
+        let read = stream.read(&mut chunk).ok()?;
+        if read == 0 {
+            break;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+    let header_end = buffer.windows(4).position(|w| w == b"\r\n\r\n").map(|p| p + 4).unwrap_or(buffer.len());
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let lines: Vec<&str> = head.lines().collect();
+    let mut parts = lines.first()?.split_whitespace();
+    let (method, path) = (parts.next()?.to_string(), parts.next()?.to_string());
+    let headers: Vec<&str> = lines[1..].iter().copied().take_while(|line| !line.is_empty()).collect();
+
+    // Only the issuance form has a body, and a small one
+    let length: usize = header(&headers, "content-length").and_then(|l| l.parse().ok()).unwrap_or(0).min(MAX_BODY);
+    let mut body = buffer[header_end..].to_vec();
+    while body.len() < length {
+        let read = stream.read(&mut chunk).ok()?;
+        if read == 0 {
+            break;
+        }
+        body.extend_from_slice(&chunk[..read]);
+    }
+    body.truncate(length);
+    Some(Request {
+        method,
+        path,
+        host: header(&headers, "host"),
+        authorization: header(&headers, "authorization"),
+        body: String::from_utf8_lossy(&body).to_string(),
+    })
+}
+
+fn is_local_host(host: &str) -> bool {
+    // "localhost:8080", "127.0.0.1:8080" or "[::1]:8080"
+    let name = match host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => host.rsplit_once(':').map(|(name, _)| name).unwrap_or(host),
+    };
+    name.eq_ignore_ascii_case("localhost") || name.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+pub fn respond(request: &Request) -> Response {
+    // A name that resolves to 127.0.0.1 after the page was loaded still sends its own Host
+    if !request.host.as_deref().is_some_and(is_local_host) {
+        return Response::forbidden("Host não permitido; acesse o painel por localhost ou 127.0.0.1.");
+    }
+    let path = request.path.split('?').next().unwrap_or(&request.path);
+    match request.method.as_str() {
+        "POST" if path == ISSUE_ROUTE => return issue_prescription(&request.body),
+        "GET" => {}
+        _ => {
+            return Response { status: "405 Method Not Allowed", content_type: "text/plain; charset=utf-8", body: "Método não permitido".to_string() };
+        }
+    }
+    if request.path.starts_with(crate::api::PREFIX) {
+        return crate::api::route(&request.path, request.authorization.as_deref());
+    }
+    route(&request.path)
+}
+
+fn handle(mut stream: TcpStream) {
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+    let Some(request) = read_request(&mut stream) else {
+        return;
+    };
+    let response = respond(&request);
+    let header = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    let _ = stream.write_all(header.as_bytes());
+    let _ = stream.write_all(response.body.as_bytes());
+}
+
+pub fn serve(listener: TcpListener) {
+    // Runs until the process ends; a listener bound to port 0 serves an in-process instance
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => handle(stream),
+            Err(e) => eprintln!("Falha na conexão: {}", e),
+        }
+    }
+}
+
+pub fn run(host: &str, port: u16) -> Result<(), String> {
+    // The pages have no authentication, so they are never offered to the network
+    let addresses: Vec<_> = (host, port).to_socket_addrs().map_err(|e| format!("Endereço inválido {}: {}", host, e))?.collect();
+    if addresses.is_empty() || addresses.iter().any(|address| !address.ip().is_loopback()) {
+        return Err(format!("O painel só pode ser aberto em um endereço local (127.0.0.1, ::1 ou localhost), não em {}.", host));
+    }
+    let listener = TcpListener::bind(addresses.as_slice()).map_err(|e| format!("Falha ao abrir o servidor em {}:{}: {}", host, port, e))?;
+    let address = listener.local_addr().map_err(|e| format!("Falha ao abrir o servidor: {}", e))?;
+    println!("Painel disponível em http://{}/ (Ctrl+C para sair)", address);
+    println!("Prescrição para impressão em http://{}{}", address, ISSUE_ROUTE);
+    println!("API JSON em http://{}{}/ (descrição em {}/openapi.json)", address, crate::api::PREFIX, crate::api::PREFIX);
+    serve(listener);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{self, Workspace};
+
+    const ENCOUNTER: &str = "[ANAMNESE]\nCefaleia.\n[CONDUTA]\n!PRESCREVO TOPIRAMATO 25MG [1 COMPRIMIDO] NOITE;\n";
+
+    #[test]
+    fn pages_are_rendered_with_live_reload() {
+        let workspace = Workspace::new();
+        workspace.file("MARIA SOUZA 2025-01-10.med", ENCOUNTER);
+        let server = testing::start_server();
+
+        let index = testing::get(server, "/", None);
+        assert_eq!(index.status, 200);
+        assert!(index.headers.contains("text/html"));
+        assert!(index.body.contains("/__versao"));
+
+        let print = testing::get(server, "/imprimir/index.html", None);
+        assert_eq!(print.status, 200);
+        assert!(print.body.contains("window.print()"));
+        assert!(!print.body.contains("/__versao"));
+
+        assert_eq!(testing::get(server, "/nada.html", None).status, 404);
+    }
+
+    #[test]
+    fn version_changes_when_a_med_file_is_added() {
+        let workspace = Workspace::new();
+        workspace.file("MARIA SOUZA 2025-01-10.med", ENCOUNTER);
+        let server = testing::start_server();
+        let before = testing::get(server, "/__versao", None).body;
+        assert_eq!(testing::get(server, "/__versao", None).body, before);
+        workspace.file("MARIA SOUZA 2025-02-20.med", ENCOUNTER);
+        assert_ne!(testing::get(server, "/__versao", None).body, before);
+    }
+
+    #[test]
+    fn only_get_is_accepted_for_pages() {
+        let _workspace = Workspace::new();
+        let server = testing::start_server();
+        let response = testing::http(server, &format!("DELETE / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", server));
+        assert_eq!(response.status, 405);
+        assert_eq!(testing::post(server, "/", "").status, 405);
+    }
+
+    #[test]
+    fn requests_for_other_hosts_are_refused() {
+        let workspace = Workspace::new();
+        workspace.file("MARIA SOUZA 2025-01-10.med", ENCOUNTER);
+        let server = testing::start_server();
+        let request = |host: &str| testing::http(server, &format!("GET / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", host)).status;
+        assert_eq!(request("medfiles.example.com:8080"), 403);
+        assert_eq!(request("192.168.0.10"), 403);
+        assert_eq!(testing::http(server, "GET / HTTP/1.0\r\n\r\n").status, 403);
+        assert_eq!(request("localhost:8080"), 200);
+        assert_eq!(request("[::1]:8080"), 200);
+        assert_eq!(request("127.0.0.1"), 200);
+    }
+
+    #[test]
+    fn network_addresses_are_refused() {
+        assert!(super::run("0.0.0.0", 0).unwrap_err().contains("endereço local"));
+    }
+
+    fn form_nonce(page: &str) -> String {
+        let start = page.find("name=\"nonce\" value=\"").expect("formulário de emissão") + "name=\"nonce\" value=\"".len();
+        page[start..].split('"').next().unwrap().to_string()
+    }
+
+    #[test]
+    fn prescription_is_issued_only_by_the_form_post() {
+        let workspace = Workspace::new();
+        workspace.file("MARIA SOUZA 2025-01-10.med", ENCOUNTER);
+        let server = testing::start_server();
+        let issued = || workspace.dir.path().join(crate::issuance::ARCHIVE_PATH).exists();
+        let audited = || std::fs::read_to_string(crate::audit::AUDIT_PATH).unwrap_or_default().contains("imprimir");
+
+        let form = testing::get(server, "/imprimir/prescricao", None);
+        assert_eq!(form.status, 200);
+        assert!(form.body.contains("TOPIRAMATO"));
+        assert!(form.body.contains("method=\"post\""));
+        assert!(!issued() && !audited());
+
+        assert_eq!(testing::post(server, "/imprimir/prescricao", "").status, 403);
+        assert_eq!(testing::post(server, "/imprimir/prescricao", "nonce=0123").status, 403);
+        assert!(!issued());
+
+        let nonce = form_nonce(&form.body);
+        let printed = testing::post(server, "/imprimir/prescricao", &format!("nonce={}", nonce));
+        assert_eq!(printed.status, 200);
+        assert!(printed.body.contains("window.print()"));
+        assert!(issued() && audited());
+
+        // A reload of the printed page does not issue a second number
+        assert_eq!(testing::post(server, "/imprimir/prescricao", &format!("nonce={}", nonce)).status, 403);
+    }
+
+    #[test]
+    fn issued_prescription_page_carries_the_signature() {
+        let workspace = Workspace::new();
+        workspace.file("MARIA SOUZA 2025-01-10.med", ENCOUNTER);
+        workspace.file(crate::signing::KEY_PATH, include_str!("../tests/fixtures/signing/key.pem"));
+        let server = testing::start_server();
+        let nonce = form_nonce(&testing::get(server, "/imprimir/prescricao", None).body);
+        let printed = testing::post(server, "/imprimir/prescricao", &format!("nonce={}", nonce));
+        assert_eq!(printed.status, 200);
+
+        // The page as saved from the browser
+        let saved = workspace.file("prescricao.html", &printed.body);
+        let report = crate::signing::verify(&saved).unwrap();
+        assert!(report.embedded);
+        assert!(report.record.document.starts_with("prescricao-"));
+    }
+}
@@ -0,0 +1,548 @@
+// Digital signatures for issued documents. The doctor's Ed25519 private key
+// (PKCS#8 PEM, signing_key.pem) signs the SHA-256 of each document written to
+// disk, optionally together with an X.509 certificate for that key
+// (signing_cert.pem). The signature record is stored in a `<document>.sig.json`
+// sidecar and also embedded as a trailing comment in the HTML or PDF, so
+// either one is enough for `medfiles verify`.
+//
+// A valid signature only proves that the document was not changed since it
+// was signed with the key stored in the record. Whose key that is comes from
+// the trust sources below: the local signing key, fingerprints pinned in
+// trusted_signers.txt, or a certificate issued by the CA in trusted_ca.pem.
+// Anything else is reported as an unverified signer.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use ed25519_dalek::pkcs8::spki::der::pem::LineEnding;
+use ed25519_dalek::pkcs8::{DecodePrivateKey, EncodePrivateKey};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use x509_cert::der::{DecodePem, Encode};
+use x509_cert::Certificate;
+
+use medfiles::MedError;
+
+use crate::crypto;
+
+pub const KEY_PATH: &str = "signing_key.pem";
+pub const CERT_PATH: &str = "signing_cert.pem";
+pub const TRUSTED_SIGNERS_PATH: &str = "trusted_signers.txt";
+pub const TRUSTED_CA_PATH: &str = "trusted_ca.pem";
+const ALGORITHM: &str = "Ed25519";
+const ED25519_OID: &str = "1.3.101.112";
+const HTML_MARKER: &[u8] = b"\n<!-- medfiles-signature ";
+const HTML_END: &[u8] = b" -->\n";
+const PDF_MARKER: &[u8] = b"\n%medfiles-signature ";
+const PDF_END: &[u8] = b"\n";
+
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct SignatureRecord {
+    pub version: u32,
+    pub algorithm: String,
+    pub document: String,
+    pub sha256: String,
+    pub signed_at: String,
+    pub signer: String,
+    pub public_key: String,
+    pub signature: String,
+    #[serde(default)]
+    pub certificate: Option<String>,
+}
+
+pub struct VerifyReport {
+    pub record: SignatureRecord,
+    pub fingerprint: String,
+    pub certificate_subject: Option<String>,
+    pub trust: Option<Trust>,
+    pub embedded: bool,
+    pub sidecar: bool,
+}
+
+// How the signing key was tied to a known signer
+#[derive(Debug, PartialEq)]
+pub enum Trust {
+    LocalKey,
+    PinnedFingerprint,
+    CertificateAuthority(String),
+}
+
+fn b64() -> base64::engine::GeneralPurpose {
+    base64::engine::general_purpose::STANDARD
+}
+
+pub fn fingerprint(key: &VerifyingKey) -> String {
+    // Short, human-comparable form of the public key
+    let digest = Sha256::digest(key.as_bytes());
+    digest[..16].chunks(2).map(crypto::hex).collect::<Vec<_>>().join(":")
+}
+
+fn key_path() -> PathBuf {
+    PathBuf::from(env::var("MEDFILES_SIGNING_KEY").unwrap_or_else(|_| KEY_PATH.to_string()))
+}
+
+fn cert_path() -> PathBuf {
+    PathBuf::from(env::var("MEDFILES_SIGNING_CERT").unwrap_or_else(|_| CERT_PATH.to_string()))
+}
+
+fn trusted_signers_path() -> PathBuf {
+    PathBuf::from(env::var("MEDFILES_TRUSTED_SIGNERS").unwrap_or_else(|_| TRUSTED_SIGNERS_PATH.to_string()))
+}
+
+fn trusted_ca_path() -> PathBuf {
+    PathBuf::from(env::var("MEDFILES_TRUSTED_CA").unwrap_or_else(|_| TRUSTED_CA_PATH.to_string()))
+}
+
+fn signed_message(sha256: &str, signed_at: &str, signer: &str) -> String {
+    format!("medfiles-signature-v1\n{}\n{}\n{}\n", sha256, signed_at, signer)
+}
+
+fn sidecar_path(document: &Path) -> PathBuf {
+    let mut name = document.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+    name.push(".sig.json");
+    document.with_file_name(name)
+}
+
+fn is_pdf(document: &Path) -> bool {
+    document.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"))
+}
+
+fn load_signing_key() -> Result<Option<SigningKey>, String> {
+    let path = key_path();
+    if !path.exists() {
+        return Ok(None);
+    }
+    let pem = crate::storage::read_to_string(&path).map_err(|e| format!("Falha ao ler {}: {}", path.display(), e))?;
+    SigningKey::from_pkcs8_pem(&pem)
+        .map(Some)
+        .map_err(|e| format!("Chave de assinatura inválida em {} (esperado Ed25519 PKCS#8): {}", path.display(), e))
+}
+
+fn parse_certificate(pem: &str) -> Result<(Certificate, VerifyingKey), String> {
+    let cert = Certificate::from_pem(pem.as_bytes()).map_err(|e| format!("Certificado X.509 inválido: {}", e))?;
+    let spki = &cert.tbs_certificate.subject_public_key_info;
+    if spki.algorithm.oid.to_string() != ED25519_OID {
+        return Err(format!("O certificado não é Ed25519 (algoritmo {}).", spki.algorithm.oid));
+    }
+    let raw: [u8; 32] = spki
+        .subject_public_key
+        .raw_bytes()
+        .try_into()
+        .map_err(|_| "Chave pública do certificado com tamanho inválido.".to_string())?;
+    let key = VerifyingKey::from_bytes(&raw).map_err(|e| e.to_string())?;
+    Ok((cert, key))
+}
+
+// Checks that `cert` was issued and signed by the configured CA
+fn issued_by_trusted_ca(cert: &Certificate) -> Result<Option<String>, String> {
+    let path = trusted_ca_path();
+    if !path.exists() {
+        return Ok(None);
+    }
+    let pem = fs::read_to_string(&path).map_err(|e| format!("Falha ao ler {}: {}", path.display(), e))?;
+    let (ca, ca_key) = parse_certificate(&pem).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if cert.tbs_certificate.issuer != ca.tbs_certificate.subject
+        || cert.signature_algorithm.oid.to_string() != ED25519_OID
+    {
+        return Ok(None);
+    }
+    let tbs = cert.tbs_certificate.to_der().map_err(|e| e.to_string())?;
+    let Ok(signature) = Signature::from_slice(cert.signature.raw_bytes()) else {
+        return Ok(None);
+    };
+    Ok(ca_key.verify(&tbs, &signature).is_ok().then(|| ca.tbs_certificate.subject.to_string()))
+}
+
+fn pinned_fingerprints() -> Result<Vec<String>, String> {
+    let path = trusted_signers_path();
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let text = fs::read_to_string(&path).map_err(|e| format!("Falha ao ler {}: {}", path.display(), e))?;
+    // One fingerprint per line, as printed by `medfiles keygen`; anything after it is a comment
+    Ok(text
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .filter(|fingerprint| !fingerprint.starts_with('#'))
+        .map(|fingerprint| fingerprint.to_lowercase())
+        .collect())
+}
+
+fn trust_of(key: &VerifyingKey, certificate: Option<&Certificate>) -> Result<Option<Trust>, String> {
+    if let Some(cert) = certificate
+        && let Some(ca) = issued_by_trusted_ca(cert)?
+    {
+        return Ok(Some(Trust::CertificateAuthority(ca)));
+    }
+    if let Ok(Some(local)) = load_signing_key()
+        && local.verifying_key() == *key
+    {
+        return Ok(Some(Trust::LocalKey));
+    }
+    if pinned_fingerprints()?.contains(&fingerprint(key)) {
+        return Ok(Some(Trust::PinnedFingerprint));
+    }
+    Ok(None)
+}
+
+fn load_certificate(key: &VerifyingKey) -> Result<Option<String>, String> {
+    let path = cert_path();
+    if !path.exists() {
+        return Ok(None);
+    }
+    let pem = fs::read_to_string(&path).map_err(|e| format!("Falha ao ler {}: {}", path.display(), e))?;
+    let (_, cert_key) = parse_certificate(&pem)?;
+    if cert_key != *key {
+        return Err(format!("O certificado {} não corresponde à chave de assinatura.", path.display()));
+    }
+    Ok(Some(pem))
+}
+
+fn signer_description() -> String {
+    match crate::load_user_info() {
+        Some(user) => {
+            let registration = medfiles::profile::registration_line(&user);
+            if registration.is_empty() { user.name } else { format!("{} ({})", user.name, registration) }
+        }
+        None => String::new(),
+    }
+}
+
+fn embed(document: &Path, content: &[u8], record: &SignatureRecord) -> Result<Vec<u8>, String> {
+    let json = serde_json::to_string(record).map_err(|e| e.to_string())?;
+    let (marker, end) = if is_pdf(document) { (PDF_MARKER, PDF_END) } else { (HTML_MARKER, HTML_END) };
+    let mut out = content.to_vec();
+    out.extend_from_slice(marker);
+    out.extend_from_slice(b64().encode(json).as_bytes());
+    out.extend_from_slice(end);
+    Ok(out)
+}
+
+fn split_embedded(data: &[u8]) -> (&[u8], Option<SignatureRecord>) {
+    for (marker, end) in [(HTML_MARKER, HTML_END), (PDF_MARKER, PDF_END)] {
+        let Some(start) = data.windows(marker.len()).rposition(|w| w == marker) else {
+            continue;
+        };
+        let tail = &data[start + marker.len()..];
+        let encoded = tail.strip_suffix(end).unwrap_or(tail);
+        let record = b64()
+            .decode(encoded)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok());
+        if record.is_some() {
+            return (&data[..start], record);
+        }
+    }
+    (data, None)
+}
+
+pub fn sign(document: &Path, content: &[u8]) -> Result<Option<SignatureRecord>, String> {
+    let Some(key) = load_signing_key()? else {
+        return Ok(None);
+    };
+    let verifying_key = key.verifying_key();
+    let certificate = load_certificate(&verifying_key)?;
+    let sha256 = crypto::sha256_hex(content);
+    let signed_at = chrono::Local::now().to_rfc3339();
+    let signer = signer_description();
+    let signature = key.sign(signed_message(&sha256, &signed_at, &signer).as_bytes());
+    Ok(Some(SignatureRecord {
+        version: 1,
+        algorithm: ALGORITHM.to_string(),
+        document: document.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default(),
+        sha256,
+        signed_at,
+        signer,
+        public_key: b64().encode(verifying_key.as_bytes()),
+        signature: b64().encode(signature.to_bytes()),
+        certificate,
+    }))
+}
+
+pub fn write_document(document: &Path, content: &[u8]) -> Result<Option<PathBuf>, MedError> {
+    // Without a configured key the document is written unsigned, as before
+    let Some(record) = sign(document, content).map_err(|e| MedError::config(KEY_PATH, e))? else {
+        fs::write(document, content).map_err(|e| MedError::io(document, e))?;
+        return Ok(None);
+    };
+    let signed = embed(document, content, &record).map_err(|e| MedError::parse(document, e))?;
+    fs::write(document, signed).map_err(|e| MedError::io(document, e))?;
+    let sidecar = sidecar_path(document);
+    let json = serde_json::to_string_pretty(&record).map_err(|e| MedError::parse(&sidecar, e))?;
+    fs::write(&sidecar, json).map_err(|e| MedError::io(&sidecar, e))?;
+    Ok(Some(sidecar))
+}
+
+pub fn page_name(issued_number: &str) -> String {
+    if issued_number.is_empty() { "prescricao.html".to_string() } else { format!("prescricao-{}.html", issued_number) }
+}
+
+pub fn sign_page(name: &str, html: &str) -> Result<String, String> {
+    // Pages served to the browser never touch the disk, so the record is only embedded;
+    // the page saved from the browser is verified like any signed HTML
+    let document = Path::new(name);
+    match sign(document, html.as_bytes())? {
+        Some(record) => String::from_utf8(embed(document, html.as_bytes(), &record)?).map_err(|e| e.to_string()),
+        None => Ok(html.to_string()),
+    }
+}
+
+pub fn verify(document: &Path) -> Result<VerifyReport, String> {
+    let data = fs::read(document).map_err(|e| format!("Falha ao ler {}: {}", document.display(), e))?;
+    let (content, embedded) = split_embedded(&data);
+    let sidecar_file = sidecar_path(document);
+    let sidecar: Option<SignatureRecord> = match fs::read_to_string(&sidecar_file) {
+        Ok(json) => Some(serde_json::from_str(&json).map_err(|e| format!("Falha ao parsear {}: {}", sidecar_file.display(), e))?),
+        Err(_) => None,
+    };
+    let (has_embedded, has_sidecar) = (embedded.is_some(), sidecar.is_some());
+    let record = match (embedded, sidecar) {
+        (Some(embedded), Some(sidecar)) if embedded != sidecar => {
+            return Err(format!("A assinatura embutida difere de {}.", sidecar_file.display()));
+        }
+        (Some(record), _) | (None, Some(record)) => record,
+        (None, None) => return Err("Documento sem assinatura MedFiles.".to_string()),
+    };
+    if record.algorithm != ALGORITHM {
+        return Err(format!("Algoritmo de assinatura não suportado: {}.", record.algorithm));
+    }
+
+    if crypto::sha256_hex(content) != record.sha256 {
+        return Err("O conteúdo do documento foi alterado após a assinatura.".to_string());
+    }
+    let key_bytes: [u8; 32] = b64()
+        .decode(&record.public_key)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or("Chave pública inválida na assinatura.")?;
+    let key = VerifyingKey::from_bytes(&key_bytes).map_err(|e| e.to_string())?;
+    let signature_bytes = b64().decode(&record.signature).map_err(|e| e.to_string())?;
+    let signature = Signature::from_slice(&signature_bytes).map_err(|e| e.to_string())?;
+    key.verify(signed_message(&record.sha256, &record.signed_at, &record.signer).as_bytes(), &signature)
+        .map_err(|_| "Assinatura inválida.".to_string())?;
+
+    let certificate = match &record.certificate {
+        Some(pem) => {
+            let (cert, cert_key) = parse_certificate(pem)?;
+            if cert_key != key {
+                return Err("O certificado não corresponde à chave que assinou o documento.".to_string());
+            }
+            let signed_at = chrono::DateTime::parse_from_rfc3339(&record.signed_at).map_err(|e| e.to_string())?;
+            let signed_secs = signed_at.timestamp().max(0) as u64;
+            let validity = &cert.tbs_certificate.validity;
+            if signed_secs < validity.not_before.to_unix_duration().as_secs()
+                || signed_secs > validity.not_after.to_unix_duration().as_secs()
+            {
+                return Err("O documento foi assinado fora do período de validade do certificado.".to_string());
+            }
+            Some(cert)
+        }
+        None => None,
+    };
+    let trust = trust_of(&key, certificate.as_ref())?;
+
+    Ok(VerifyReport {
+        fingerprint: fingerprint(&key),
+        record,
+        certificate_subject: certificate.map(|cert| cert.tbs_certificate.subject.to_string()),
+        trust,
+        embedded: has_embedded,
+        sidecar: has_sidecar,
+    })
+}
+
+pub fn generate_key() -> Result<String, String> {
+    let path = key_path();
+    if path.exists() {
+        return Err(format!("{} já existe.", path.display()));
+    }
+    let key = SigningKey::generate(&mut rand::rngs::OsRng);
+    let pem = key.to_pkcs8_pem(LineEnding::LF).map_err(|e| e.to_string())?;
+    crate::storage::write(&path, pem.as_bytes()).map_err(|e| format!("Falha ao gravar {}: {}", path.display(), e))?;
+    Ok(fingerprint(&key.verifying_key()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Workspace;
+
+    // Generated with `openssl genpkey -algorithm ed25519` and `openssl req -x509`, for the tests only
+    const KEY: &str = include_str!("../tests/fixtures/signing/key.pem");
+    const CERT: &str = include_str!("../tests/fixtures/signing/cert.pem");
+    const OTHER_KEY: &str = include_str!("../tests/fixtures/signing/other_key.pem");
+    const OTHER_CERT: &str = include_str!("../tests/fixtures/signing/other_cert.pem");
+    // ca_cert.pem issued issued_cert.pem for KEY; forged_cert.pem is OTHER_KEY's certificate from a
+    // look-alike CA with the same name
+    const CA_CERT: &str = include_str!("../tests/fixtures/signing/ca_cert.pem");
+    const ISSUED_CERT: &str = include_str!("../tests/fixtures/signing/issued_cert.pem");
+    const FORGED_CERT: &str = include_str!("../tests/fixtures/signing/forged_cert.pem");
+    const EXPIRED_CERT: &str = include_str!("../tests/fixtures/signing/expired_cert.pem");
+    const PAGE: &[u8] = b"<!DOCTYPE html>\n<html><body><p>TOPIRAMATO 25MG</p></body></html>\n";
+
+    fn signed_page(workspace: &Workspace) -> PathBuf {
+        let document = workspace.dir.path().join("prescricao.html");
+        assert!(write_document(&document, PAGE).unwrap().is_some());
+        document
+    }
+
+    fn error(document: &Path) -> String {
+        verify(document).err().expect("a verificação deveria falhar")
+    }
+
+    #[test]
+    fn signed_document_verifies_from_the_embedded_record_and_the_sidecar() {
+        let workspace = Workspace::new();
+        workspace.file(KEY_PATH, KEY);
+        let document = signed_page(&workspace);
+
+        let report = verify(&document).unwrap();
+        assert!(report.embedded && report.sidecar);
+        assert_eq!(report.record.document, "prescricao.html");
+        assert_eq!(report.record.sha256, crypto::sha256_hex(PAGE));
+        assert!(report.certificate_subject.is_none());
+        assert_eq!(report.trust, Some(Trust::LocalKey));
+        let key = SigningKey::from_pkcs8_pem(KEY).unwrap();
+        assert_eq!(report.fingerprint, fingerprint(&key.verifying_key()));
+
+        // Either copy is enough on its own
+        fs::remove_file(sidecar_path(&document)).unwrap();
+        assert!(verify(&document).unwrap().embedded);
+    }
+
+    #[test]
+    fn pdf_documents_are_signed_too() {
+        let workspace = Workspace::new();
+        workspace.file(KEY_PATH, KEY);
+        let document = workspace.dir.path().join("prescricao.pdf");
+        write_document(&document, b"%PDF-1.4\n%%EOF\n").unwrap();
+        assert!(fs::read(&document).unwrap().windows(PDF_MARKER.len()).any(|w| w == PDF_MARKER));
+        assert!(verify(&document).is_ok());
+    }
+
+    #[test]
+    fn without_a_key_documents_are_written_unsigned() {
+        let workspace = Workspace::new();
+        let document = workspace.dir.path().join("prescricao.html");
+        assert!(write_document(&document, PAGE).unwrap().is_none());
+        assert_eq!(fs::read(&document).unwrap(), PAGE);
+        assert!(error(&document).contains("sem assinatura"));
+    }
+
+    #[test]
+    fn tampered_document_is_rejected() {
+        let workspace = Workspace::new();
+        workspace.file(KEY_PATH, KEY);
+        let document = signed_page(&workspace);
+        let tampered = String::from_utf8(fs::read(&document).unwrap()).unwrap().replace("25MG", "250MG");
+        fs::write(&document, tampered).unwrap();
+        assert!(error(&document).contains("alterado"));
+    }
+
+    #[test]
+    fn tampered_sidecar_is_rejected() {
+        let workspace = Workspace::new();
+        workspace.file(KEY_PATH, KEY);
+        let document = signed_page(&workspace);
+        let sidecar = sidecar_path(&document);
+        let mut record: SignatureRecord = serde_json::from_str(&fs::read_to_string(&sidecar).unwrap()).unwrap();
+        record.signer = "Outro Médico (CRM 000)".to_string();
+        fs::write(&sidecar, serde_json::to_string(&record).unwrap()).unwrap();
+        assert!(error(&document).contains("difere"));
+
+        // With only the sidecar left, the signature itself no longer matches
+        fs::write(&document, PAGE).unwrap();
+        assert_eq!(error(&document), "Assinatura inválida.");
+    }
+
+    #[test]
+    fn certificate_is_embedded_and_checked() {
+        let workspace = Workspace::new();
+        workspace.file(KEY_PATH, KEY);
+        workspace.file(CERT_PATH, CERT);
+        let document = signed_page(&workspace);
+        let report = verify(&document).unwrap();
+        assert_eq!(report.record.certificate.as_deref(), Some(CERT));
+        assert!(report.certificate_subject.unwrap().contains("CN=Dra. Teste"));
+    }
+
+    #[test]
+    fn signature_with_a_forged_key_is_intact_but_not_trusted() {
+        let workspace = Workspace::new();
+        workspace.file(KEY_PATH, OTHER_KEY);
+        let document = signed_page(&workspace);
+        // The doctor's own key is back in place when the document is checked
+        workspace.file(KEY_PATH, KEY);
+        let report = verify(&document).unwrap();
+        assert_eq!(report.trust, None);
+
+        workspace.file(TRUSTED_SIGNERS_PATH, &format!("# Dr. Colega\n{} Dr. Colega\n", report.fingerprint));
+        assert_eq!(verify(&document).unwrap().trust, Some(Trust::PinnedFingerprint));
+    }
+
+    #[test]
+    fn certificate_issued_by_the_trusted_ca_identifies_the_signer() {
+        let workspace = Workspace::new();
+        workspace.file(KEY_PATH, KEY);
+        workspace.file(CERT_PATH, ISSUED_CERT);
+        let document = signed_page(&workspace);
+        fs::remove_file(KEY_PATH).unwrap();
+        assert_eq!(verify(&document).unwrap().trust, None);
+
+        workspace.file(TRUSTED_CA_PATH, CA_CERT);
+        let Some(Trust::CertificateAuthority(ca)) = verify(&document).unwrap().trust else {
+            panic!("o certificado deveria ser aceito pela AC");
+        };
+        assert!(ca.contains("CN=AC Teste MedFiles"));
+    }
+
+    #[test]
+    fn self_made_certificates_are_not_trusted() {
+        let workspace = Workspace::new();
+        workspace.file(TRUSTED_CA_PATH, CA_CERT);
+        for (key, cert) in [(OTHER_KEY, FORGED_CERT), (OTHER_KEY, OTHER_CERT), (KEY, CERT)] {
+            workspace.file(KEY_PATH, key);
+            workspace.file(CERT_PATH, cert);
+            let document = signed_page(&workspace);
+            fs::remove_file(KEY_PATH).unwrap();
+            let report = verify(&document).unwrap();
+            assert!(report.certificate_subject.is_some());
+            assert_eq!(report.trust, None);
+        }
+    }
+
+    #[test]
+    fn certificate_of_another_key_is_refused() {
+        let workspace = Workspace::new();
+        workspace.file(KEY_PATH, KEY);
+        workspace.file(CERT_PATH, OTHER_CERT);
+        let error = write_document(&workspace.dir.path().join("prescricao.html"), PAGE).unwrap_err();
+        assert!(matches!(error, MedError::Config { .. }));
+        assert!(error.to_string().contains("não corresponde"));
+    }
+
+    #[test]
+    fn signature_outside_the_certificate_validity_is_rejected() {
+        let workspace = Workspace::new();
+        workspace.file(KEY_PATH, KEY);
+        workspace.file(CERT_PATH, EXPIRED_CERT);
+        let document = signed_page(&workspace);
+        assert!(error(&document).contains("fora do período de validade"));
+    }
+
+    #[test]
+    fn embedded_certificate_must_match_the_signing_key() {
+        let workspace = Workspace::new();
+        workspace.file(KEY_PATH, KEY);
+        let document = signed_page(&workspace);
+        let sidecar = sidecar_path(&document);
+        let mut record: SignatureRecord = serde_json::from_str(&fs::read_to_string(&sidecar).unwrap()).unwrap();
+        record.certificate = Some(OTHER_CERT.to_string());
+        fs::write(&document, PAGE).unwrap();
+        fs::write(&sidecar, serde_json::to_string(&record).unwrap()).unwrap();
+        assert!(error(&document).contains("não corresponde"));
+    }
+}
@@ -0,0 +1,462 @@
+// Static site generation for the patient dashboard: index, listings,
+// per-encounter attendance and prescription pages, the medication timeline
+// and a printable view of every active medication. `render_page` is shared
+// with the local server, which renders the same pages on request. Every page
+// is rendered on each run, since the pages of one encounter also show the
+// encounters before it and the doctor profile, but a file is only rewritten
+// when its content changed. Pages of encounters that no longer exist are
+// removed by `remove_stale_pages`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::error::MedError;
+use crate::medfile::MedDocument;
+use crate::html::{
+    generate_html_footer, generate_html_header_with_nav, generate_recipe_section, generate_signature_section, generate_timeline_section,
+    html_escape, parse_prescription_to_list, prescription_graphs_html,
+};
+use crate::prescription::{self, recipe_lines, Prescription};
+use crate::profile::UserInfo;
+
+pub struct Encounter {
+    pub modified: SystemTime,
+    pub name: String,
+    pub slug: String,
+    pub date_str: String,
+    pub doc: MedDocument,
+}
+
+pub struct SiteReport {
+    pub written: usize,
+    pub unchanged: usize,
+}
+
+pub fn load_encounters(files: &[(PathBuf, SystemTime)], read: impl Fn(&Path) -> io::Result<String>) -> (Vec<Encounter>, Vec<MedError>) {
+    // `read` lets the caller decide how the files are opened, e.g. decrypting them.
+    // A file that cannot be read is skipped and returned with the errors.
+    let mut encounters = vec![];
+    let mut skipped = vec![];
+    for (path, modified) in files {
+        let content = match read(path) {
+            Ok(content) => content,
+            Err(e) => {
+                skipped.push(MedError::read(path, e));
+                continue;
+            }
+        };
+        let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let datetime = chrono::DateTime::<chrono::Local>::from(*modified);
+        encounters.push(Encounter {
+            modified: *modified,
+            slug: page_slug(&name),
+            date_str: datetime.format("%d/%m/%Y").to_string(),
+            name,
+            doc: MedDocument::parse(&content),
+        });
+    }
+    (encounters, skipped)
+}
+
+pub fn encounter_dates(modified: impl IntoIterator<Item = SystemTime>) -> Vec<chrono::NaiveDate> {
+    let mut dates: Vec<chrono::NaiveDate> = modified
+        .into_iter()
+        .map(|time| chrono::DateTime::<chrono::Local>::from(time).date_naive())
+        .collect();
+    dates.dedup();
+    dates
+}
+
+pub fn patient_name(file_name: &str) -> String {
+    // "MARTA DOS SANTOS BEZERRA 2025-03-15.med" and "MARTA DOS SANTOS BEZERRA.med" are the same patient
+    let stem = file_name.strip_suffix(".med").unwrap_or(file_name);
+    let name = stem.trim_end_matches(|c: char| c.is_ascii_digit() || matches!(c, '-' | '_' | '.' | ' '));
+    if name.is_empty() { stem.to_string() } else { name.to_string() }
+}
+
+pub fn group_by_patient(files: Vec<(PathBuf, SystemTime)>) -> Vec<(String, Vec<(PathBuf, SystemTime)>)> {
+    let mut patients: Vec<(String, Vec<(PathBuf, SystemTime)>)> = vec![];
+    for (path, modified) in files {
+        let name = patient_name(&path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default());
+        match patients.iter_mut().find(|(patient, _)| *patient == name) {
+            Some((_, files)) => files.push((path, modified)),
+            None => patients.push((name, vec![(path, modified)])),
+        }
+    }
+    patients.sort_by(|a, b| a.0.cmp(&b.0));
+    patients
+}
+
+pub fn page_slug(file_name: &str) -> String {
+    file_name
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect()
+}
+
+pub fn attendance_page_name(encounter: &Encounter) -> String {
+    format!("attendance_{}.html", encounter.slug)
+}
+
+pub fn prescription_page_name(encounter: &Encounter) -> String {
+    format!("prescription_{}.html", encounter.slug)
+}
+
+pub fn generate_navbar() -> String {
+    "    <nav class=\"navbar\"><a href=\"index.html\">Início</a><a href=\"all-attendances.html\">Atendimentos</a><a href=\"all-prescriptions.html\">Prescrições</a><a href=\"problems.html\">Problemas</a><a href=\"timeline.html\">Linha do Tempo</a><a href=\"print-all-prescriptions.html\">Imprimir Todas</a></nav>\n".to_string()
+}
+
+pub fn encounter_prescriptions(encounter: &Encounter) -> Vec<Prescription> {
+    prescription::document_prescriptions(&encounter.doc)
+}
+
+fn recipe_content(prescriptions: &[Prescription]) -> String {
+    prescriptions
+        .iter()
+        .map(|p| {
+            let (line1, line2) = recipe_lines(p);
+            format!("{}\n{}", html_escape(&line1), html_escape(&line2))
+        })
+        .collect::<Vec<String>>()
+        .join("\n\n")
+}
+
+pub fn state_prescriptions(medications: &HashMap<String, HashMap<String, String>>) -> Vec<Prescription> {
+    let mut names: Vec<&String> = medications.keys().collect();
+    names.sort();
+    names
+        .into_iter()
+        .map(|name| {
+            let mut map = medications[name].clone();
+            map.insert("medication".to_string(), name.clone());
+            map.insert("command".to_string(), "PRESCRIBE".to_string());
+            Prescription::from(map)
+        })
+        .collect()
+}
+
+fn anamnesis_excerpt(encounter: &Encounter) -> String {
+    let text = encounter
+        .doc
+        .section("ANAMNESE")
+        .map(|s| s.free_text().join(" "))
+        .unwrap_or_default();
+    if text.chars().count() > 50 {
+        format!("{}...", text.chars().take(50).collect::<String>())
+    } else {
+        text
+    }
+}
+
+pub fn generate_prescription_page(encounter: &Encounter, doctor: Option<&UserInfo>, signature_image: Option<&[u8]>) -> String {
+    let prescriptions = encounter_prescriptions(encounter);
+    let mut html = generate_html_header_with_nav(
+        &format!("Prescrição: {}", html_escape(&encounter.name)),
+        &generate_navbar(),
+        doctor,
+    );
+    html.push_str(&format!(
+        "        <p class=\"attendance-date\"><strong>Data:</strong> {}</p>\n",
+        encounter.date_str
+    ));
+    if prescriptions.is_empty() {
+        html.push_str(&generate_recipe_section("<p>Nenhuma prescrição neste atendimento.</p>"));
+    } else {
+        html.push_str(&generate_recipe_section(&parse_prescription_to_list(&recipe_content(&prescriptions))));
+    }
+    html.push_str(&generate_signature_section(doctor, signature_image));
+    html.push_str("        <button class=\"print-button\" onclick=\"window.print()\">Imprimir</button>\n");
+    html.push_str(&generate_html_footer());
+    html
+}
+
+pub fn generate_index_page(encounters: &[Encounter], doctor: Option<&UserInfo>) -> String {
+    let mut html = generate_html_header_with_nav("Painel de Controle MedFiles", &generate_navbar(), doctor);
+    html.push_str("<div class=\"dashboard\"><div class=\"card\"><h2>Últimos Atendimentos</h2><ul>");
+    for encounter in encounters.iter().rev().take(5) {
+        html.push_str(&format!(
+            "<li><a href=\"{}\">{} - {}</a></li>",
+            attendance_page_name(encounter),
+            html_escape(&encounter.name),
+            encounter.date_str
+        ));
+    }
+    html.push_str("</ul><a href=\"all-attendances.html\">Ver Todos</a></div>");
+
+    html.push_str("<div class=\"card\"><h2>Últimas Prescrições</h2><ul>");
+    let with_prescriptions = encounters
+        .iter()
+        .rev()
+        .filter(|e| !encounter_prescriptions(e).is_empty())
+        .take(5);
+    for encounter in with_prescriptions {
+        let medications: Vec<String> = encounter_prescriptions(encounter)
+            .iter()
+            .map(|p| p.medication.to_uppercase())
+            .collect();
+        html.push_str(&format!(
+            "<li><a href=\"{}\">{} - {}</a></li>",
+            prescription_page_name(encounter),
+            html_escape(&medications.join(", ")),
+            encounter.date_str
+        ));
+    }
+    html.push_str("</ul><a href=\"all-prescriptions.html\">Ver Todas</a></div></div>");
+
+    let history = crate::vitals::vitals_history(encounters);
+    if let Some((date, latest)) = history.last() {
+        html.push_str(&format!("<div class=\"card\"><h2>Sinais Vitais</h2><p>Último registro em {}</p>", date));
+        html.push_str(&crate::vitals::generate_vitals_table(latest));
+        html.push_str(&crate::vitals::generate_vitals_charts(&history));
+        html.push_str("</div>");
+    }
+    let doses = prescription_graphs_html(encounters);
+    if !doses.is_empty() {
+        html.push_str(&format!("<div class=\"card\"><h2>Doses ao Longo do Tempo</h2>{}</div>", doses));
+    }
+    html.push_str(&generate_html_footer());
+    html
+}
+
+pub fn generate_all_attendances_page(encounters: &[Encounter], doctor: Option<&UserInfo>) -> String {
+    let mut html = generate_html_header_with_nav("Todos os Atendimentos", &generate_navbar(), doctor);
+    html.push_str("<input type=\"text\" class=\"filter\" id=\"filter\" placeholder=\"Filtrar atendimentos...\">");
+    html.push_str("<table class=\"exams\" id=\"attendances-table\"><thead><tr><th>Data</th><th>Atendimento</th><th>Anamnese</th><th>Prescrição</th></tr></thead><tbody>");
+    for encounter in encounters.iter().rev() {
+        html.push_str(&format!(
+            "<tr><td>{}</td><td><a href=\"{}\">{}</a></td><td>{}</td><td><a href=\"{}\">Imprimir</a></td></tr>",
+            encounter.date_str,
+            attendance_page_name(encounter),
+            html_escape(&encounter.name),
+            html_escape(&anamnesis_excerpt(encounter)),
+            prescription_page_name(encounter)
+        ));
+    }
+    html.push_str("</tbody></table>");
+    html.push_str(r#"
+    <script>
+        const rows = document.querySelectorAll('#attendances-table tbody tr');
+        document.getElementById('filter').addEventListener('input', (event) => {
+            const query = event.target.value.toLowerCase();
+            rows.forEach(row => {
+                row.style.display = row.textContent.toLowerCase().includes(query) ? '' : 'none';
+            });
+        });
+    </script>
+"#);
+    html.push_str(&generate_html_footer());
+    html
+}
+
+pub fn generate_all_prescriptions_page(
+    medications: &HashMap<String, HashMap<String, String>>,
+    encounters: &[Encounter],
+    doctor: Option<&UserInfo>,
+) -> String {
+    let mut html = generate_html_header_with_nav("Todas as Prescrições", &generate_navbar(), doctor);
+    let current = state_prescriptions(medications);
+    if current.is_empty() {
+        html.push_str(&generate_recipe_section("<p>Nenhuma medicação em uso.</p>"));
+    } else {
+        html.push_str(&generate_recipe_section(&parse_prescription_to_list(&recipe_content(&current))));
+    }
+    let graph_html = prescription_graphs_html(encounters);
+    if !graph_html.is_empty() {
+        html.push_str(&generate_timeline_section(&graph_html));
+    }
+    html.push_str("        <button class=\"print-button\" onclick=\"window.open('print-all-prescriptions.html', '_blank')\">Imprimir Todas as Medicações</button>\n");
+    html.push_str(&generate_html_footer());
+    html
+}
+
+pub fn generate_problems_page(encounters: &[Encounter], doctor: Option<&UserInfo>) -> String {
+    let mut html = generate_html_header_with_nav("Lista de Problemas", &generate_navbar(), doctor);
+    html.push_str(&crate::problems::generate_problem_list_html(&crate::problems::build_problem_list(encounters)));
+    html.push_str(&generate_html_footer());
+    html
+}
+
+pub fn generate_print_all_page(
+    medications: &HashMap<String, HashMap<String, String>>,
+    encounters: &[Encounter],
+    doctor: Option<&UserInfo>,
+    signature_image: Option<&[u8]>,
+) -> String {
+    let mut html = generate_html_header_with_nav("Prescrição Completa", &generate_navbar(), doctor);
+    html.push_str(&generate_recipe_section(&parse_prescription_to_list(&recipe_content(
+        &state_prescriptions(medications),
+    ))));
+    for encounter in encounters.iter().rev() {
+        let prescriptions = encounter_prescriptions(encounter);
+        if prescriptions.is_empty() {
+            continue;
+        }
+        html.push_str(&format!(
+            "<div class=\"page-break\"></div><h2>{} - {}</h2>{}",
+            html_escape(&encounter.name),
+            encounter.date_str,
+            parse_prescription_to_list(&recipe_content(&prescriptions))
+        ));
+    }
+    html.push_str(&generate_signature_section(doctor, signature_image));
+    html.push_str("        <button class=\"print-button\" onclick=\"window.print()\">Imprimir</button>\n");
+    html.push_str(&generate_html_footer());
+    html
+}
+
+pub fn generate_timeline_page(encounters: &[Encounter], doctor: Option<&UserInfo>) -> String {
+    let mut html = generate_html_header_with_nav("Linha do Tempo das Medicações", &generate_navbar(), doctor);
+    let today = chrono::Local::now().date_naive();
+    let bars = crate::gantt::bars_from_doses(&crate::doses::dose_history(encounters), today);
+    let dates = encounter_dates(encounters.iter().map(|e| e.modified));
+    html.push_str(&crate::gantt::generate_gantt_html(&bars, &dates, today));
+    html.push_str(&generate_html_footer());
+    html
+}
+
+pub const PAGES: [&str; 6] = [
+    "index.html",
+    "all-attendances.html",
+    "all-prescriptions.html",
+    "problems.html",
+    "timeline.html",
+    "print-all-prescriptions.html",
+];
+
+pub fn render_page(
+    name: &str,
+    encounters: &[Encounter],
+    medications: &HashMap<String, HashMap<String, String>>,
+    doctor: Option<&UserInfo>,
+    signature_image: Option<&[u8]>,
+) -> Option<String> {
+    // Fixed pages first, then the attendance and prescription page of each encounter
+    let page = match name {
+        "index.html" => generate_index_page(encounters, doctor),
+        "all-attendances.html" => generate_all_attendances_page(encounters, doctor),
+        "all-prescriptions.html" => generate_all_prescriptions_page(medications, encounters, doctor),
+        "problems.html" => generate_problems_page(encounters, doctor),
+        "timeline.html" => generate_timeline_page(encounters, doctor),
+        "print-all-prescriptions.html" => generate_print_all_page(medications, encounters, doctor, signature_image),
+        _ => {
+            let index = encounters
+                .iter()
+                .position(|e| attendance_page_name(e) == name || prescription_page_name(e) == name)?;
+            let encounter = &encounters[index];
+            if prescription_page_name(encounter) == name {
+                generate_prescription_page(encounter, doctor, signature_image)
+            } else {
+                crate::attendance::generate_attendance_html(
+                    &encounter.name,
+                    &encounter.date_str,
+                    &encounter.doc,
+                    &generate_navbar(),
+                    &encounters[..=index],
+                    doctor,
+                )
+            }
+        }
+    };
+    Some(page)
+}
+
+pub fn write_if_changed(path: &Path, content: &str) -> io::Result<bool> {
+    if let Ok(existing) = fs::read_to_string(path)
+        && existing == content
+    {
+        return Ok(false);
+    }
+    fs::write(path, content)?;
+    Ok(true)
+}
+
+pub fn generate_site(
+    out_dir: &Path,
+    encounters: &[Encounter],
+    medications: &HashMap<String, HashMap<String, String>>,
+    doctor: Option<&UserInfo>,
+    signature_image: Option<&[u8]>,
+) -> Result<SiteReport, MedError> {
+    fs::create_dir_all(out_dir).map_err(|e| MedError::io(out_dir, e))?;
+    let mut report = SiteReport { written: 0, unchanged: 0 };
+
+    let encounter_pages = encounters.iter().flat_map(|e| [attendance_page_name(e), prescription_page_name(e)]);
+    for name in PAGES.map(str::to_string).into_iter().chain(encounter_pages) {
+        let content = render_page(&name, encounters, medications, doctor, signature_image).unwrap_or_default();
+        let path = out_dir.join(&name);
+        if write_if_changed(&path, &content).map_err(|e| MedError::io(&path, e))? {
+            report.written += 1;
+        } else {
+            report.unchanged += 1;
+        }
+    }
+    Ok(report)
+}
+
+pub fn remove_stale_pages(out_dir: &Path, encounters: &[Encounter]) -> Result<usize, MedError> {
+    // Only call it with every encounter loaded: a file that could not be read would lose its pages
+    let expected: Vec<String> = encounters.iter().flat_map(|e| [attendance_page_name(e), prescription_page_name(e)]).collect();
+    let entries = match fs::read_dir(out_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(MedError::io(out_dir, e)),
+    };
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let is_encounter_page = (name.starts_with("attendance_") || name.starts_with("prescription_")) && name.ends_with(".html");
+        if is_encounter_page && !expected.contains(&name) {
+            fs::remove_file(entry.path()).map_err(|e| MedError::io(entry.path(), e))?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encounter(name: &str, content: &str) -> Encounter {
+        Encounter {
+            modified: SystemTime::UNIX_EPOCH,
+            name: name.to_string(),
+            slug: page_slug(name),
+            date_str: "01/01/1970".to_string(),
+            doc: MedDocument::parse(content),
+        }
+    }
+
+    #[test]
+    fn every_page_is_rendered_and_pages_of_deleted_encounters_are_removed() {
+        let out = tempfile::tempdir().unwrap();
+        let mut encounters = vec![
+            encounter("ANA LIMA 2025-01-10.med", "[CONDUTA]\n!PRESCREVO TOPIRAMATO 25MG [1 COMPRIMIDO] NOITE;\n"),
+            encounter("ANA LIMA 2025-02-10.med", "[ANAMNESE]\nCEFALEIA MELHOR.\n"),
+        ];
+        let medications = HashMap::new();
+        let pages = PAGES.len() + 4;
+        let report = generate_site(out.path(), &encounters, &medications, None, None).unwrap();
+        assert_eq!((report.written, report.unchanged), (pages, 0));
+        let report = generate_site(out.path(), &encounters, &medications, None, None).unwrap();
+        assert_eq!((report.written, report.unchanged), (0, pages));
+
+        // The sources did not change, but the doctor shown on every page did
+        let doctor = UserInfo { name: "DRA. CLARA MENDES".to_string(), ..Default::default() };
+        let report = generate_site(out.path(), &encounters, &medications, Some(&doctor), None).unwrap();
+        assert_eq!(report.written, pages);
+        let page = fs::read_to_string(out.path().join(attendance_page_name(&encounters[1]))).unwrap();
+        assert!(page.contains("DRA. CLARA MENDES"));
+
+        fs::write(out.path().join("notas.html"), "").unwrap();
+        let deleted = encounters.remove(0);
+        assert_eq!(remove_stale_pages(out.path(), &encounters).unwrap(), 2);
+        assert!(!out.path().join(attendance_page_name(&deleted)).exists());
+        assert!(!out.path().join(prescription_page_name(&deleted)).exists());
+        assert!(out.path().join(attendance_page_name(&encounters[0])).exists());
+        assert!(out.path().join("notas.html").exists());
+    }
+}
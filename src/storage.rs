@@ -0,0 +1,294 @@
+// Encryption at rest for the workspace. When .medfiles_vault.json exists, the
+// .med files and the state files are stored as XChaCha20-Poly1305 ciphertext
+// under a key derived from the workspace passphrase with Argon2id. Every read
+// and write of workspace data goes through this module, so the rest of the
+// program works on plaintext without knowing whether the workspace is
+// encrypted. Once the vault exists, a plaintext file is refused: it was either
+// left by an interrupted `medfiles encrypt`, which finishes the job when run
+// again, or put there by someone without the passphrase.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+const VAULT_PATH: &str = ".medfiles_vault.json";
+const MAGIC: &[u8] = b"MEDENC1\0";
+const NONCE_LEN: usize = 24;
+const KEY_CHECK: &[u8] = b"medfiles-vault-check";
+const MIN_PASSPHRASE_LEN: usize = 8;
+
+// State files kept next to the .med files
+pub const STATE_FILES: [&str; 11] = [
+    "medications.json",
+    "history.json",
+    "user_info.json",
+    "prescription_recipe.txt",
+    "pending_verification.json",
+    "smtp_config.json",
+    crate::audit::AUDIT_PATH,
+    crate::audit::HEAD_PATH,
+    crate::signing::KEY_PATH,
+    crate::issuance::ARCHIVE_PATH,
+    crate::api::TOKENS_PATH,
+];
+
+#[derive(Serialize, Deserialize)]
+struct Vault {
+    version: u32,
+    kdf: String,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    salt: String,
+    check: String,
+}
+
+static SESSION_KEY: OnceLock<Result<[u8; 32], String>> = OnceLock::new();
+
+pub fn is_encrypted_workspace() -> bool {
+    Path::new(VAULT_PATH).exists()
+}
+
+fn b64() -> base64::engine::GeneralPurpose {
+    base64::engine::general_purpose::STANDARD
+}
+
+fn derive_key(passphrase: &str, vault: &Vault) -> Result<[u8; 32], String> {
+    let salt = b64().decode(&vault.salt).map_err(|e| e.to_string())?;
+    let params = Params::new(vault.m_cost, vault.t_cost, vault.p_cost, Some(32)).map_err(|e| e.to_string())?;
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+        .map_err(|e| e.to_string())?;
+    Ok(key)
+}
+
+fn seal(key: &[u8; 32], plaintext: &[u8]) -> Result<Vec<u8>, String> {
+    let cipher = XChaCha20Poly1305::new(key.into());
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let ciphertext = cipher
+        .encrypt(XNonce::from_slice(&nonce), plaintext)
+        .map_err(|_| "Falha ao criptografar.".to_string())?;
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+fn open(key: &[u8; 32], data: &[u8]) -> Result<Vec<u8>, String> {
+    let Some(body) = data.strip_prefix(MAGIC) else {
+        return Err("Arquivo não criptografado.".to_string());
+    };
+    if body.len() < NONCE_LEN {
+        return Err("Arquivo criptografado truncado.".to_string());
+    }
+    let (nonce, ciphertext) = body.split_at(NONCE_LEN);
+    XChaCha20Poly1305::new(key.into())
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "Falha na autenticação: senha incorreta ou arquivo alterado.".to_string())
+}
+
+fn is_sealed(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+fn read_passphrase(prompt: &str) -> Result<String, String> {
+    if let Ok(passphrase) = env::var("MEDFILES_PASSPHRASE") {
+        return Ok(passphrase);
+    }
+    rpassword::prompt_password(prompt).map_err(|e| e.to_string())
+}
+
+fn load_vault() -> Result<Vault, String> {
+    let content = fs::read_to_string(VAULT_PATH).map_err(|e| format!("Falha ao ler {}: {}", VAULT_PATH, e))?;
+    serde_json::from_str(&content).map_err(|e| format!("Falha ao parsear {}: {}", VAULT_PATH, e))
+}
+
+fn unlock() -> Result<[u8; 32], String> {
+    let vault = load_vault()?;
+    let passphrase = read_passphrase("Senha do workspace: ")?;
+    let key = derive_key(&passphrase, &vault)?;
+    let check = b64().decode(&vault.check).map_err(|e| e.to_string())?;
+    match open(&key, &check) {
+        Ok(plain) if plain == KEY_CHECK => Ok(key),
+        _ => Err("Senha do workspace incorreta.".to_string()),
+    }
+}
+
+fn session_key() -> Result<[u8; 32], String> {
+    SESSION_KEY.get_or_init(unlock).clone()
+}
+
+pub fn derived_key(purpose: &str) -> Result<Option<[u8; 32]>, String> {
+    // Other modules get their own keys from the workspace key, never the key itself
+    if !is_encrypted_workspace() {
+        return Ok(None);
+    }
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&session_key()?).map_err(|e| e.to_string())?;
+    mac.update(purpose.as_bytes());
+    Ok(Some(mac.finalize().into_bytes().into()))
+}
+
+fn to_io_error(message: String) -> io::Error {
+    io::Error::other(message)
+}
+
+pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+    let data = fs::read(path)?;
+    if is_sealed(&data) {
+        let key = session_key().map_err(to_io_error)?;
+        return open(&key, &data).map_err(to_io_error);
+    }
+    if is_encrypted_workspace() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "arquivo sem criptografia em um workspace criptografado; rode 'medfiles encrypt' para concluir a criptografia",
+        ));
+    }
+    Ok(data)
+}
+
+pub fn read_to_string(path: impl AsRef<Path>) -> io::Result<String> {
+    let data = read(path)?;
+    String::from_utf8(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    if !is_encrypted_workspace() {
+        return fs::write(path, contents);
+    }
+    let key = session_key().map_err(to_io_error)?;
+    let sealed = seal(&key, contents.as_ref()).map_err(to_io_error)?;
+    fs::write(path, sealed)
+}
+
+fn workspace_files() -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = crate::medfile_finder().into_iter().map(|(path, _)| path).collect();
+    for name in STATE_FILES {
+        if Path::new(name).exists() {
+            files.push(PathBuf::from(name));
+        }
+    }
+    files
+}
+
+fn replace_file(path: &Path, contents: &[u8]) -> Result<(), String> {
+    // Write next to the original and rename, so an interruption never leaves a half-written record
+    let tmp = path.with_extension("medfiles-tmp");
+    fs::write(&tmp, contents).map_err(|e| format!("Falha ao gravar {}: {}", tmp.display(), e))?;
+    fs::rename(&tmp, path).map_err(|e| format!("Falha ao substituir {}: {}", path.display(), e))
+}
+
+fn seal_files(key: &[u8; 32]) -> Result<usize, String> {
+    // Reads the raw bytes: these are the only plaintext files accepted in an encrypted workspace
+    let mut count = 0;
+    for path in workspace_files() {
+        let data = fs::read(&path).map_err(|e| format!("Falha ao ler {}: {}", path.display(), e))?;
+        if is_sealed(&data) {
+            continue;
+        }
+        replace_file(&path, &seal(key, &data)?)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+pub fn encrypt_workspace() -> Result<usize, String> {
+    if is_encrypted_workspace() {
+        // An earlier run stopped after writing the vault: finish it with the same passphrase
+        let count = seal_files(&session_key()?)?;
+        if count == 0 {
+            return Err("O workspace já está criptografado.".to_string());
+        }
+        return Ok(count);
+    }
+    let passphrase = read_passphrase("Nova senha do workspace: ")?;
+    if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+        return Err(format!("A senha deve ter pelo menos {} caracteres.", MIN_PASSPHRASE_LEN));
+    }
+    if env::var("MEDFILES_PASSPHRASE").is_err() && read_passphrase("Confirme a senha: ")? != passphrase {
+        return Err("As senhas não conferem.".to_string());
+    }
+
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let defaults = Params::default();
+    let mut vault = Vault {
+        version: 1,
+        kdf: "argon2id".to_string(),
+        m_cost: defaults.m_cost(),
+        t_cost: defaults.t_cost(),
+        p_cost: defaults.p_cost(),
+        salt: b64().encode(salt),
+        check: String::new(),
+    };
+    let key = derive_key(&passphrase, &vault)?;
+    vault.check = b64().encode(seal(&key, KEY_CHECK)?);
+    // The vault is written first: after an interruption, running encrypt again seals the rest
+    let json = serde_json::to_string_pretty(&vault).map_err(|e| e.to_string())?;
+    fs::write(VAULT_PATH, json).map_err(|e| format!("Falha ao gravar {}: {}", VAULT_PATH, e))?;
+
+    let count = seal_files(&key)?;
+    // The key is already known; later writes in this run must not ask for the passphrase again
+    let _ = SESSION_KEY.set(Ok(key));
+    Ok(count)
+}
+
+pub fn decrypt_workspace() -> Result<usize, String> {
+    if !is_encrypted_workspace() {
+        return Err("O workspace não está criptografado.".to_string());
+    }
+    let key = session_key()?;
+    let mut count = 0;
+    for path in workspace_files() {
+        let data = fs::read(&path).map_err(|e| format!("Falha ao ler {}: {}", path.display(), e))?;
+        if !is_sealed(&data) {
+            continue;
+        }
+        let plain = open(&key, &data).map_err(|e| format!("{}: {}", path.display(), e))?;
+        replace_file(&path, &plain)?;
+        count += 1;
+    }
+    fs::remove_file(VAULT_PATH).map_err(|e| format!("Falha ao remover {}: {}", VAULT_PATH, e))?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Workspace;
+
+    #[test]
+    fn truncated_or_unsealed_data_is_an_error() {
+        let key = [7u8; 32];
+        let sealed = seal(&key, b"TOPIRAMATO 25MG").unwrap();
+        assert_eq!(open(&key, &sealed).unwrap(), b"TOPIRAMATO 25MG");
+        for data in [&b""[..], &b"MED"[..], MAGIC, &sealed[..MAGIC.len() + NONCE_LEN - 1], b"[CONDUTA]"] {
+            assert!(open(&key, data).is_err());
+        }
+        assert!(open(&[8u8; 32], &sealed).unwrap_err().contains("senha incorreta"));
+    }
+
+    #[test]
+    fn plaintext_is_refused_once_the_vault_exists() {
+        let workspace = Workspace::new();
+        let med = workspace.file("MARIA SOUZA 2025-01-10.med", "[CONDUTA]\n!PRESCREVO TOPIRAMATO 25MG;\n");
+        assert!(read_to_string(&med).is_ok());
+        workspace.file(VAULT_PATH, "{}");
+        let error = read_to_string(&med).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("medfiles encrypt"));
+    }
+}
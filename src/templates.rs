@@ -0,0 +1,212 @@
+// Templates for new encounters (`medfiles new`). A template is a .med
+// skeleton with the standard sections and `#` comments guiding what to write;
+// the bundled ones follow the specialties of the samples in docs/ and a file
+// in templates/<nome>.med inside the workspace overrides or adds to them.
+// The follow-up is pre-filled from the patient's record, always commented
+// out: active medications as `!PRESCREVO` continuation lines, the open
+// diagnoses and the requested exams that still have no result.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use medfiles::problems::{Problem, ProblemStatus};
+use medfiles::site::Encounter;
+use medfiles::{cid10, lab, medfile};
+
+pub const TEMPLATE_DIR: &str = "templates";
+
+const BUNDLED: [(&str, &str); 4] = [
+    ("geral", include_str!("../assets/templates/geral.med")),
+    ("neurologia", include_str!("../assets/templates/neurologia.med")),
+    ("psiquiatria", include_str!("../assets/templates/psiquiatria.med")),
+    ("cardiologia", include_str!("../assets/templates/cardiologia.med")),
+];
+
+pub struct PendingRequest {
+    pub date: String,
+    pub exam: String,
+}
+
+pub fn names() -> Vec<String> {
+    let mut names: Vec<String> = BUNDLED.iter().map(|(name, _)| name.to_string()).collect();
+    if let Ok(entries) = fs::read_dir(TEMPLATE_DIR) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|e| e == "med")
+                && let Some(stem) = path.file_stem()
+            {
+                names.push(stem.to_string_lossy().to_string());
+            }
+        }
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+pub fn for_specialty(specialty: &str) -> &'static str {
+    let specialty = cid10::normalize(specialty);
+    if specialty.contains("neuro") {
+        "neurologia"
+    } else if specialty.contains("psiq") {
+        "psiquiatria"
+    } else if specialty.contains("cardio") {
+        "cardiologia"
+    } else {
+        "geral"
+    }
+}
+
+pub fn load(name: &str) -> Result<String, String> {
+    let local = Path::new(TEMPLATE_DIR).join(format!("{}.med", name));
+    if local.exists() {
+        return fs::read_to_string(&local).map_err(|e| format!("Falha ao ler {}: {}", local.display(), e));
+    }
+    BUNDLED
+        .iter()
+        .find(|(bundled, _)| *bundled == name)
+        .map(|(_, text)| text.to_string())
+        .ok_or_else(|| format!("Modelo '{}' não encontrado. Modelos disponíveis: {}", name, names().join(", ")))
+}
+
+pub fn continuation_line(medication: &str, state: &HashMap<String, String>) -> String {
+    // Same layout read by medication_list_tokenizer: NOME DOSE [QUANTIDADE] POSOLOGIA [OBSERVAÇÃO]
+    let field = |name: &str| state.get(name).map(|v| v.trim()).unwrap_or("");
+    let mut parts = vec!["!PRESCREVO".to_string()];
+    parts.push(if medication.contains(' ') { format!("'{}'", medication) } else { medication.to_string() });
+    if !field("dosage").is_empty() && field("dosage") != "1 UNIDADE" {
+        parts.push(field("dosage").to_string());
+    }
+    if !field("dosage_observations").is_empty() {
+        parts.push(format!("[{}]", field("dosage_observations")));
+    }
+    if !field("posologia").is_empty() {
+        parts.push(field("posologia").to_string());
+    }
+    if !field("posology_observations").is_empty() {
+        parts.push(format!("[{}]", field("posology_observations")));
+    }
+    format!("{};", parts.join(" "))
+}
+
+fn exam_key(text: &str) -> String {
+    cid10::normalize(&text.replace('_', " "))
+}
+
+fn is_reported(request: &str, encounters: &[Encounter]) -> bool {
+    let code = lab::analyte(&request.replace('_', " ")).map(|a| a.code.clone());
+    let request = exam_key(request);
+    let matches = |reported: &str| !reported.is_empty() && (request.contains(reported) || reported.contains(&request));
+    encounters.iter().any(|encounter| {
+        let exams = encounter.doc.sections.iter().flat_map(|s| s.directives()).filter(|d| d.keyword == "EX");
+        exams.map(|d| exam_key(&medfile::parse_exam(&d.content).name)).any(|name| matches(&name))
+            || lab::document_results(&encounter.doc, "")
+                .iter()
+                .any(|r| (code.is_some() && r.code == code) || matches(&exam_key(&r.name)))
+    })
+}
+
+pub fn pending_requests(encounters: &[Encounter]) -> Vec<PendingRequest> {
+    // A `!SOLICITO` is pending until a later encounter brings an `!EX` or `!LAB` with the same exam
+    let mut pending = vec![];
+    for (index, encounter) in encounters.iter().enumerate() {
+        for directive in encounter.doc.sections.iter().flat_map(|s| s.directives()) {
+            if directive.keyword == "SOLICITO" && !is_reported(&directive.content, &encounters[index + 1..]) {
+                pending.push(PendingRequest { date: encounter.date_str.clone(), exam: directive.content.clone() });
+            }
+        }
+    }
+    pending
+}
+
+fn prefill(
+    section: &str,
+    medications: &[(String, HashMap<String, String>)],
+    problems: &[Problem],
+    pending: &[PendingRequest],
+) -> Vec<String> {
+    let mut lines = vec![];
+    match section {
+        "ANAMNESE" if !pending.is_empty() => {
+            lines.push("# Exames solicitados sem resultado registrado:".to_string());
+            for request in pending {
+                lines.push(format!("# solicitado em {}: {}", request.date, request.exam));
+                lines.push(format!("# !EX @{}[MM/AAAA]: RESULTADO;", request.exam.replace(' ', "_")));
+            }
+        }
+        "HIPOTESE DIAGNOSTICA" if !problems.is_empty() => {
+            lines.push("# Diagnósticos anteriores (descomente os que continuam válidos):".to_string());
+            for problem in problems {
+                let suspected = if problem.status == ProblemStatus::Suspected { "?" } else { "" };
+                let code = problem.code.as_ref().map(|code| format!(" {{{}}}", code)).unwrap_or_default();
+                lines.push(format!("# {}{}{};", problem.name, suspected, code));
+            }
+        }
+        "CONDUTA" if !medications.is_empty() => {
+            lines.push("# Medicações em uso (descomente para manter):".to_string());
+            for (medication, state) in medications {
+                lines.push(format!("# {}", continuation_line(medication, state)));
+            }
+        }
+        _ => {}
+    }
+    lines
+}
+
+fn close_section(
+    out: &mut Vec<String>,
+    section: &str,
+    medications: &[(String, HashMap<String, String>)],
+    problems: &[Problem],
+    pending: &[PendingRequest],
+) {
+    let lines = prefill(section, medications, problems, pending);
+    if lines.is_empty() {
+        return;
+    }
+    // Keep the blank lines that separate sections after the pre-filled block
+    let mut trailing = 0;
+    while out.last().is_some_and(|l| l.trim().is_empty()) {
+        out.pop();
+        trailing += 1;
+    }
+    out.extend(lines);
+    out.extend(std::iter::repeat_n(String::new(), trailing));
+}
+
+pub fn build(
+    template: &str,
+    header: &str,
+    medications: &[(String, HashMap<String, String>)],
+    problems: &[Problem],
+    pending: &[PendingRequest],
+) -> String {
+    let mut out: Vec<String> = vec![format!("# {}", header)];
+    let mut sections: Vec<String> = vec![];
+    for line in template.lines() {
+        if let Some(name) = medfile::section_name(line) {
+            if let Some(previous) = sections.last() {
+                close_section(&mut out, previous, medications, problems, pending);
+            }
+            sections.push(name);
+        }
+        out.push(line.to_string());
+    }
+    if out.last().is_some_and(|l| !l.trim().is_empty()) {
+        out.push(String::new());
+    }
+    if let Some(last) = sections.last() {
+        close_section(&mut out, last, medications, problems, pending);
+    }
+    // Sections the template does not have are added at the end
+    for section in ["ANAMNESE", "HIPOTESE DIAGNOSTICA", "CONDUTA"] {
+        let lines = prefill(section, medications, problems, pending);
+        if !sections.iter().any(|s| s == section) && !lines.is_empty() {
+            out.push(format!("[{}]", section));
+            out.extend(lines);
+            out.push(String::new());
+        }
+    }
+    out.join("\n")
+}
@@ -0,0 +1,89 @@
+// Helpers for the tests of the command-line modules. Most of them work on the
+// current directory (the .med files, api_tokens.json, the audit log, the keys),
+// so each test runs inside its own temporary workspace and holds a lock while
+// it does, since the current directory is shared by the whole test process.
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+
+static CURRENT_DIR: Mutex<()> = Mutex::new(());
+
+pub struct Workspace {
+    pub dir: tempfile::TempDir,
+    previous: PathBuf,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Workspace {
+    pub fn new() -> Workspace {
+        // A failed test poisons the lock; the next one still gets a fresh directory
+        let lock = CURRENT_DIR.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = tempfile::tempdir().expect("diretório temporário");
+        let previous = std::env::current_dir().expect("diretório atual");
+        std::env::set_current_dir(dir.path()).expect("entrar no workspace");
+        Workspace { dir, previous, _lock: lock }
+    }
+
+    pub fn file(&self, name: &str, content: &str) -> PathBuf {
+        let path = self.dir.path().join(name);
+        fs::write(&path, content).expect("gravar arquivo de teste");
+        path
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        let _ = std::env::set_current_dir(&self.previous);
+    }
+}
+
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: String,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).unwrap_or_else(|e| panic!("JSON inválido ({}): {}", e, self.body))
+    }
+}
+
+pub fn start_server() -> SocketAddr {
+    // The server thread outlives the test; it only answers while the test holds the workspace
+    let listener = TcpListener::bind("127.0.0.1:0").expect("abrir porta local");
+    let address = listener.local_addr().expect("endereço local");
+    std::thread::spawn(move || crate::server::serve(listener));
+    address
+}
+
+pub fn http(address: SocketAddr, request: &str) -> HttpResponse {
+    let mut stream = TcpStream::connect(address).expect("conectar ao servidor");
+    stream.write_all(request.as_bytes()).expect("enviar requisição");
+    let mut raw = String::new();
+    stream.read_to_string(&mut raw).expect("ler resposta");
+    let (headers, body) = raw.split_once("\r\n\r\n").unwrap_or((&raw, ""));
+    let status = headers.split_whitespace().nth(1).and_then(|code| code.parse().ok()).unwrap_or(0);
+    HttpResponse { status, headers: headers.to_string(), body: body.to_string() }
+}
+
+pub fn get(address: SocketAddr, path: &str, token: Option<&str>) -> HttpResponse {
+    let authorization = token.map(|t| format!("Authorization: Bearer {}\r\n", t)).unwrap_or_default();
+    http(address, &format!("GET {} HTTP/1.1\r\nHost: {}\r\n{}Connection: close\r\n\r\n", path, address, authorization))
+}
+
+pub fn post(address: SocketAddr, path: &str, body: &str) -> HttpResponse {
+    http(
+        address,
+        &format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            path,
+            address,
+            body.len(),
+            body
+        ),
+    )
+}
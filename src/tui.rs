@@ -0,0 +1,508 @@
+// Full-screen terminal interface opened by `medfiles` without arguments.
+// The .med files of the workspace are grouped by patient (the file name
+// without a trailing date) and shown as patient and encounter lists, the
+// selected encounter rendered with the same blocks as the PDF, and the active
+// medications with their dose timeline. Printing, exporting and editing run
+// the regular commands with the terminal temporarily restored.
+
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+
+use medfiles::pdf::{PdfBlock, PageSize};
+use medfiles::prescription::recipe_lines;
+use medfiles::site::{self, Encounter};
+use medfiles::{attendance, charts, cid10, doses};
+
+use crate::audit;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Focus {
+    Patients,
+    Encounters,
+    Viewer,
+    Medications,
+}
+
+impl Focus {
+    fn next(self) -> Focus {
+        match self {
+            Focus::Patients => Focus::Encounters,
+            Focus::Encounters => Focus::Viewer,
+            Focus::Viewer => Focus::Medications,
+            Focus::Medications => Focus::Patients,
+        }
+    }
+
+    fn previous(self) -> Focus {
+        match self {
+            Focus::Patients => Focus::Medications,
+            Focus::Encounters => Focus::Patients,
+            Focus::Viewer => Focus::Encounters,
+            Focus::Medications => Focus::Viewer,
+        }
+    }
+}
+
+enum Action {
+    Quit,
+    Prescription(PathBuf),
+    Attendance(PathBuf),
+    Pdf(PathBuf),
+    Editor(PathBuf),
+}
+
+struct Patient {
+    name: String,
+    files: Vec<(PathBuf, SystemTime)>,
+    // Oldest first, as everywhere else
+    encounters: Vec<Encounter>,
+    // Accent-insensitive text of each encounter, used by the search
+    texts: Vec<String>,
+}
+
+struct App {
+    patients: Vec<Patient>,
+    patient: usize,
+    encounter: usize,
+    focus: Focus,
+    search: String,
+    searching: bool,
+    scroll: u16,
+    medication_scroll: u16,
+    status: String,
+    viewed: HashSet<String>,
+}
+
+fn load_patients() -> Vec<Patient> {
+    let mut patients: Vec<Patient> = site::group_by_patient(crate::medfile_finder())
+        .into_iter()
+        .map(|(name, files)| Patient { name, files, encounters: vec![], texts: vec![] })
+        .collect();
+    for patient in &mut patients {
+        patient.encounters = crate::load_encounters(&patient.files);
+        patient.texts = patient
+            .encounters
+            .iter()
+            .map(|e| {
+                let lines: Vec<&str> = e.doc.sections.iter().flat_map(|s| s.lines.iter().map(String::as_str)).collect();
+                cid10::normalize(&format!("{} {}", e.name, lines.join(" ")))
+            })
+            .collect();
+    }
+    patients
+}
+
+fn block_lines(blocks: Vec<PdfBlock>) -> (Vec<Line<'static>>, Vec<u16>) {
+    // Returns the lines and the offset of every section heading
+    let mut lines = vec![];
+    let mut headings = vec![];
+    for block in blocks {
+        match block {
+            PdfBlock::Heading(title) => {
+                if !lines.is_empty() {
+                    lines.push(Line::default());
+                }
+                headings.push(lines.len() as u16);
+                lines.push(Line::styled(title, Style::new().fg(Color::Cyan).add_modifier(Modifier::BOLD)));
+            }
+            PdfBlock::Paragraph(text) => lines.push(Line::raw(text)),
+            PdfBlock::Item(first, second) => {
+                lines.push(Line::styled(first, Style::new().add_modifier(Modifier::BOLD)));
+                lines.push(Line::raw(format!("   {}", second)));
+            }
+            PdfBlock::Signature { .. } | PdfBlock::Verification { .. } => {}
+        }
+    }
+    (lines, headings)
+}
+
+impl App {
+    fn new() -> App {
+        App {
+            patients: load_patients(),
+            patient: 0,
+            encounter: 0,
+            focus: Focus::Encounters,
+            search: String::new(),
+            searching: false,
+            scroll: 0,
+            medication_scroll: 0,
+            status: String::new(),
+            viewed: HashSet::new(),
+        }
+    }
+
+    fn reload(&mut self) {
+        let selected = self.selected_patient().map(|p| p.name.clone());
+        self.patients = load_patients();
+        let visible = self.visible_patients();
+        self.patient = selected.and_then(|name| visible.iter().position(|i| self.patients[*i].name == name)).unwrap_or(0);
+        self.encounter = 0;
+        self.scroll = 0;
+    }
+
+    fn matches(&self, text: &str) -> bool {
+        let query = cid10::normalize(&self.search);
+        query.is_empty() || text.contains(&query)
+    }
+
+    fn visible_patients(&self) -> Vec<usize> {
+        (0..self.patients.len())
+            .filter(|i| {
+                let patient = &self.patients[*i];
+                self.matches(&cid10::normalize(&patient.name)) || patient.texts.iter().any(|t| self.matches(t))
+            })
+            .collect()
+    }
+
+    fn selected_patient(&self) -> Option<&Patient> {
+        self.visible_patients().get(self.patient).map(|i| &self.patients[*i])
+    }
+
+    fn visible_encounters(&self) -> Vec<usize> {
+        // Newest first
+        let Some(patient) = self.selected_patient() else {
+            return vec![];
+        };
+        (0..patient.encounters.len()).rev().filter(|i| self.matches(&patient.texts[*i])).collect()
+    }
+
+    fn selected_encounter(&self) -> Option<&Encounter> {
+        let index = *self.visible_encounters().get(self.encounter)?;
+        self.selected_patient().map(|p| &p.encounters[index])
+    }
+
+    fn selected_path(&self) -> Option<PathBuf> {
+        self.selected_encounter().map(|e| PathBuf::from(&e.name))
+    }
+
+    fn record_view(&mut self) {
+        // One audit entry per encounter actually shown, not per redraw
+        let Some(name) = self.selected_encounter().map(|e| e.name.clone()) else {
+            return;
+        };
+        if self.viewed.insert(name.clone()) {
+            audit::record_file("visualizar", Path::new(&name), "atendimento no terminal");
+        }
+    }
+
+    fn move_selection(&mut self, delta: i32) {
+        let step = |index: usize, len: usize| -> usize {
+            if len == 0 {
+                return 0;
+            }
+            (index as i32 + delta).clamp(0, len as i32 - 1) as usize
+        };
+        match self.focus {
+            Focus::Patients => {
+                self.patient = step(self.patient, self.visible_patients().len());
+                self.encounter = 0;
+                self.scroll = 0;
+                self.medication_scroll = 0;
+            }
+            Focus::Encounters => {
+                self.encounter = step(self.encounter, self.visible_encounters().len());
+                self.scroll = 0;
+            }
+            Focus::Viewer => self.scroll = (self.scroll as i32 + delta).max(0) as u16,
+            Focus::Medications => self.medication_scroll = (self.medication_scroll as i32 + delta).max(0) as u16,
+        }
+    }
+
+    fn jump_section(&mut self, forward: bool) {
+        let Some(encounter) = self.selected_encounter() else {
+            return;
+        };
+        let (_, headings) = block_lines(attendance::attendance_pdf_blocks(&encounter.doc));
+        let target = if forward {
+            headings.iter().find(|h| **h > self.scroll).copied()
+        } else {
+            headings.iter().rev().find(|h| **h < self.scroll).copied()
+        };
+        if let Some(target) = target {
+            self.scroll = target;
+        }
+    }
+
+    fn handle_search_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Enter => self.searching = false,
+            KeyCode::Esc => {
+                self.searching = false;
+                self.search.clear();
+            }
+            KeyCode::Backspace => {
+                self.search.pop();
+            }
+            KeyCode::Char(c) => self.search.push(c),
+            _ => {}
+        }
+        self.patient = 0;
+        self.encounter = 0;
+        self.scroll = 0;
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Option<Action> {
+        if self.searching {
+            self.handle_search_key(key);
+            return None;
+        }
+        self.status.clear();
+        match key.code {
+            KeyCode::Char('q') => return Some(Action::Quit),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Some(Action::Quit),
+            KeyCode::Esc if !self.search.is_empty() => {
+                self.search.clear();
+                self.patient = 0;
+                self.encounter = 0;
+            }
+            KeyCode::Esc => return Some(Action::Quit),
+            KeyCode::Tab => self.focus = self.focus.next(),
+            KeyCode::BackTab => self.focus = self.focus.previous(),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::PageDown => self.move_selection(10),
+            KeyCode::PageUp => self.move_selection(-10),
+            KeyCode::Right | KeyCode::Char('l') => self.jump_section(true),
+            KeyCode::Left | KeyCode::Char('h') => self.jump_section(false),
+            KeyCode::Enter if self.focus == Focus::Patients => self.focus = Focus::Encounters,
+            KeyCode::Enter if self.focus == Focus::Encounters => self.focus = Focus::Viewer,
+            KeyCode::Char('/') => self.searching = true,
+            KeyCode::Char('r') => {
+                self.reload();
+                self.status = "Arquivos recarregados.".to_string();
+            }
+            KeyCode::Char('p') => return self.selected_path().map(Action::Prescription),
+            KeyCode::Char('a') => return self.selected_path().map(Action::Attendance),
+            KeyCode::Char('e') => return self.selected_path().map(Action::Pdf),
+            KeyCode::Char('o') => return self.selected_path().map(Action::Editor),
+            _ => {}
+        }
+        None
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [left, viewer, right] =
+            Layout::horizontal([Constraint::Length(32), Constraint::Min(40), Constraint::Length(48)]).areas(main);
+        let [patients, encounters] = Layout::vertical([Constraint::Percentage(40), Constraint::Percentage(60)]).areas(left);
+        self.draw_patients(frame, patients);
+        self.draw_encounters(frame, encounters);
+        self.draw_viewer(frame, viewer);
+        self.draw_medications(frame, right);
+        self.draw_status(frame, status);
+    }
+
+    fn panel(&self, title: String, focus: Focus) -> Block<'static> {
+        let style = if self.focus == focus { Style::new().fg(Color::Yellow) } else { Style::new().fg(Color::DarkGray) };
+        Block::bordered().title(title).border_style(style)
+    }
+
+    fn draw_list(&self, frame: &mut Frame, area: Rect, title: String, focus: Focus, items: Vec<ListItem>, selected: usize) {
+        let list = List::new(items)
+            .block(self.panel(title, focus))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        let mut state = ListState::default().with_selected(Some(selected));
+        frame.render_stateful_widget(list, area, &mut state);
+    }
+
+    fn draw_patients(&self, frame: &mut Frame, area: Rect) {
+        let items: Vec<ListItem> = self
+            .visible_patients()
+            .iter()
+            .map(|i| {
+                let patient = &self.patients[*i];
+                ListItem::new(format!("{} ({})", patient.name, patient.encounters.len()))
+            })
+            .collect();
+        self.draw_list(frame, area, " Pacientes ".to_string(), Focus::Patients, items, self.patient);
+    }
+
+    fn draw_encounters(&self, frame: &mut Frame, area: Rect) {
+        let items: Vec<ListItem> = match self.selected_patient() {
+            Some(patient) => self
+                .visible_encounters()
+                .iter()
+                .map(|i| {
+                    let encounter = &patient.encounters[*i];
+                    ListItem::new(Line::from(vec![
+                        Span::styled(format!("{} ", encounter.date_str), Style::new().fg(Color::Cyan)),
+                        Span::raw(encounter.name.clone()),
+                    ]))
+                })
+                .collect(),
+            None => vec![],
+        };
+        self.draw_list(frame, area, " Atendimentos ".to_string(), Focus::Encounters, items, self.encounter);
+    }
+
+    fn draw_viewer(&self, frame: &mut Frame, area: Rect) {
+        let (title, lines) = match self.selected_encounter() {
+            Some(encounter) => (
+                format!(" {} - {} ", encounter.name, encounter.date_str),
+                block_lines(attendance::attendance_pdf_blocks(&encounter.doc)).0,
+            ),
+            None => (" Atendimento ".to_string(), vec![Line::raw("Nenhum atendimento encontrado.")]),
+        };
+        let paragraph = Paragraph::new(lines)
+            .block(self.panel(title, Focus::Viewer))
+            .wrap(Wrap { trim: false })
+            .scroll((self.scroll, 0));
+        frame.render_widget(paragraph, area);
+    }
+
+    fn medication_lines(&self) -> Vec<Line<'static>> {
+        let Some(patient) = self.selected_patient() else {
+            return vec![];
+        };
+        let mut lines = vec![];
+        let (medications, _) = crate::medication_state_replay(&patient.files);
+        let active = site::state_prescriptions(&medications);
+        if active.is_empty() {
+            lines.push(Line::raw("Nenhuma medicação em uso."));
+        }
+        for item in &active {
+            let (first, second) = recipe_lines(item);
+            lines.push(Line::styled(first, Style::new().add_modifier(Modifier::BOLD)));
+            lines.push(Line::raw(format!("   {}", second)));
+        }
+        let histories = doses::dose_history(&patient.encounters);
+        if !histories.is_empty() {
+            lines.push(Line::default());
+            lines.push(Line::styled("Evolução das doses", Style::new().fg(Color::Cyan).add_modifier(Modifier::BOLD)));
+        }
+        for history in &histories {
+            lines.push(Line::styled(format!("{} ({})", history.medication, history.unit()), Style::new().add_modifier(Modifier::BOLD)));
+            for event in &history.events {
+                let (mark, color) = match event.command.as_str() {
+                    "SUSPEND" => ("✕", Color::Red),
+                    "INCREASE" => ("▲", Color::Green),
+                    _ => ("●", Color::Blue),
+                };
+                let value = if event.suspended { String::new() } else { charts::format_value(history.value(event)) };
+                lines.push(Line::from(vec![
+                    Span::raw(format!("   {} ", event.date_str)),
+                    Span::styled(mark, Style::new().fg(color)),
+                    Span::raw(format!(" {} {}", doses::command_label(&event.command), value)),
+                ]));
+            }
+        }
+        lines
+    }
+
+    fn draw_medications(&self, frame: &mut Frame, area: Rect) {
+        let paragraph = Paragraph::new(self.medication_lines())
+            .block(self.panel(" Medicações em uso ".to_string(), Focus::Medications))
+            .wrap(Wrap { trim: false })
+            .scroll((self.medication_scroll, 0));
+        frame.render_widget(paragraph, area);
+    }
+
+    fn draw_status(&self, frame: &mut Frame, area: Rect) {
+        let line = if self.searching {
+            Line::from(vec![Span::styled("Buscar: ", Style::new().fg(Color::Yellow)), Span::raw(format!("{}▏", self.search))])
+        } else if !self.status.is_empty() {
+            Line::raw(self.status.clone())
+        } else {
+            let filter = if self.search.is_empty() { String::new() } else { format!("[filtro: {}] ", self.search) };
+            Line::styled(
+                format!(
+                    "{}Tab painel · ↑↓ navegar · ←→ seção · / buscar · p prescrição · a atendimento · e PDF · o editor · r recarregar · q sair",
+                    filter
+                ),
+                Style::new().fg(Color::DarkGray),
+            )
+        };
+        frame.render_widget(Paragraph::new(line), area);
+    }
+}
+
+fn wait_for_enter() {
+    print!("\nPressione Enter para voltar...");
+    io::stdout().flush().ok();
+    let mut line = String::new();
+    io::stdin().read_line(&mut line).ok();
+}
+
+fn run_action(app: &App, action: &Action) -> String {
+    match action {
+        Action::Quit => String::new(),
+        Action::Prescription(path) => {
+            let Some(encounter) = app.selected_encounter() else {
+                return String::new();
+            };
+            audit::record_file("imprimir", path, "prescrição do atendimento no navegador");
+            let (doctor, signature) = crate::load_doctor();
+            let page = site::generate_prescription_page(encounter, doctor.as_ref(), signature.as_deref());
+            match crate::serve_html_once(&page) {
+                Ok(()) => format!("Prescrição de {} aberta no navegador.", path.display()),
+                Err(e) => e.to_string(),
+            }
+        }
+        Action::Attendance(path) => match crate::attendance_viewer(path, None) {
+            Ok(()) => format!("Atendimento {} aberto no navegador.", path.display()),
+            Err(e) => e.to_string(),
+        },
+        Action::Pdf(path) => {
+            let pdf_path = path.with_extension("pdf");
+            match crate::print_command(Some(path), Some(&pdf_path), PageSize::A4) {
+                Ok(()) => format!("Atendimento exportado para {}.", pdf_path.display()),
+                Err(e) => e.to_string(),
+            }
+        }
+        Action::Editor(path) => match crate::open_in_editor(path) {
+            Ok(_) => format!("{} editado.", path.display()),
+            Err(e) => e,
+        },
+    }
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, app: &mut App) -> io::Result<()> {
+    loop {
+        app.record_view();
+        terminal.draw(|frame| app.draw(frame))?;
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let Some(action) = app.handle_key(key) else {
+            continue;
+        };
+        if let Action::Quit = action {
+            return Ok(());
+        }
+        // Browser, PDF and editor need the normal terminal back
+        ratatui::restore();
+        app.status = run_action(app, &action);
+        if !matches!(action, Action::Editor(_)) {
+            wait_for_enter();
+        }
+        *terminal = ratatui::init();
+        if matches!(action, Action::Editor(_)) {
+            app.reload();
+        }
+    }
+}
+
+pub fn run() -> io::Result<()> {
+    let mut app = App::new();
+    if app.patients.is_empty() {
+        println!("Nenhum arquivo .med encontrado no diretório atual.");
+        return Ok(());
+    }
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut app);
+    ratatui::restore();
+    result
+}
@@ -0,0 +1,171 @@
+// One-time token verification for `medfiles config`. Only an Argon2id hash of
+// the issued token is kept in pending_verification.json, together with the data
+// being registered, so an interrupted configuration can be resumed. A 6-digit
+// code has only 10^6 values: the slow, memory-hard hash keeps anyone who reads
+// the file from trying all of them before the code expires.
+
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use medfiles::{MedError, UserInfo};
+
+use crate::crypto;
+
+const PENDING_PATH: &str = "pending_verification.json";
+pub const TOKEN_TTL_SECS: u64 = 600; // 10 minutes
+pub const MAX_ATTEMPTS: u32 = 5;
+pub const MAX_RESENDS: u32 = 3;
+pub const RESEND_COOLDOWN_SECS: u64 = 60;
+// Argon2id cost: 64 MiB and 3 passes, so trying every code takes hours instead of milliseconds
+#[cfg(not(test))]
+const KDF_COST: (u32, u32) = (64 * 1024, 3);
+#[cfg(test)]
+const KDF_COST: (u32, u32) = (64, 1);
+
+#[derive(Serialize, Deserialize)]
+pub struct PendingVerification {
+    pub user: UserInfo,
+    salt: String,
+    token_hash: String,
+    issued_at: u64,
+    pub attempts: u32,
+    pub resends: u32,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn hash_token(salt: &str, token: &str) -> String {
+    crypto::argon2id(salt, token.trim(), KDF_COST)
+}
+
+fn new_salt() -> String {
+    crypto::random_hex(16)
+}
+
+impl PendingVerification {
+    pub fn new(user: UserInfo, token: &str) -> PendingVerification {
+        let salt = new_salt();
+        PendingVerification {
+            user,
+            token_hash: hash_token(&salt, token),
+            salt,
+            issued_at: now_secs(),
+            attempts: 0,
+            resends: 0,
+        }
+    }
+
+    pub fn reissue(&mut self, token: &str) {
+        // The attempts are kept: a resend must not buy more guesses
+        self.salt = new_salt();
+        self.token_hash = hash_token(&self.salt, token);
+        self.issued_at = now_secs();
+        self.resends += 1;
+    }
+
+    pub fn resend_refusal(&self) -> Option<String> {
+        if self.resends >= MAX_RESENDS {
+            return Some("Limite de reenvios atingido.".to_string());
+        }
+        match self.resend_wait_secs() {
+            0 => None,
+            wait => Some(format!("Aguarde {}s para reenviar o token.", wait)),
+        }
+    }
+
+    pub fn register_failure(&mut self) -> u32 {
+        // Attempts left; zero ends the verification
+        self.attempts += 1;
+        MAX_ATTEMPTS.saturating_sub(self.attempts)
+    }
+
+    pub fn remaining_secs(&self) -> u64 {
+        TOKEN_TTL_SECS.saturating_sub(now_secs().saturating_sub(self.issued_at))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining_secs() == 0
+    }
+
+    pub fn resend_wait_secs(&self) -> u64 {
+        RESEND_COOLDOWN_SECS.saturating_sub(now_secs().saturating_sub(self.issued_at))
+    }
+
+    pub fn matches(&self, input: &str) -> bool {
+        crypto::same_digest(&self.token_hash, &hash_token(&self.salt, input))
+    }
+}
+
+pub fn load_pending() -> Option<PendingVerification> {
+    let content = crate::storage::read_to_string(PENDING_PATH).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+pub fn save_pending(pending: &PendingVerification) -> Result<(), MedError> {
+    // Without the file the verification still finishes, but cannot be resumed later
+    let json = serde_json::to_string_pretty(pending).map_err(|e| MedError::state(PENDING_PATH, e))?;
+    crate::storage::write(PENDING_PATH, json).map_err(|e| MedError::io(PENDING_PATH, e))
+}
+
+pub fn clear_pending() {
+    let _ = fs::remove_file(PENDING_PATH);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending() -> PendingVerification {
+        PendingVerification::new(UserInfo { email: "clara@example.com".to_string(), ..Default::default() }, "123456")
+    }
+
+    #[test]
+    fn only_the_issued_token_matches_and_it_is_not_stored() {
+        let pending = pending();
+        assert!(pending.matches("123456") && pending.matches(" 123456\n"));
+        assert!(!pending.matches("123457"));
+        let json = serde_json::to_string(&pending).unwrap();
+        assert!(!json.contains("123456"));
+    }
+
+    #[test]
+    fn token_expires_after_the_ttl() {
+        let mut pending = pending();
+        assert!(!pending.is_expired());
+        pending.issued_at -= TOKEN_TTL_SECS - 1;
+        assert_eq!(pending.remaining_secs(), 1);
+        pending.issued_at -= 1;
+        assert!(pending.is_expired());
+    }
+
+    #[test]
+    fn attempts_are_limited_and_survive_a_resend() {
+        let mut pending = pending();
+        assert_eq!(pending.register_failure(), MAX_ATTEMPTS - 1);
+        assert_eq!(pending.register_failure(), MAX_ATTEMPTS - 2);
+        pending.issued_at -= RESEND_COOLDOWN_SECS;
+        pending.reissue("654321");
+        assert!(pending.matches("654321") && !pending.matches("123456"));
+        assert_eq!(pending.attempts, 2);
+        let left: Vec<u32> = (0..3).map(|_| pending.register_failure()).collect();
+        assert_eq!(left, [2, 1, 0]);
+    }
+
+    #[test]
+    fn resends_wait_for_the_cooldown_and_are_counted() {
+        let mut pending = pending();
+        assert!(pending.resend_refusal().unwrap().starts_with("Aguarde 60s"));
+        for resend in 1..=MAX_RESENDS {
+            pending.issued_at -= RESEND_COOLDOWN_SECS;
+            assert_eq!(pending.resend_refusal(), None);
+            pending.reissue("654321");
+            assert_eq!(pending.resends, resend);
+        }
+        pending.issued_at -= RESEND_COOLDOWN_SECS;
+        assert_eq!(pending.resend_refusal().as_deref(), Some("Limite de reenvios atingido."));
+    }
+}
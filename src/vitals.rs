@@ -0,0 +1,285 @@
+// Vital signs and anthropometrics of an encounter. They are read from the
+// `[EXAME FISICO]` section, either as `!SV PA 130X80 FC 72 ...` directives or
+// as the same label/value pairs inside free text, so existing records written
+// as `PA: 130/80 MMHG` are recognized too. Adult reference ranges are used to
+// flag out-of-range values.
+
+use crate::charts::{self, Point, Series};
+use crate::medfile::{self, MedDocument, MedSection};
+use crate::site::Encounter;
+use crate::html::html_escape;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Flag {
+    Low,
+    High,
+}
+
+impl Flag {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Flag::Low => "baixo",
+            Flag::High => "alto",
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct Vitals {
+    pub systolic: Option<f64>,
+    pub diastolic: Option<f64>,
+    pub heart_rate: Option<f64>,
+    pub respiratory_rate: Option<f64>,
+    pub temperature: Option<f64>,
+    pub spo2: Option<f64>,
+    // Kilograms and meters
+    pub weight: Option<f64>,
+    pub height: Option<f64>,
+}
+
+pub struct Reading {
+    pub name: &'static str,
+    pub value: String,
+    pub flag: Option<Flag>,
+    pub note: String,
+}
+
+// (low, high) limits of the normal adult range, inclusive
+const SYSTOLIC_RANGE: (f64, f64) = (90.0, 139.0);
+const DIASTOLIC_RANGE: (f64, f64) = (60.0, 89.0);
+const HEART_RATE_RANGE: (f64, f64) = (60.0, 100.0);
+const RESPIRATORY_RATE_RANGE: (f64, f64) = (12.0, 20.0);
+const TEMPERATURE_RANGE: (f64, f64) = (35.0, 37.7);
+const SPO2_RANGE: (f64, f64) = (94.0, 100.0);
+const BMI_RANGE: (f64, f64) = (18.5, 24.9);
+
+pub fn flag(value: f64, range: (f64, f64)) -> Option<Flag> {
+    if value < range.0 {
+        Some(Flag::Low)
+    } else if value > range.1 {
+        Some(Flag::High)
+    } else {
+        None
+    }
+}
+
+fn parse_number(token: &str) -> Option<f64> {
+    // "36,5", "70KG", "98%", "1,65M", "37.2°C"
+    let number: String = token
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == ',' || *c == '.')
+        .collect();
+    number.trim_end_matches(['.', ',']).replace(',', ".").parse().ok()
+}
+
+fn parse_pressure(tokens: &[&str]) -> Option<(f64, f64)> {
+    // "130X80", "130/80MMHG" or "130 X 80"
+    let first = tokens.first()?;
+    if let Some((systolic, diastolic)) = first.split_once(['X', '/']) {
+        return Some((parse_number(systolic)?, parse_number(diastolic)?));
+    }
+    match tokens {
+        [systolic, separator, diastolic, ..] if *separator == "X" || *separator == "/" => {
+            Some((parse_number(systolic)?, parse_number(diastolic)?))
+        }
+        _ => None,
+    }
+}
+
+impl Vitals {
+    pub fn is_empty(&self) -> bool {
+        self.readings().is_empty()
+    }
+
+    pub fn bmi(&self) -> Option<f64> {
+        match (self.weight, self.height) {
+            (Some(weight), Some(height)) if height > 0.0 => Some(weight / (height * height)),
+            _ => None,
+        }
+    }
+
+    fn read_line(&mut self, line: &str) {
+        let upper = line.to_uppercase().replace([':', '='], " ");
+        let tokens: Vec<&str> = upper.split_whitespace().map(|t| t.trim_end_matches([';', ','])).collect();
+        for (i, token) in tokens.iter().enumerate() {
+            let rest = &tokens[i + 1..];
+            let value = rest.first().and_then(|t| parse_number(t));
+            match *token {
+                "PA" => {
+                    if let Some((systolic, diastolic)) = parse_pressure(rest) {
+                        self.systolic = Some(systolic);
+                        self.diastolic = Some(diastolic);
+                    }
+                }
+                "FC" => self.heart_rate = value.or(self.heart_rate),
+                "FR" => self.respiratory_rate = value.or(self.respiratory_rate),
+                "TAX" | "TEMP" => self.temperature = value.or(self.temperature),
+                "SPO2" | "SATO2" => self.spo2 = value.or(self.spo2),
+                "PESO" => self.weight = value.or(self.weight),
+                "ALTURA" | "ESTATURA" => {
+                    // Heights written in centimeters are converted to meters
+                    self.height = value.map(|h| if h > 3.0 { h / 100.0 } else { h }).or(self.height);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn from_section(section: &MedSection) -> Vitals {
+        let mut vitals = Vitals::default();
+        for line in &section.lines {
+            match medfile::parse_directive(line) {
+                Some(directive) if directive.keyword == "SV" => vitals.read_line(&directive.content),
+                Some(_) => {}
+                None => vitals.read_line(line),
+            }
+        }
+        vitals
+    }
+
+    pub fn from_document(doc: &MedDocument) -> Vitals {
+        doc.section("EXAME FISICO").map(Vitals::from_section).unwrap_or_default()
+    }
+
+    pub fn readings(&self) -> Vec<Reading> {
+        let mut readings = vec![];
+        if let (Some(systolic), Some(diastolic)) = (self.systolic, self.diastolic) {
+            readings.push(Reading {
+                name: "PA",
+                value: format!("{}x{} mmHg", charts::format_value(systolic), charts::format_value(diastolic)),
+                flag: flag(systolic, SYSTOLIC_RANGE).or(flag(diastolic, DIASTOLIC_RANGE)),
+                note: String::new(),
+            });
+        }
+        let simple = [
+            ("FC", self.heart_rate, "bpm", HEART_RATE_RANGE),
+            ("FR", self.respiratory_rate, "irpm", RESPIRATORY_RATE_RANGE),
+            ("TAX", self.temperature, "°C", TEMPERATURE_RANGE),
+            ("SpO2", self.spo2, "%", SPO2_RANGE),
+        ];
+        for (name, value, unit, range) in simple {
+            if let Some(value) = value {
+                readings.push(Reading {
+                    name,
+                    value: format!("{} {}", charts::format_value(value), unit),
+                    flag: flag(value, range),
+                    note: String::new(),
+                });
+            }
+        }
+        if let Some(weight) = self.weight {
+            readings.push(Reading { name: "Peso", value: format!("{} kg", charts::format_value(weight)), flag: None, note: String::new() });
+        }
+        if let Some(height) = self.height {
+            readings.push(Reading { name: "Altura", value: format!("{:.2} m", height).replace('.', ","), flag: None, note: String::new() });
+        }
+        if let Some(bmi) = self.bmi() {
+            readings.push(Reading {
+                name: "IMC",
+                value: format!("{:.1} kg/m²", bmi).replace('.', ","),
+                flag: flag(bmi, BMI_RANGE),
+                note: bmi_class(bmi).to_string(),
+            });
+        }
+        readings
+    }
+}
+
+pub fn bmi_class(bmi: f64) -> &'static str {
+    match bmi {
+        b if b < 18.5 => "baixo peso",
+        b if b < 25.0 => "eutrófico",
+        b if b < 30.0 => "sobrepeso",
+        b if b < 35.0 => "obesidade grau I",
+        b if b < 40.0 => "obesidade grau II",
+        _ => "obesidade grau III",
+    }
+}
+
+pub fn reading_text(reading: &Reading) -> String {
+    let mut text = format!("{} {}", reading.name, reading.value);
+    if !reading.note.is_empty() {
+        text.push_str(&format!(" ({})", reading.note));
+    }
+    if let Some(flag) = reading.flag {
+        text.push_str(&format!(" [{}]", flag.label()));
+    }
+    text
+}
+
+pub fn generate_vitals_table(vitals: &Vitals) -> String {
+    let readings = vitals.readings();
+    if readings.is_empty() {
+        return String::new();
+    }
+    let mut html = String::from("<h3>Sinais Vitais</h3><table class=\"exams vitals\"><tbody>");
+    for reading in readings {
+        let (class, badge) = match reading.flag {
+            Some(flag) => (" class=\"flagged\"", format!(" <span class=\"badge alert\">{}</span>", flag.label())),
+            None => ("", String::new()),
+        };
+        let note = if reading.note.is_empty() { String::new() } else { format!(" <small>{}</small>", html_escape(&reading.note)) };
+        html.push_str(&format!(
+            "<tr{}><th>{}</th><td>{}{}{}</td></tr>",
+            class, reading.name, html_escape(&reading.value), note, badge
+        ));
+    }
+    html.push_str("</tbody></table>");
+    html
+}
+
+pub fn vitals_history(encounters: &[Encounter]) -> Vec<(String, Vitals)> {
+    encounters
+        .iter()
+        .map(|encounter| (encounter.date_str.clone(), Vitals::from_document(&encounter.doc)))
+        .filter(|(_, vitals)| !vitals.is_empty())
+        .collect()
+}
+
+fn series(history: &[(String, Vitals)], name: &str, value: fn(&Vitals) -> Option<f64>, range: Option<(f64, f64)>) -> Series {
+    Series {
+        name: name.to_string(),
+        points: history
+            .iter()
+            .map(|(date, vitals)| {
+                value(vitals).map(|v| Point {
+                    label: date.clone(),
+                    value: v,
+                    flagged: range.and_then(|r| flag(v, r)).is_some(),
+                })
+            })
+            .collect(),
+    }
+}
+
+pub fn generate_vitals_charts(history: &[(String, Vitals)]) -> String {
+    // A single visit has no trend to show
+    if history.len() < 2 {
+        return String::new();
+    }
+    let labels: Vec<String> = history.iter().map(|(date, _)| date.clone()).collect();
+    let charts = [
+        (
+            "Pressão arterial (mmHg)",
+            vec![
+                series(history, "Sistólica", |v| v.systolic, Some(SYSTOLIC_RANGE)),
+                series(history, "Diastólica", |v| v.diastolic, Some(DIASTOLIC_RANGE)),
+            ],
+            None,
+        ),
+        ("Frequência cardíaca (bpm)", vec![series(history, "FC", |v| v.heart_rate, Some(HEART_RATE_RANGE))], Some(HEART_RATE_RANGE)),
+        ("SpO2 (%)", vec![series(history, "SpO2", |v| v.spo2, Some(SPO2_RANGE))], Some(SPO2_RANGE)),
+        ("Temperatura (°C)", vec![series(history, "TAX", |v| v.temperature, Some(TEMPERATURE_RANGE))], Some(TEMPERATURE_RANGE)),
+        ("Peso (kg)", vec![series(history, "Peso", |v| v.weight, None)], None),
+        ("IMC (kg/m²)", vec![series(history, "IMC", |v| v.bmi(), Some(BMI_RANGE))], Some(BMI_RANGE)),
+    ];
+    let mut html = String::new();
+    for (title, series, band) in charts {
+        let points = series.iter().flat_map(|s| s.points.iter().flatten()).count();
+        if points < 2 {
+            continue;
+        }
+        html.push_str(&format!("<h3>{}</h3>{}", title, charts::line_chart_svg(&labels, &series, band)));
+    }
+    html
+}
@@ -0,0 +1,259 @@
+// Watch mode (`medfiles watch`). The workspace directory is watched for .med
+// files being created, saved or removed; after a short quiet period the
+// patient is reprocessed: the encounters are parsed and checked, the
+// prescriptions replayed to rewrite medications.json and history.json, and the
+// pages in <out>/<paciente>/ regenerated. Results and warnings are printed as
+// they happen, while the doctor keeps typing. The state files hold a single
+// list keyed by drug name, so a workspace with several patients is refused.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, SystemTime};
+
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use medfiles::medfile::{self, MedDocument};
+use medfiles::prescription::{self, Medications};
+use medfiles::{cid10, site, ChangeRecord, MedError, Prescription};
+
+use crate::{audit, storage};
+
+// Editors write a file in several steps; wait for them to settle
+const QUIET_PERIOD: Duration = Duration::from_millis(300);
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+pub fn lint(doc: &MedDocument, items: &[Prescription], active: &Medications) -> Vec<String> {
+    let mut warnings = vec![];
+    if doc.sections.is_empty() {
+        warnings.push("nenhuma seção [NOME] encontrada".to_string());
+    }
+    for diagnosis in medfile::diagnosis_items(&doc.section_lines("HIPOTESE DIAGNOSTICA")) {
+        if let Some(code) = &diagnosis.code
+            && diagnosis.cid_code().is_none()
+        {
+            warnings.push(format!("{}: código CID-10 {{{}}} inválido", diagnosis.name, code));
+        }
+    }
+    for item in items {
+        match item.command.as_str() {
+            "PRESCRIBE" if item.posologia.is_empty() => {
+                warnings.push(format!("{}: prescrição sem posologia", item.medication));
+            }
+            "INCREASE" | "SUSPEND" if !active.contains_key(&item.medication) => {
+                warnings.push(format!("{}: não está em uso; a mudança foi ignorada", item.medication));
+            }
+            "PRESCRIBE" | "INCREASE" | "SUSPEND" => {}
+            other => warnings.push(format!("{}: !{} não altera as medicações em uso", item.medication, other)),
+        }
+    }
+    warnings
+}
+
+fn update_state(medications: &Medications, changes: &[ChangeRecord]) -> Result<(), MedError> {
+    // The replay covers every encounter of the only patient, so it replaces the stored state,
+    // including the records of earlier versions of the saved files
+    crate::write_state("medications.json", medications)?;
+    crate::write_state("history.json", &changes)
+}
+
+fn patient_key(name: &str) -> String {
+    cid10::normalize(&site::patient_name(name))
+}
+
+fn single_patient(files: &[(PathBuf, SystemTime)]) -> Result<Option<String>, String> {
+    let mut patients: Vec<String> = files.iter().map(|(path, _)| site::patient_name(&file_name(path))).collect();
+    patients.sort_by_key(|name| cid10::normalize(name));
+    patients.dedup_by_key(|name| cid10::normalize(name));
+    if patients.len() > 1 {
+        return Err(format!(
+            "O modo watch só funciona em um workspace de um único paciente, e este tem {} ({}). medications.json e history.json guardam uma só lista de medicações; separe os pacientes em diretórios.",
+            patients.len(),
+            patients.join(", ")
+        ));
+    }
+    Ok(patients.pop())
+}
+
+fn process_patient(patient: &str, changed: &HashSet<String>, out_dir: &Path) {
+    let files = crate::patient_files(patient);
+    let mut medications: Medications = HashMap::new();
+    let mut changes: Vec<ChangeRecord> = vec![];
+    for (path, modified) in &files {
+        let name = file_name(path);
+        let content = match storage::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!("  {}", MedError::read(path, e));
+                continue;
+            }
+        };
+        let doc = MedDocument::parse(&content);
+        let items = prescription::document_prescriptions(&doc);
+        let warnings = if changed.contains(&name) { lint(&doc, &items, &medications) } else { vec![] };
+        let (returns, recipes, file_changes) = prescription::prescription_finalizer(items, &mut medications, chrono::DateTime::<chrono::Local>::from(*modified));
+        changes.extend(file_changes);
+        if !changed.contains(&name) {
+            continue;
+        }
+        audit::record_file("processar", path, "modo watch");
+        println!("  {}", name);
+        if !recipes.is_empty() {
+            println!("  Prescrição:");
+            for (index, recipe) in recipes.iter().enumerate() {
+                let mut lines = recipe.lines();
+                println!("    {}. {}", index + 1, lines.next().unwrap_or(""));
+                for line in lines {
+                    println!("       {}", line);
+                }
+            }
+        }
+        for output in &returns {
+            for line in output.lines() {
+                println!("    {}", line);
+            }
+        }
+        for warning in &warnings {
+            eprintln!("  \x1b[33mAviso:\x1b[0m {}", warning);
+        }
+    }
+    if let Err(e) = update_state(&medications, &changes) {
+        eprintln!("  Estado das medicações não atualizado: {}", e);
+    }
+    let patient_dir = out_dir.join(site::page_slug(patient));
+    let encounters = crate::load_encounters(&files);
+    // The pages of a file that could not be read are kept until it can be
+    if encounters.len() == files.len() {
+        match site::remove_stale_pages(&patient_dir, &encounters) {
+            Ok(0) => {}
+            Ok(removed) => println!("  {} página(s) de atendimentos apagados removida(s).", removed),
+            Err(e) => eprintln!("  Páginas antigas não removidas: {}", e),
+        }
+    }
+    if files.is_empty() {
+        return;
+    }
+    if storage::is_encrypted_workspace() {
+        // The pages would hold the decrypted records
+        println!("  Workspace criptografado: páginas HTML não regeradas.");
+        return;
+    }
+    let (doctor, signature) = crate::load_doctor();
+    match site::generate_site(&patient_dir, &encounters, &medications, doctor.as_ref(), signature.as_deref()) {
+        Ok(report) => println!("  Páginas em {}: {} atualizadas, {} inalteradas.", patient_dir.display(), report.written, report.unchanged),
+        Err(e) => eprintln!("  Páginas não geradas: {}", e),
+    }
+}
+
+fn process(paths: &HashSet<PathBuf>, known: &mut HashMap<String, SystemTime>, patient: &mut Option<String>, out_dir: &Path) {
+    let mut patients: BTreeMap<String, HashSet<String>> = BTreeMap::new();
+    for path in paths {
+        let name = file_name(path);
+        // The first file names the patient of an empty workspace; files of anyone else are left alone
+        let watched = patient.get_or_insert_with(|| site::patient_name(&name));
+        if patient_key(&name) != cid10::normalize(watched) {
+            eprintln!("{}: ignorado, o modo watch acompanha apenas {}.", name, watched);
+            continue;
+        }
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let previous = match modified {
+            Some(modified) => known.insert(name.clone(), modified),
+            None => known.remove(&name),
+        };
+        if previous.is_some() && previous == modified {
+            continue;
+        }
+        patients.entry(watched.clone()).or_default().insert(name);
+    }
+    for (patient, changed) in patients {
+        println!("[{}] {}", chrono::Local::now().format("%H:%M:%S"), patient);
+        process_patient(&patient, &changed, out_dir);
+    }
+}
+
+fn is_change(event: &notify::Event) -> bool {
+    !matches!(event.kind, EventKind::Access(_))
+}
+
+pub fn run(out_dir: &Path) -> Result<(), String> {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(|e| format!("Falha ao iniciar o monitoramento: {}", e))?;
+    watcher
+        .watch(Path::new("."), RecursiveMode::NonRecursive)
+        .map_err(|e| format!("Falha ao monitorar o diretório: {}", e))?;
+
+    // First pass over every file, so the pages and the state start up to date
+    let files = crate::medfile_finder();
+    let mut patient = single_patient(&files)?;
+    let mut known: HashMap<String, SystemTime> = HashMap::new();
+    let all: HashSet<PathBuf> = files.into_iter().map(|(path, _)| path).collect();
+    process(&all, &mut known, &mut patient, out_dir);
+    println!("Monitorando os arquivos .med em {} (Ctrl+C para sair)...", crate::workspace_name());
+
+    loop {
+        let mut paths: HashSet<PathBuf> = HashSet::new();
+        let mut collect = |result: notify::Result<notify::Event>| match result {
+            Ok(event) if is_change(&event) => {
+                let med_files = event.paths.into_iter().filter(|p| p.extension().is_some_and(|e| e == "med"));
+                paths.extend(med_files.map(|p| PathBuf::from(file_name(&p))));
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("Erro no monitoramento: {}", e),
+        };
+        let first = receiver.recv().map_err(|e| format!("Monitoramento interrompido: {}", e))?;
+        collect(first);
+        while let Ok(result) = receiver.recv_timeout(QUIET_PERIOD) {
+            collect(result);
+        }
+        if !paths.is_empty() {
+            process(&paths, &mut known, &mut patient, out_dir);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::testing::Workspace;
+
+    #[test]
+    fn workspace_with_several_patients_is_refused() {
+        let workspace = Workspace::new();
+        workspace.file("ANA LIMA 2025-01-10.med", "[CONDUTA]\n");
+        workspace.file("Ana Lima 2025-02-10.med", "[CONDUTA]\n");
+        let patient = single_patient(&crate::medfile_finder()).unwrap().unwrap();
+        assert_eq!(cid10::normalize(&patient), cid10::normalize("ANA LIMA"));
+        workspace.file("BIA REIS 2025-01-10.med", "[CONDUTA]\n");
+        let error = single_patient(&crate::medfile_finder()).unwrap_err();
+        assert!(error.contains("único paciente") && error.contains("BIA REIS"));
+        assert!(run(Path::new("site")).unwrap_err().contains("único paciente"));
+    }
+
+    #[test]
+    fn saving_a_file_replaces_the_state_of_the_patient() {
+        let workspace = Workspace::new();
+        let first = workspace.file("ANA LIMA 2025-01-10.med", "[CONDUTA]\n!PRESCREVO TOPIRAMATO 25MG [1 COMPRIMIDO] NOITE;\n");
+        let mut known = HashMap::new();
+        let mut patient = None;
+        let out = workspace.dir.path().join("site");
+        process(&HashSet::from([first.clone()]), &mut known, &mut patient, &out);
+        assert_eq!(patient.as_deref(), Some("ANA LIMA"));
+
+        let second = workspace.file("ANA LIMA 2025-02-10.med", "[CONDUTA]\n!AUMENTO TOPIRAMATO 50MG [1 COMPRIMIDO] NOITE;\n");
+        let other = workspace.file("BIA REIS 2025-02-10.med", "[CONDUTA]\n!PRESCREVO PROPRANOLOL 40MG [1 COMPRIMIDO] 12/12 HORAS;\n");
+        process(&HashSet::from([second, other]), &mut known, &mut patient, &out);
+        process(&HashSet::from([first]), &mut known, &mut patient, &out);
+
+        let medications = crate::load_medications().unwrap();
+        assert_eq!(medications.keys().collect::<Vec<_>>(), ["TOPIRAMATO"]);
+        assert_eq!(medications["TOPIRAMATO"]["dosage"], "50MG");
+        let history = crate::load_history().unwrap();
+        assert_eq!(history.iter().filter(|r| r.5 == "PRESCRIBE").count(), 1);
+        assert!(!Path::new("prescription_recipe.txt").exists());
+    }
+}